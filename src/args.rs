@@ -1,6 +1,10 @@
+use std::path::PathBuf;
+
 use bevy::prelude::Resource;
 use clap::{Parser, Subcommand};
 
+use crate::{gameplay::solver::DEFAULT_NODE_BUDGET, level::level_generator::GENERATOR_NODE_BUDGET};
+
 /// Cli API.
 /// Run a level
 /// ./snake-bird -l 0
@@ -12,6 +16,17 @@ use clap::{Parser, Subcommand};
 /// ./snake-bird test
 /// // Run the automated tests for a specific test case
 /// ./snake-bird -t 0 test
+/// // Watch the automated tests in a window
+/// ./snake-bird test --window
+/// // Record the moves of a session and replay them on the same level
+/// ./snake-bird -l 0 --record moves.replay
+/// ./snake-bird -l 0 --replay moves.replay
+/// // Find the shortest solution of a level, or of all the levels
+/// ./snake-bird solve -l 0
+/// ./snake-bird solve
+/// // Generate a solvable level from a seed, or from a random seed
+/// ./snake-bird generate --seed 42
+/// ./snake-bird generate
 
 #[derive(Parser, Debug, Default, Clone, Resource)]
 pub struct Args {
@@ -21,6 +36,24 @@ pub struct Args {
     #[arg(short, long)]
     pub test_level: Option<usize>,
 
+    /// Start with the color blind palette, the palette can also be changed from the inspector.
+    #[arg(long)]
+    pub colorblind: bool,
+
+    /// Start without the music and the sound effects, the mute key toggles them while playing.
+    #[arg(long)]
+    pub no_audio: bool,
+
+    /// Start without the background music.
+    #[arg(long)]
+    pub no_music: bool,
+
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
@@ -28,8 +61,33 @@ pub struct Args {
 #[derive(Subcommand, Debug, Clone)]
 pub enum Commands {
     /// Run automated tests.
+    /// The tests run without a window unless asked to show them.
     Test {
         #[arg(short, long)]
         test_case: Option<usize>,
+
+        /// Show the tests in a window.
+        #[arg(long)]
+        window: bool,
+    },
+
+    /// Find the shortest solution of the levels with a breadth first search.
+    Solve {
+        #[arg(short, long)]
+        level: Option<usize>,
+
+        /// Maximum number of game states to explore per level.
+        #[arg(short, long, default_value_t = DEFAULT_NODE_BUDGET)]
+        budget: usize,
+    },
+
+    /// Generate a random solvable level and print it.
+    Generate {
+        #[arg(short, long)]
+        seed: Option<u64>,
+
+        /// Maximum number of game states to explore per candidate level.
+        #[arg(short, long, default_value_t = GENERATOR_NODE_BUDGET)]
+        budget: usize,
     },
 }
@@ -105,7 +105,11 @@ pub(super) fn spawn_water_system(
     commands.spawn((
         MaterialMesh2dBundle {
             mesh: meshes.add(water_mesh).into(),
-            transform: Transform::from_xyz(0.0, 0.0, 3.0),
+            transform: Transform::from_xyz(
+                0.0,
+                level_template.water_level.unwrap_or(0) as f32 * GRID_TO_WORLD_UNIT,
+                3.0,
+            ),
             material: materials.add(WaterMaterial::from(game_constants.water_color)),
             ..default()
         },
@@ -1,29 +1,41 @@
 use args::Args;
-use bevy::prelude::*;
-use bevy_kira_audio::{Audio, AudioControl, AudioPlugin, AudioSource};
-use bevy_tweening::TweeningPlugin;
+use std::time::Duration;
+
+use bevy::{
+    app::ScheduleRunnerSettings, asset::AssetPlugin, asset::LoadState, hierarchy::HierarchyPlugin,
+    input::InputPlugin, prelude::*, transform::TransformPlugin,
+};
+use bevy_kira_audio::{AudioApp, AudioChannel, AudioControl, AudioPlugin, AudioSource};
+use bevy_prototype_lyon::prelude::ShapePlugin;
+use bevy_tweening::{component_animator_system, AnimationSystem, TweenCompleted};
 use environment::EnvironmentPlugin;
 use gameplay::camera_plugin::CameraPlugin;
 use gameplay::game_constants_pluggin::*;
+use gameplay::ghost_pluggin::GhostPluggin;
 use gameplay::level_pluggin::{
-    ClearLevelEvent, LevelEntity, LevelPluggin, StartLevelEventWithIndex,
+    LevelEntity, LevelPluggin, LevelRenderPluggin, StartLevelEventWithIndex,
     StartTestLevelEventWithIndex,
 };
-use gameplay::movement_pluggin::MovementPluggin;
-use gameplay::snake_pluggin::SnakePluggin;
-use iyes_loopless::{
-    prelude::{AppLooplessStateExt, ConditionSet},
-    state::NextState,
+use gameplay::movement_pluggin::{
+    sound_effects_system, MovementInputPluggin, MovementPluggin, MovementSystem,
 };
+use gameplay::save_pluggin::SavePluggin;
+use gameplay::snake_pluggin::{SnakePluggin, SnakeRenderPluggin};
+use iyes_loopless::prelude::AppLooplessStateExt;
+use menus::game_controls::GameControlsPlugin;
+use menus::history_timeline::HistoryTimelinePlugin;
 use menus::main_menu::MainMenuPlugin;
+use menus::pause_menu::PauseMenuPlugin;
 use menus::select_level_menu::{NextLevel, SelectLevelMenuPlugin};
 use menus::MenuPlugin;
+use tools::automated_test_pluggin::{AutomatedTestPluggin, TestRun};
 use tools::dev_tools_pluggin::DevToolsPlugin;
+use tools::replay_pluggin::{Player, Recorder, Replay, ReplayPluggin};
 
 pub mod args;
 mod environment;
 mod gameplay;
-mod level;
+pub mod level;
 mod menus;
 mod tools;
 
@@ -38,6 +50,8 @@ pub enum GameState {
     Game,
 }
 
+/// The simulation of the game, the levels, the snakes and their moves.
+/// It runs without a window or rendering for the automated tests.
 pub struct GamePlugin {
     args: Args,
 }
@@ -45,43 +59,108 @@ pub struct GamePlugin {
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_exit_system(GameState::Game, despawn_with::<LevelEntity>)
-            .add_system_set(
-                ConditionSet::new()
-                    .run_in_state(GameState::Game)
-                    .with_system(back_to_menu_on_escape_system)
-                    .into(),
-            )
             .add_plugin(LevelPluggin)
             .add_plugin(SnakePluggin)
             .add_plugin(MovementPluggin)
             .add_plugin(GameConstantsPlugin)
-            .add_plugin(CameraPlugin)
-            .add_plugin(DevToolsPlugin)
-            .add_plugin(TweeningPlugin)
-            .add_plugin(EnvironmentPlugin)
+            // The game only animates the transforms, the tweening plugin also animates the render assets.
+            .add_event::<TweenCompleted>()
+            .add_system(
+                component_animator_system::<Transform>.label(AnimationSystem::AnimationUpdate),
+            )
+            .add_plugin(ReplayPluggin)
             .insert_resource(self.args.clone())
             .insert_resource(NextLevel(self.args.level.unwrap_or(0)));
 
-        //if let Some(args::Commands::Test { test_case: _ }) = self.args.command {
-        //app.add_plugin(AutomatedTestPluggin);
-        //}
+        // Only the progress of a normal play session is saved, it overrides the level to start with.
+        if self.args.command.is_none()
+            && self.args.level.is_none()
+            && self.args.test_level.is_none()
+            && self.args.replay.is_none()
+        {
+            app.add_plugin(SavePluggin);
+        }
+
+        if self.args.colorblind {
+            app.world.resource_mut::<GameConstants>().palette = Palette::ColorBlind;
+        }
+
+        // The automated tests run muted.
+        let is_test = matches!(self.args.command, Some(args::Commands::Test { .. }));
+        if self.args.no_audio || is_test {
+            let mut constants = app.world.resource_mut::<GameConstants>();
+            constants.music = false;
+            constants.sound_effects = false;
+        } else if self.args.no_music {
+            app.world.resource_mut::<GameConstants>().music = false;
+        }
+
+        if let Some(path) = &self.args.record {
+            app.insert_resource(Recorder::new(path.clone()));
+        }
+
+        if let Some(path) = &self.args.replay {
+            match Replay::load(path) {
+                Ok(replay) => {
+                    app.insert_resource(Player::new(replay));
+                }
+                Err(error) => error!("Failed to load the replay {:?}: {}", path, error),
+            }
+        }
+
+        if let Some(args::Commands::Test { test_case, .. }) = self.args.command {
+            app.add_plugin(AutomatedTestPluggin)
+                .insert_resource(TestRun::new(test_case));
+        }
 
         app.add_enter_system(GameState::Game, enter_game_system);
     }
 }
 
+/// The rendering, the controls, the menus, the audio and the tools of the game, added on top of the game plugin.
+pub struct GamePresentationPlugin;
+
+impl Plugin for GamePresentationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(ShapePlugin)
+            .add_plugin(LevelRenderPluggin)
+            .add_plugin(SnakeRenderPluggin)
+            .add_plugin(MovementInputPluggin)
+            .add_plugin(GhostPluggin)
+            .add_plugin(CameraPlugin)
+            .add_plugin(DevToolsPlugin)
+            .add_plugin(EnvironmentPlugin)
+            .add_plugin(MenuPlugin)
+            .add_plugin(MainMenuPlugin)
+            .add_plugin(SelectLevelMenuPlugin)
+            .add_plugin(PauseMenuPlugin)
+            .add_plugin(GameControlsPlugin)
+            .add_plugin(HistoryTimelinePlugin)
+            .add_plugin(AudioPlugin)
+            .add_audio_channel::<MusicChannel>()
+            .add_startup_system(load_assets)
+            .add_system(forget_failed_sounds_system)
+            .add_system(mute_keyboard_system)
+            .add_system(update_music_system)
+            .add_system(
+                sound_effects_system
+                    .run_in_state(GameState::Game)
+                    .after(MovementSystem::SnakeGrow),
+            );
+    }
+}
+
 fn enter_game_system(
     args: Res<Args>,
     next_level: Res<NextLevel>,
-    // mut start_test_case_event: EventWriter<StartTestCaseEventWithIndex>,
     mut start_test_level_event: EventWriter<StartTestLevelEventWithIndex>,
     mut start_level_event: EventWriter<StartLevelEventWithIndex>,
 ) {
     match args.command {
-        Some(args::Commands::Test { test_case: _ }) => {
-            // let start_test_case = test_case.unwrap_or(0);
-            // start_test_case_event.send(StartTestCaseEventWithIndex(start_test_case));
-        }
+        // The automated tests start their first test case.
+        Some(args::Commands::Test { .. }) => return,
+        // Solving or generating levels doesn't start the game.
+        Some(args::Commands::Solve { .. }) | Some(args::Commands::Generate { .. }) => return,
         None => {
             if let Some(test_level) = args.test_level {
                 start_test_level_event.send(StartTestLevelEventWithIndex(test_level));
@@ -93,17 +172,6 @@ fn enter_game_system(
     start_level_event.send(StartLevelEventWithIndex(next_level.0));
 }
 
-fn back_to_menu_on_escape_system(
-    mut event_clear_level: EventWriter<ClearLevelEvent>,
-    mut commands: Commands,
-    keyboard: Res<Input<KeyCode>>,
-) {
-    if keyboard.just_pressed(KeyCode::Escape) {
-        event_clear_level.send(ClearLevelEvent);
-        commands.insert_resource(NextState(GameState::MainMenu));
-    }
-}
-
 pub fn despawn_with<T: Component>(mut commands: Commands, q: Query<Entity, With<T>>) {
     for e in q.iter() {
         commands.entity(e).despawn_recursive();
@@ -111,7 +179,26 @@ pub fn despawn_with<T: Component>(mut commands: Commands, q: Query<Entity, With<
 }
 
 pub fn run(app: &mut App, args: &Args) {
-    let start_state = if args.command.is_none() && args.level.is_none() && args.test_level.is_none()
+    if let Some(args::Commands::Solve { level, budget }) = args.command {
+        gameplay::solver::print_solutions(level, budget);
+        return;
+    }
+
+    if let Some(args::Commands::Generate { seed, budget }) = args.command {
+        level::level_generator::print_generated_level(seed, budget);
+        return;
+    }
+
+    if let Some(args::Commands::Test { window: false, .. }) = args.command {
+        add_headless_plugins(app, args);
+        app.run();
+        return;
+    }
+
+    let start_state = if args.command.is_none()
+        && args.level.is_none()
+        && args.test_level.is_none()
+        && args.replay.is_none()
     {
         GameState::MainMenu
     } else {
@@ -130,30 +217,146 @@ pub fn run(app: &mut App, args: &Args) {
             ..default()
         }))
         .add_loopless_state_before_stage(CoreStage::PreUpdate, start_state)
-        .add_plugin(MenuPlugin)
-        .add_plugin(MainMenuPlugin)
-        .add_plugin(SelectLevelMenuPlugin)
         .add_plugin(GamePlugin { args: args.clone() })
-        .add_plugin(AudioPlugin)
-        .add_startup_system(load_assets)
+        .add_plugin(GamePresentationPlugin)
         .run();
 }
 
+/// The game without a window or rendering, for the automated tests on machines without a display.
+fn add_headless_plugins(app: &mut App, args: &Args) {
+    app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::from_secs_f64(
+        1.0 / 60.0,
+    )))
+    .add_plugins(MinimalPlugins)
+    .add_plugin(AssetPlugin::default())
+    // No window is opened, the game still reads the windows and the inputs.
+    .add_plugin(WindowPlugin {
+        add_primary_window: false,
+        exit_on_all_closed: false,
+        ..default()
+    })
+    .add_plugin(InputPlugin)
+    .add_plugin(TransformPlugin)
+    .add_plugin(HierarchyPlugin)
+    .add_loopless_state_before_stage(CoreStage::PreUpdate, GameState::Game)
+    .add_plugin(GamePlugin { args: args.clone() });
+}
+
+/// The audio channel of the background music, paused and resumed independently of the effects.
+#[derive(Resource)]
+pub struct MusicChannel;
+
+/// The sounds of the game, a sound is None once it failed to load and the game continues without it.
 #[derive(Resource)]
 pub struct Assets {
-    pub background_noise: Handle<AudioSource>,
-    pub move_effect_1: Handle<AudioSource>,
-    pub move_effect_2: Handle<AudioSource>,
+    pub background_noise: Option<Handle<AudioSource>>,
+    pub move_effect_1: Option<Handle<AudioSource>>,
+    pub move_effect_2: Option<Handle<AudioSource>>,
+    pub eat_effect: Option<Handle<AudioSource>>,
+    pub goal_effect: Option<Handle<AudioSource>>,
 }
 
-fn load_assets(mut commands: Commands, asset_server: Res<AssetServer>, audio: Res<Audio>) {
-    let background_noise: Handle<AudioSource> = asset_server.load("beach.mp3");
+impl Assets {
+    fn sounds_mut(&mut self) -> [&mut Option<Handle<AudioSource>>; 5] {
+        [
+            &mut self.background_noise,
+            &mut self.move_effect_1,
+            &mut self.move_effect_2,
+            &mut self.eat_effect,
+            &mut self.goal_effect,
+        ]
+    }
+}
 
+fn load_assets(mut commands: Commands, asset_server: Res<AssetServer>) {
     commands.insert_resource(Assets {
-        background_noise: background_noise.clone(),
-        move_effect_1: asset_server.load("effects1.mp3"),
-        move_effect_2: asset_server.load("effects2.mp3"),
+        background_noise: Some(asset_server.load("beach.mp3")),
+        move_effect_1: Some(asset_server.load("effects1.mp3")),
+        move_effect_2: Some(asset_server.load("effects2.mp3")),
+        // Eating and reaching the goal play the first move effect at a different pitch.
+        eat_effect: Some(asset_server.load("effects1.mp3")),
+        goal_effect: Some(asset_server.load("effects1.mp3")),
     });
+}
+
+/// Forget the sounds that failed to load, for builds shipped without the audio files.
+fn forget_failed_sounds_system(asset_server: Res<AssetServer>, mut assets: ResMut<Assets>) {
+    for sound in assets.bypass_change_detection().sounds_mut() {
+        let Some(handle) = sound else {
+            continue;
+        };
+
+        if asset_server.get_load_state(&*handle) == LoadState::Failed {
+            warn!(
+                "Failed to load the sound {:?}, continuing without it.",
+                asset_server.get_handle_path(&*handle)
+            );
+            *sound = None;
+        }
+    }
+}
+
+/// Start, pause and resume the background music when it is toggled, it is only started once enabled.
+fn update_music_system(
+    constants: Res<GameConstants>,
+    assets: Res<Assets>,
+    music: Res<AudioChannel<MusicChannel>>,
+    mut is_started: Local<bool>,
+) {
+    if !constants.is_changed() {
+        return;
+    }
+
+    if !constants.music {
+        music.pause();
+    } else if *is_started {
+        music.resume();
+    } else if let Some(background_noise) = &assets.background_noise {
+        music
+            .play(background_noise.clone())
+            .looped()
+            .with_volume(0.1);
+        *is_started = true;
+    }
+}
+
+/// The mute key mutes the music and the sound effects, or unmutes both if everything is muted.
+fn mute_keyboard_system(
+    keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut constants: ResMut<GameConstants>,
+) {
+    if !keyboard.any_just_pressed(key_bindings.mute.iter().copied()) {
+        return;
+    }
 
-    audio.play(background_noise).looped().with_volume(0.1);
+    let is_muted = !constants.music && !constants.sound_effects;
+    constants.music = is_muted;
+    constants.sound_effects = is_muted;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{gameplay::snake_pluggin::Snake, level::level_instance::LevelInstance};
+
+    #[test]
+    pub fn test_headless_app_loads_level() {
+        let mut app = App::new();
+        add_headless_plugins(
+            &mut app,
+            &Args {
+                test_level: Some(0),
+                ..default()
+            },
+        );
+
+        for _ in 0..3 {
+            app.update();
+        }
+
+        assert!(app.world.contains_resource::<LevelInstance>());
+        let mut snakes = app.world.query::<&Snake>();
+        assert!(snakes.iter(&app.world).count() > 0);
+    }
 }
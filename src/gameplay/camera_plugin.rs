@@ -15,6 +15,12 @@ use super::{
     level_pluggin::{LevelEntity, StartLevelEventWithLevel},
 };
 
+/// Zoomed out the level fits in the window with this margin.
+const LEVEL_FIT_MARGIN: f32 = 1.2;
+
+/// Zoomed in the view is this fraction of the zoomed out view.
+const ZOOM_IN_RATIO: f32 = 0.4;
+
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
@@ -61,26 +67,43 @@ fn camera_setup_system(
         .insert(LevelEntity);
 }
 
+/// The minimum and maximum projection scales for a level, the maximum scale fits the whole level in the window.
+fn zoom_bounds(level_size: Vec2, window_size: Vec2) -> (f32, f32) {
+    let fit_scale = (level_size / window_size).max_element() * LEVEL_FIT_MARGIN;
+    (ZOOM_IN_RATIO * fit_scale, fit_scale)
+}
+
 fn camera_zoom_scroll_system(
     mut scroll_event: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    level_template: Res<LevelTemplate>,
     mut camera: Query<&mut OrthographicProjection>,
 ) {
     let Ok(mut projection) = camera.get_single_mut() else {
         return;
     };
 
-    const SCALE_MAX: f32 = 1.3;
-    const SCALE_MIN: f32 = 0.5;
+    let Some(window) = windows.get_primary() else {
+        return;
+    };
+
+    let level_size = GRID_TO_WORLD_UNIT
+        * Vec2::new(
+            level_template.grid.width() as f32,
+            level_template.grid.height() as f32,
+        );
+    let (scale_min, scale_max) =
+        zoom_bounds(level_size, Vec2::new(window.width(), window.height()));
 
     for event in scroll_event.iter() {
         match event.unit {
             MouseScrollUnit::Line => {
                 projection.scale -= 0.05 * event.y;
-                projection.scale = projection.scale.clamp(SCALE_MIN, SCALE_MAX);
+                projection.scale = projection.scale.clamp(scale_min, scale_max);
             }
             MouseScrollUnit::Pixel => {
                 projection.scale -= 0.005 * event.y;
-                projection.scale = projection.scale.clamp(SCALE_MIN, SCALE_MAX);
+                projection.scale = projection.scale.clamp(scale_min, scale_max);
             }
         }
     }
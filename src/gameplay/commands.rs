@@ -1,5 +1,5 @@
 use crate::{
-    gameplay::level_pluggin::Food,
+    gameplay::level_pluggin::{Block, Food, ShrinkFood},
     gameplay::movement_pluggin::GravityFall,
     gameplay::snake_pluggin::Snake,
     gameplay::undo::{BeginFall, EndFall, MoveHistoryEvent, SnakeHistory},
@@ -7,6 +7,9 @@ use crate::{
 };
 use bevy::prelude::*;
 
+/// Blocks falling out of the level are moved this number of cells past the edge of the level, out of sight.
+const BLOCK_FALL_OUT_DISTANCE: i32 = 10;
+
 /// Provides commands that implement the undoable game mechanics.
 /// Commands manage the state of the game data such as snakes, food, etc..
 /// In addition they propagate the changes to the level instance that keep track of which object occupies which position.
@@ -29,13 +32,36 @@ impl<'a> SnakeCommands<'a> {
             level_instance: self.level_instance,
             history: self.history,
             snake,
-            other_snake: None,
+            other_snakes: vec![],
+            blocks: vec![],
             food: None,
+            shrink_food: None,
+            portal_exit: None,
             direction,
         }
     }
 
-    pub fn exit_level(&mut self, snake: &'a Snake, entity: Entity, falling: Option<&GravityFall>) {
+    /// Carry a snake resting on a conveyor, with the snakes and blocks it pushes.
+    /// The pushed blocks are attributed to the carried snake.
+    pub fn convey(
+        &mut self,
+        snake_index: i32,
+        snakes: &mut [&mut Snake],
+        blocks: &mut [&mut Block],
+        direction: IVec2,
+    ) {
+        move_entities(
+            self.level_instance,
+            self.history,
+            snakes,
+            blocks,
+            direction,
+            MoveHistoryEvent::Conveyor(direction),
+            snake_index,
+        );
+    }
+
+    pub fn exit_level(&mut self, snake: &'a Snake, falling: Option<&GravityFall>) {
         let updates = if falling.is_none() {
             self.level_instance.clear_snake_positions(snake)
         } else {
@@ -43,7 +69,40 @@ impl<'a> SnakeCommands<'a> {
         };
 
         self.history
-            .push_with_updates(MoveHistoryEvent::ExitLevel(entity), snake.index(), updates);
+            .push_with_updates(MoveHistoryEvent::ExitLevel, snake.index(), updates);
+    }
+
+    /// Drop a block to the ground, or out of the level if there is no ground below it.
+    /// Blocks fall as a consequence of the last action so the move is attributed to the last snake in the history.
+    pub fn drop_block(&mut self, block: &mut Block, distance_to_ground: Option<i32>) {
+        let old_position = block.0;
+        let updates = match distance_to_ground {
+            Some(distance) => {
+                let offset = (distance - 1) * self.level_instance.gravity();
+                block.0 += offset;
+                self.level_instance
+                    .move_entities(&[], &[old_position], offset)
+            }
+            None => {
+                let gravity = self.level_instance.gravity();
+                let distance = self.level_instance.gravity_edge() + BLOCK_FALL_OUT_DISTANCE
+                    - old_position.dot(gravity);
+                block.0 = old_position + distance * gravity;
+                self.level_instance.remove_block(old_position)
+            }
+        };
+
+        let snake_index = self
+            .history
+            .move_history
+            .last()
+            .map_or(0, |event| event.snake_index);
+
+        self.history.push_with_updates(
+            MoveHistoryEvent::BlockMove(old_position, block.0),
+            snake_index,
+            updates,
+        );
     }
 
     /// Execute a command when a skake start falling.
@@ -109,14 +168,22 @@ pub struct PlayerMoveCommand<'a> {
     level_instance: &'a mut LevelInstance,
     history: &'a mut SnakeHistory,
     snake: &'a mut Snake,
-    other_snake: Option<&'a mut Snake>,
+    other_snakes: Vec<&'a mut Snake>,
+    blocks: Vec<&'a mut Block>,
     food: Option<&'a Food>,
+    shrink_food: Option<&'a ShrinkFood>,
+    portal_exit: Option<IVec2>,
     direction: IVec2,
 }
 
 impl<'a> PlayerMoveCommand<'a> {
-    pub fn pushing_snake(mut self, other_snake: Option<&'a mut Snake>) -> Self {
-        self.other_snake = other_snake;
+    pub fn pushing_snakes(mut self, other_snakes: Vec<&'a mut Snake>) -> Self {
+        self.other_snakes = other_snakes;
+        self
+    }
+
+    pub fn pushing_blocks(mut self, blocks: Vec<&'a mut Block>) -> Self {
+        self.blocks = blocks;
         self
     }
 
@@ -125,23 +192,33 @@ impl<'a> PlayerMoveCommand<'a> {
         self
     }
 
+    pub fn eating_shrink_food(mut self, shrink_food: Option<&'a ShrinkFood>) -> Self {
+        self.shrink_food = shrink_food;
+        self
+    }
+
+    pub fn through_portal(mut self, portal_exit: Option<IVec2>) -> Self {
+        self.portal_exit = portal_exit;
+        self
+    }
+
     pub fn execute(&mut self) {
         // Push the player action marker.
         self.history
-            .push(MoveHistoryEvent::PlayerSnakeMove, self.snake.index());
-
-        // Move the other snake.
-        if let Some(other_snake) = &mut self.other_snake {
-            let walkable_updates = self.level_instance.move_snake(other_snake, self.direction);
-
-            other_snake.translate(self.direction);
+            .push_player_move(self.snake.index(), self.direction);
 
-            self.history.push_with_updates(
+        // Move the other snakes and the blocks.
+        if !self.other_snakes.is_empty() || !self.blocks.is_empty() {
+            move_entities(
+                self.level_instance,
+                self.history,
+                &mut self.other_snakes,
+                &mut self.blocks,
+                self.direction,
                 MoveHistoryEvent::PassiveSnakeMove(self.direction),
-                other_snake.index(),
-                walkable_updates,
+                self.snake.index(),
             );
-        };
+        }
 
         // Consume food.
         if let Some(food) = &self.food {
@@ -153,19 +230,42 @@ impl<'a> PlayerMoveCommand<'a> {
             );
         }
 
-        // Then move the selected snake.
+        if let Some(shrink_food) = &self.shrink_food {
+            let walkable_updates = self.level_instance.eat_food(shrink_food.0);
+            self.history.push_with_updates(
+                MoveHistoryEvent::EatShrinkFood(shrink_food.0),
+                self.snake.index(),
+                walkable_updates,
+            );
+        }
+
+        // Then move the selected snake, it emerges at the portal exit when entering a portal.
         let old_tail = self.snake.tail();
-        let updates = self
-            .level_instance
-            .move_snake_forward(self.snake, self.direction);
+        if let Some(portal_exit) = self.portal_exit {
+            let updates = self
+                .level_instance
+                .move_snake_head_to(self.snake, portal_exit);
 
-        self.snake.move_forward(self.direction);
+            self.snake.move_head_to(portal_exit, self.direction);
 
-        self.history.push_with_updates(
-            MoveHistoryEvent::SnakeMoveForward(old_tail),
-            self.snake.index(),
-            updates,
-        );
+            self.history.push_with_updates(
+                MoveHistoryEvent::Teleport(old_tail),
+                self.snake.index(),
+                updates,
+            );
+        } else {
+            let updates = self
+                .level_instance
+                .move_snake_forward(self.snake, self.direction);
+
+            self.snake.move_forward(self.direction);
+
+            self.history.push_with_updates(
+                MoveHistoryEvent::SnakeMoveForward(old_tail),
+                self.snake.index(),
+                updates,
+            );
+        }
 
         // Grow.
         if self.food.is_some() {
@@ -178,5 +278,57 @@ impl<'a> PlayerMoveCommand<'a> {
                 walkable_updates,
             );
         }
+
+        // Shrink.
+        if self.shrink_food.is_some() {
+            let old_tail = self.snake.tail();
+            let walkable_updates = self.level_instance.shrink_snake(self.snake);
+            self.snake.shrink();
+
+            self.history.push_with_updates(
+                MoveHistoryEvent::Shrink(old_tail),
+                self.snake.index(),
+                walkable_updates,
+            );
+        }
+    }
+}
+
+/// Move snakes and blocks by an offset, they move together since they can move into each other's positions.
+/// Each snake records `snake_event`, the block moves are attributed to the snake `snake_index`.
+fn move_entities(
+    level_instance: &mut LevelInstance,
+    history: &mut SnakeHistory,
+    snakes: &mut [&mut Snake],
+    blocks: &mut [&mut Block],
+    offset: IVec2,
+    snake_event: MoveHistoryEvent,
+    snake_index: i32,
+) {
+    let snake_refs: Vec<&Snake> = snakes.iter().map(|snake| &**snake).collect();
+    let block_positions: Vec<IVec2> = blocks.iter().map(|block| block.0).collect();
+    let walkable_updates = level_instance.move_entities(&snake_refs, &block_positions, offset);
+
+    // The walkable updates can't be split per entity, they are all undone with the first moved entity.
+    let mut walkable_updates = Some(walkable_updates);
+    for snake in snakes.iter_mut() {
+        snake.translate(offset);
+
+        history.push_with_updates(
+            snake_event.clone(),
+            snake.index(),
+            walkable_updates.take().unwrap_or_default(),
+        );
+    }
+
+    for block in blocks.iter_mut() {
+        let old_position = block.0;
+        block.0 += offset;
+
+        history.push_with_updates(
+            MoveHistoryEvent::BlockMove(old_position, block.0),
+            snake_index,
+            walkable_updates.take().unwrap_or_default(),
+        );
     }
 }
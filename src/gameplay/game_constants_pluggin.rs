@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use bevy::prelude::*;
 use bevy_inspector_egui::prelude::*;
 
@@ -5,10 +7,16 @@ pub const GRID_TO_WORLD_UNIT: f32 = 35.;
 pub const GRID_TO_WORLD_UNIT_INVERSE: f32 = 1. / GRID_TO_WORLD_UNIT;
 pub const SNAKE_SIZE: Vec2 = Vec2::splat(GRID_TO_WORLD_UNIT);
 pub const SNAKE_EYE_SIZE: Vec2 = Vec2::splat(5.);
+/// The offset of the eyes from the center of the head, forward and to the side.
+pub const SNAKE_EYE_OFFSET: Vec2 = Vec2::splat(5.);
 pub const GRID_CELL_SIZE: Vec2 = SNAKE_SIZE;
 pub const MOVE_START_VELOCITY: f32 = 180.0;
 pub const JUMP_START_VELOCITY: f32 = 65.0;
 pub const GRAVITY: f32 = 300.0;
+pub const ANIMATION_SPEED: f32 = 1.0;
+pub const MOVE_EFFECT_VOLUME: f64 = 2.0;
+pub const EAT_EFFECT_VOLUME: f64 = 1.5;
+pub const GOAL_EFFECT_VOLUME: f64 = 2.0;
 
 pub const UP: IVec2 = IVec2::Y;
 pub const DOWN: IVec2 = IVec2::NEG_Y;
@@ -37,8 +45,12 @@ pub const SPIKE_COLOR: Color = Color::rgb(0.8, 0.7176471, 0.68235296);
 pub const WALL_COLOR: Color = rgb_u8!(119, 89, 54);
 pub const WATER_COLOR: Color = rgba_u8!(27, 85, 124, 108);
 pub const FOOD_COLOR: Color = Color::rgb(0.9764706, 0.5176471, 0.2901961);
+pub const SHRINK_FOOD_COLOR: Color = rgb_u8!(122, 82, 173);
+pub const BLOCK_COLOR: Color = rgb_u8!(160, 160, 170);
+pub const PORTAL_COLOR: Color = rgb_u8!(153, 102, 204);
+pub const GOAL_COLOR: Color = rgb_u8!(250, 227, 25);
 
-pub const SNAKE_COLORS: [[Color; 2]; 3] = [
+const SNAKE_COLORS: [[Color; 2]; 3] = [
     [
         rgb_u8!(68, 171, 96),
         Color::rgb(0.5647059, 0.74509805, 0.42745098),
@@ -50,14 +62,114 @@ pub const SNAKE_COLORS: [[Color; 2]; 3] = [
     [rgb_u8!(66, 135, 245), rgb_u8!(105, 159, 245)],
 ];
 
+/// The main and secondary colors of a snake, the parts alternate between the two.
+/// Past the palette the hue steps by the golden ratio so that any number of snakes have distinct colors.
+pub fn snake_colors(index: i32) -> [Color; 2] {
+    if let Some(colors) = SNAKE_COLORS.get(index as usize) {
+        return *colors;
+    }
+
+    let hue = (index as f32 * 0.618_034 * 360.0) % 360.0;
+    [Color::hsl(hue, 0.6, 0.45), Color::hsl(hue, 0.55, 0.6)]
+}
+
+/// Okabe-Ito colors, distinct for the common color vision deficiencies.
+const COLOR_BLIND_SNAKE_COLORS: [[Color; 2]; 3] = [
+    [rgb_u8!(0, 114, 178), rgb_u8!(86, 180, 233)],
+    [rgb_u8!(213, 94, 0), rgb_u8!(230, 159, 0)],
+    [rgb_u8!(204, 121, 167), rgb_u8!(225, 170, 200)],
+];
+
+/// The colors of the level entities, it can be changed from the inspector while playing.
+#[derive(Reflect, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Palette {
+    #[default]
+    Default,
+    /// Okabe-Ito based colors, the food is also outlined to be distinct by shape.
+    ColorBlind,
+}
+
+pub struct PaletteColors {
+    pub food: Color,
+    pub shrink_food: Color,
+    pub spike: Color,
+    pub block: Color,
+    pub portal: Color,
+    pub outline_food: bool,
+}
+
+impl Palette {
+    pub fn colors(&self) -> PaletteColors {
+        match self {
+            Palette::Default => PaletteColors {
+                food: FOOD_COLOR,
+                shrink_food: SHRINK_FOOD_COLOR,
+                spike: SPIKE_COLOR,
+                block: BLOCK_COLOR,
+                portal: PORTAL_COLOR,
+                outline_food: false,
+            },
+            Palette::ColorBlind => PaletteColors {
+                food: rgb_u8!(240, 228, 66),
+                shrink_food: rgb_u8!(0, 158, 115),
+                spike: rgb_u8!(40, 40, 40),
+                block: BLOCK_COLOR,
+                portal: rgb_u8!(240, 240, 240),
+                outline_food: true,
+            },
+        }
+    }
+
+    pub fn snake_colors(&self, index: i32) -> [Color; 2] {
+        match self {
+            Palette::Default => snake_colors(index),
+            Palette::ColorBlind => COLOR_BLIND_SNAKE_COLORS
+                .get(index as usize)
+                .copied()
+                .unwrap_or_else(|| snake_colors(index)),
+        }
+    }
+}
+
+/// The placement of a grid in the world, the size of its cells and the world position of the corner of the cell (0, 0).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridMapping {
+    pub cell_size: f32,
+    pub origin: Vec2,
+}
+
+/// The mapping of the level grid.
+pub const DEFAULT_GRID_MAPPING: GridMapping = GridMapping {
+    cell_size: GRID_TO_WORLD_UNIT,
+    origin: Vec2::ZERO,
+};
+
+impl Default for GridMapping {
+    fn default() -> Self {
+        DEFAULT_GRID_MAPPING
+    }
+}
+
+impl GridMapping {
+    /// The world position of the center of a cell.
+    pub fn to_world(&self, position: IVec2) -> Vec2 {
+        self.origin + (position.as_vec2() + 0.5) * self.cell_size
+    }
+
+    /// The cell closest to a world position.
+    pub fn to_grid(&self, position: Vec2) -> IVec2 {
+        ((position - self.origin) / self.cell_size - 0.5)
+            .round()
+            .as_ivec2()
+    }
+}
+
 pub fn to_world(position: IVec2) -> Vec2 {
-    (position.as_vec2() + 0.5) * GRID_TO_WORLD_UNIT
+    DEFAULT_GRID_MAPPING.to_world(position)
 }
 
 pub fn to_grid(position: Vec2) -> IVec2 {
-    (position * GRID_TO_WORLD_UNIT_INVERSE - 0.5)
-        .round()
-        .as_ivec2()
+    DEFAULT_GRID_MAPPING.to_grid(position)
 }
 
 #[derive(Resource, Reflect, InspectorOptions)]
@@ -72,11 +184,41 @@ pub struct GameConstants {
     #[inspector(min = 0.0, max = 900.0)]
     pub gravity: f32,
 
+    /// Multiplier of the speed of the move, push, fall and food animations.
+    #[inspector(min = 0.1, max = 4.0)]
+    pub animation_speed: f32,
+
+    /// Skip the animations, the snakes snap to their final position.
+    pub instant_moves: bool,
+
+    /// Preview where the selected snake lands when moving toward the hovered cell.
+    pub ghost_preview: bool,
+
+    /// Squash the snakes landing after a fall, more for longer falls.
+    pub landing_bounce: bool,
+
+    /// Play the background music, it can be toggled while playing.
+    pub music: bool,
+
+    /// Play the move, eat and goal sounds.
+    pub sound_effects: bool,
+
+    #[inspector(min = 0.0, max = 4.0)]
+    pub move_effect_volume: f64,
+
+    #[inspector(min = 0.0, max = 4.0)]
+    pub eat_effect_volume: f64,
+
+    #[inspector(min = 0.0, max = 4.0)]
+    pub goal_effect_volume: f64,
+
     pub background_color: Color,
 
     pub ground_color: Color,
 
     pub water_color: Color,
+
+    pub palette: Palette,
 }
 
 impl Default for GameConstants {
@@ -85,18 +227,91 @@ impl Default for GameConstants {
             move_velocity: MOVE_START_VELOCITY,
             jump_velocity: JUMP_START_VELOCITY,
             gravity: GRAVITY,
+            animation_speed: ANIMATION_SPEED,
+            instant_moves: false,
+            ghost_preview: true,
+            landing_bounce: true,
+            music: true,
+            sound_effects: true,
+            move_effect_volume: MOVE_EFFECT_VOLUME,
+            eat_effect_volume: EAT_EFFECT_VOLUME,
+            goal_effect_volume: GOAL_EFFECT_VOLUME,
             background_color: BACKGROUND_COLOR,
             ground_color: WALL_COLOR,
             water_color: WATER_COLOR,
+            palette: Palette::Default,
         }
     }
 }
+
+impl GameConstants {
+    /// The time elapsed for the animations during a frame.
+    pub fn animation_delta(&self, time: &Time) -> f32 {
+        self.animation_speed * time.delta_seconds()
+    }
+
+    /// The duration of a tween lasting `seconds` at the normal animation speed.
+    pub fn animation_duration(&self, seconds: f32) -> Duration {
+        Duration::from_secs_f32(seconds / self.animation_speed)
+    }
+}
+
+/// The keys controlling the game, rebindable from the inspector.
+#[derive(Resource, Reflect)]
+pub struct KeyBindings {
+    pub up: Vec<KeyCode>,
+    pub down: Vec<KeyCode>,
+    pub left: Vec<KeyCode>,
+    pub right: Vec<KeyCode>,
+    pub undo: Vec<KeyCode>,
+    pub restart: Vec<KeyCode>,
+    pub mute: Vec<KeyCode>,
+    pub cycle_snake: Vec<KeyCode>,
+}
+
+impl KeyBindings {
+    /// All the keys bound to a control of the game.
+    pub fn keys(&self) -> impl Iterator<Item = KeyCode> + '_ {
+        [
+            &self.up,
+            &self.down,
+            &self.left,
+            &self.right,
+            &self.undo,
+            &self.restart,
+            &self.mute,
+            &self.cycle_snake,
+        ]
+        .into_iter()
+        .flatten()
+        .copied()
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            up: vec![KeyCode::W, KeyCode::Up],
+            down: vec![KeyCode::S, KeyCode::Down],
+            left: vec![KeyCode::A, KeyCode::Left],
+            right: vec![KeyCode::D, KeyCode::Right],
+            undo: vec![KeyCode::Back],
+            restart: vec![KeyCode::R],
+            mute: vec![KeyCode::M],
+            cycle_snake: vec![KeyCode::Tab],
+        }
+    }
+}
+
 pub struct GameConstantsPlugin;
 
 impl Plugin for GameConstantsPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<GameConstants>()
+            .register_type::<KeyBindings>()
+            .register_type::<Palette>()
             .insert_resource(GameConstants::default())
+            .init_resource::<KeyBindings>()
             .add_system(update_colors);
     }
 }
@@ -104,3 +319,26 @@ impl Plugin for GameConstantsPlugin {
 fn update_colors(mut commands: Commands, game_constants: Res<GameConstants>) {
     commands.insert_resource(ClearColor(game_constants.background_color));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_grid_mapping() {
+        let position = IVec2::new(3, -2);
+        assert_eq!(
+            to_world(position),
+            Vec2::new(3.5, -1.5) * GRID_TO_WORLD_UNIT
+        );
+        assert_eq!(to_grid(to_world(position)), position);
+
+        let mapping = GridMapping {
+            cell_size: 10.0,
+            origin: Vec2::new(100.0, -50.0),
+        };
+        assert_eq!(mapping.to_world(position), Vec2::new(135.0, -65.0));
+        assert_eq!(mapping.to_grid(Vec2::new(135.0, -65.0)), position);
+        assert_eq!(mapping.to_grid(Vec2::new(138.0, -62.0)), position);
+    }
+}
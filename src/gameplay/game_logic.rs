@@ -0,0 +1,963 @@
+//! The game rules, independent of the Bevy entities so that they can be used by the systems, tests and solvers.
+//! The functions operate on the snakes, the level instance and the history, and record undoable events.
+
+use bevy::prelude::*;
+
+use crate::{
+    gameplay::commands::SnakeCommands,
+    gameplay::level_pluggin::{Block, Food, Goal, ShrinkFood},
+    gameplay::snake_pluggin::Snake,
+    gameplay::undo::SnakeHistory,
+    level::level_instance::{LevelEntityType, LevelInstance, PushedEntities},
+    level::level_template::{Cell, GoalCondition, LevelTemplate},
+};
+
+/// Outcome of a player move.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoveOutcome {
+    /// The move is blocked by a wall, a spike, a one-way wall, the snake itself or by entities that can't be pushed.
+    Blocked,
+
+    /// Moving up while standing makes the snake jump, it falls back to the same place.
+    Jumped,
+
+    /// The snake moved, pushing the snakes with the given indices.
+    Moved {
+        pushed_snakes: Vec<i32>,
+        reached_goal: bool,
+    },
+}
+
+/// Outcome of one unit of fall of a snake.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FallOutcome {
+    Falling,
+    Landed,
+    OnSpikes,
+    Drowned,
+    OutOfLevel,
+}
+
+/// Outcome of resolving gravity on a whole level.
+#[derive(Debug, PartialEq, Eq)]
+pub enum GravityOutcome {
+    Stable,
+    Fell,
+    FellOnSpikes,
+    Drowned,
+    FellOutOfLevel,
+}
+
+/// A standing snake moving up, against the gravity, jumps in place, it doesn't have enough parts to climb.
+/// The food and the active goals accepting the snake right above its head are reached by moving up instead.
+/// The jump is decided before any collision, a standing snake under a wall jumps too.
+pub fn is_jump(level: &LevelInstance, snake: &Snake, goals: &[Goal], direction: IVec2) -> bool {
+    let new_position = snake.head_position() + direction;
+
+    direction == -level.gravity()
+        && snake.is_standing(level.gravity())
+        && !level.is_food(new_position)
+        && !goals
+            .iter()
+            .any(|goal| goal.0 == new_position && goal.accepts(snake.index()))
+}
+
+/// Move a snake one cell in a direction, pushing the snakes and blocks in the way and eating the food.
+/// `goals` are the active goals, snakes don't jump when moving up into an active goal accepting them.
+pub fn player_move(
+    level: &mut LevelInstance,
+    history: &mut SnakeHistory,
+    snake: &mut Snake,
+    other_snakes: &mut [&mut Snake],
+    blocks: &mut [&mut Block],
+    goals: &[Goal],
+    direction: IVec2,
+) -> MoveOutcome {
+    let new_position = snake.head_position() + direction;
+    let snake_index = snake.index();
+    let is_goal_for_snake = |position: IVec2| {
+        goals
+            .iter()
+            .any(|goal| goal.0 == position && goal.accepts(snake_index))
+    };
+
+    if is_jump(level, snake, goals, direction) {
+        return MoveOutcome::Jumped;
+    }
+
+    // Check for collition with self, walls and one-way walls.
+    if snake.occupies_position(new_position)
+        || level.is_wall_or_spike(new_position)
+        || !level.can_enter(new_position, direction)
+    {
+        return MoveOutcome::Blocked;
+    }
+
+    // Snakes can't shrink below two parts.
+    if level.is_shrink_food(new_position) && snake.len() <= 2 {
+        return MoveOutcome::Blocked;
+    }
+
+    // Entering a free portal moves the head to the paired portal, the move is blocked if the exit is not free.
+    let portal_exit = match level.find_portal_exit(new_position, direction) {
+        Some(None) => return MoveOutcome::Blocked,
+        Some(Some(exit)) => Some(exit),
+        None => None,
+    };
+
+    // Find if there are snakes or blocks in the way, entities in contact with a pushed entity are pushed too.
+    let pushed = if level.is_snake(new_position).is_some() || level.is_block(new_position) {
+        let snakes: Vec<&Snake> = other_snakes
+            .iter()
+            .map(|other_snake| &**other_snake)
+            .collect();
+
+        let Some(pushed) = level.find_pushed_entities(
+            &snakes,
+            snake.index(),
+            new_position,
+            direction,
+        ) else {
+            return MoveOutcome::Blocked;
+        };
+
+        pushed
+    } else {
+        PushedEntities::default()
+    };
+
+    let pushed_snakes: Vec<&mut Snake> = other_snakes
+        .iter_mut()
+        .filter(|other_snake| pushed.snakes.contains(&other_snake.index()))
+        .map(|other_snake| &mut **other_snake)
+        .collect();
+
+    let pushed_blocks: Vec<&mut Block> = blocks
+        .iter_mut()
+        .filter(|block| pushed.blocks.contains(&block.0))
+        .map(|block| &mut **block)
+        .collect();
+
+    // Any food?
+    let food = level.is_food(new_position).then_some(Food(new_position));
+    let shrink_food = level
+        .is_shrink_food(new_position)
+        .then_some(ShrinkFood(new_position));
+
+    // Finaly move the snake forward and commit the state.
+    SnakeCommands::new(level, history)
+        .player_move(snake, direction)
+        .pushing_snakes(pushed_snakes)
+        .pushing_blocks(pushed_blocks)
+        .eating_food(food.as_ref())
+        .eating_shrink_food(shrink_food.as_ref())
+        .through_portal(portal_exit)
+        .execute();
+
+    MoveOutcome::Moved {
+        pushed_snakes: pushed.snakes,
+        reached_goal: is_goal_for_snake(snake.head_position()),
+    }
+}
+
+/// Is any part of the snake under the water level.
+pub fn is_in_water(level: &LevelInstance, snake: &Snake) -> bool {
+    snake
+        .parts()
+        .iter()
+        .any(|(position, _)| level.is_water(*position))
+}
+
+/// Sort the snakes from the lowest to the highest along the gravity so that the snakes they support see them fall in the same tick.
+pub fn sort_bottom_first<T>(snakes: &mut [T], gravity: IVec2, snake: impl Fn(&T) -> &Snake) {
+    snakes.sort_by_key(|item| {
+        snake(item)
+            .parts()
+            .iter()
+            .map(|(position, _)| position.dot(-gravity))
+            .min()
+    });
+}
+
+/// Parts of the falling snakes other than the snake at `snake_index`.
+/// Falling snakes are not marked in the level while they fall, snakes above them rest on their current position.
+pub fn other_falling_parts(
+    snakes: &[&mut Snake],
+    is_falling: &[bool],
+    snake_index: usize,
+) -> Vec<IVec2> {
+    snakes
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != snake_index && is_falling[*index])
+        .flat_map(|(_, snake)| snake.parts().iter().map(|(position, _)| *position))
+        .collect()
+}
+
+/// Is there empty space below the snake, considering the falling snakes as support.
+/// Snakes only fall one unit at a time so checking the cells right below them is enough for the falling snakes.
+pub fn is_above_ground(level: &LevelInstance, snake: &Snake, falling_parts: &[IVec2]) -> bool {
+    let min_distance_to_ground = snake
+        .parts()
+        .iter()
+        .filter_map(|(position, _)| level.get_distance_to_ground(*position, snake.index()))
+        .min();
+
+    // No ground means the snake falls out of the level.
+    min_distance_to_ground.map_or(true, |distance| distance > 1)
+        && snake
+            .parts()
+            .iter()
+            .all(|(position, _)| !falling_parts.contains(&(*position + level.gravity())))
+}
+
+/// Start the fall of the snakes on the ground with nothing below them.
+/// A snake starting to fall can leave the snakes it supports without ground so iterate until no more snake starts falling.
+/// Returns the indices of the snakes that started falling, they are moved one unit along the gravity.
+pub fn start_falling_snakes(
+    level: &mut LevelInstance,
+    history: &mut SnakeHistory,
+    snakes: &mut [&mut Snake],
+    is_falling: &mut [bool],
+) -> Vec<usize> {
+    let mut started = vec![];
+
+    let mut stable = false;
+    while !stable {
+        stable = true;
+
+        for index in 0..snakes.len() {
+            if is_falling[index] {
+                continue;
+            }
+
+            let falling_parts = other_falling_parts(snakes, is_falling, index);
+            if !is_above_ground(level, snakes[index], &falling_parts) {
+                continue;
+            }
+
+            SnakeCommands::new(level, history).start_falling(snakes[index]);
+            snakes[index].fall_one_unit(level.gravity());
+
+            is_falling[index] = true;
+            started.push(index);
+            stable = false;
+        }
+    }
+
+    started
+}
+
+/// Continue the fall of a falling snake for one unit, or land it.
+/// `grid_distance` is the distance fallen so far, snakes landing without moving have nothing to commit.
+pub fn fall_one_unit(
+    level: &mut LevelInstance,
+    history: &mut SnakeHistory,
+    snake: &mut Snake,
+    falling_parts: &[IVec2],
+    grid_distance: i32,
+    fall_out_of_level_limit: i32,
+) -> FallOutcome {
+    // Check if we fell on spikes pointing against the gravity.
+    if snake
+        .parts()
+        .iter()
+        .any(|(position, _)| level.is_lethal_spike(*position, level.gravity()))
+    {
+        SnakeCommands::new(level, history).stop_falling_on_spikes(snake);
+        return FallOutcome::OnSpikes;
+    }
+
+    // Check if we fell in the water.
+    if is_in_water(level, snake) {
+        SnakeCommands::new(level, history).stop_falling(snake);
+        return FallOutcome::Drowned;
+    }
+
+    // Check if we fell out of the level, the whole snake must be past the limit.
+    if snake
+        .parts()
+        .iter()
+        .all(|(position, _)| position.dot(level.gravity()) > fall_out_of_level_limit)
+    {
+        SnakeCommands::new(level, history).stop_falling(snake);
+        return FallOutcome::OutOfLevel;
+    }
+
+    // keep falling..
+    if is_above_ground(level, snake, falling_parts) {
+        snake.fall_one_unit(level.gravity());
+        return FallOutcome::Falling;
+    }
+
+    // ..or land.
+    if grid_distance > 0 {
+        SnakeCommands::new(level, history).stop_falling(snake);
+    }
+
+    FallOutcome::Landed
+}
+
+/// Drop the blocks with nothing below them, blocks fall instantly. Returns true if a block dropped.
+pub fn drop_blocks(
+    level: &mut LevelInstance,
+    history: &mut SnakeHistory,
+    blocks: &mut [&mut Block],
+) -> bool {
+    // Lower blocks first so that stacked blocks fall together.
+    let gravity = level.gravity();
+    blocks.sort_by_key(|block| block.0.dot(-gravity));
+
+    let mut dropped = false;
+
+    for block in blocks.iter_mut() {
+        if !level.is_block(block.0) {
+            continue;
+        }
+
+        let distance_to_ground = level.get_block_distance_to_ground(block.0);
+        if distance_to_ground == Some(1) {
+            continue;
+        }
+
+        SnakeCommands::new(level, history).drop_block(block, distance_to_ground);
+        dropped = true;
+    }
+
+    dropped
+}
+
+/// Carry the snakes resting on conveyors one cell, the snakes are carried in the order of their indices.
+/// A snake rests on a conveyor when a cell right below one of its parts along the gravity is a conveyor,
+/// snakes resting on conveyors going in different directions and conveyors along the gravity don't move.
+/// Returns the indices of the moved snakes, the pushed snakes included, with the direction they moved in.
+pub fn apply_conveyors(
+    level: &mut LevelInstance,
+    history: &mut SnakeHistory,
+    snakes: &mut [&mut Snake],
+    blocks: &mut [&mut Block],
+) -> Vec<(i32, IVec2)> {
+    let mut moved: Vec<(i32, IVec2)> = vec![];
+
+    let mut snake_indices: Vec<i32> = snakes.iter().map(|snake| snake.index()).collect();
+    snake_indices.sort();
+
+    for snake_index in snake_indices {
+        // Snakes pushed by a carried snake already moved.
+        if moved.iter().any(|(index, _)| *index == snake_index) {
+            continue;
+        }
+
+        let Some(snake) = snakes.iter().find(|snake| snake.index() == snake_index) else {
+            continue;
+        };
+
+        let mut directions = snake
+            .parts()
+            .iter()
+            .filter_map(|(position, _)| level.conveyor_direction(*position + level.gravity()));
+        let Some(direction) = directions.next() else {
+            continue;
+        };
+        if directions.any(|other| other != direction) || direction.dot(level.gravity()) != 0 {
+            continue;
+        }
+
+        let snake_refs: Vec<&Snake> = snakes.iter().map(|snake| &**snake).collect();
+        let Some(conveyed) = level.find_conveyed_entities(&snake_refs, snake_index, direction)
+        else {
+            continue;
+        };
+
+        let mut conveyed_snakes: Vec<&mut Snake> = snakes
+            .iter_mut()
+            .filter(|snake| conveyed.snakes.contains(&snake.index()))
+            .map(|snake| &mut **snake)
+            .collect();
+        let mut conveyed_blocks: Vec<&mut Block> = blocks
+            .iter_mut()
+            .filter(|block| conveyed.blocks.contains(&block.0))
+            .map(|block| &mut **block)
+            .collect();
+
+        SnakeCommands::new(level, history).convey(
+            snake_index,
+            &mut conveyed_snakes,
+            &mut conveyed_blocks,
+            direction,
+        );
+
+        moved.extend(conveyed.snakes.iter().map(|index| (*index, direction)));
+    }
+
+    moved
+}
+
+/// A level played without Bevy, with the same rules as the game.
+/// Undo needs the Bevy entities and is not available, snakes exiting the level are removed.
+#[derive(Clone)]
+pub struct GameLogic {
+    pub level: LevelInstance,
+    pub history: SnakeHistory,
+    pub snakes: Vec<Snake>,
+    pub blocks: Vec<Block>,
+    pub goals: Vec<Goal>,
+    fall_out_of_level_limit: i32,
+    goal_condition: GoalCondition,
+    snake_count: usize,
+}
+
+impl GameLogic {
+    pub fn new(level_template: &LevelTemplate) -> Self {
+        let mut level = LevelInstance::new();
+        level.set_water_level(level_template.water_level);
+        level.set_gravity(level_template.gravity, level_template.grid_size());
+
+        for (position, direction) in &level_template.one_way_positions {
+            level.add_one_way(*position, *direction);
+        }
+
+        for (first, second) in &level_template.portal_pairs {
+            level.add_portal_pair(*first, *second);
+        }
+
+        for (position, cell) in level_template.grid.iter() {
+            if cell == Cell::Wall {
+                level.mark_position_occupied(position, LevelEntityType::Wall);
+            }
+        }
+
+        for (position, direction) in &level_template.conveyor_positions {
+            level.mark_position_occupied(*position, LevelEntityType::Conveyor(*direction));
+        }
+
+        for position in &level_template.food_positions {
+            level.mark_position_occupied(*position, LevelEntityType::Food);
+        }
+
+        for (position, orientation) in &level_template.spike_positions {
+            level.mark_position_occupied(*position, LevelEntityType::Spike(*orientation));
+        }
+
+        for position in &level_template.block_positions {
+            level.mark_position_occupied(*position, LevelEntityType::Block);
+        }
+
+        level.close_goals(level_template);
+
+        let snakes: Vec<Snake> = level_template
+            .initial_snakes
+            .iter()
+            .enumerate()
+            .map(|(snake_index, snake_template)| {
+                Snake::from_template(snake_template, snake_index as i32)
+            })
+            .collect();
+
+        for snake in &snakes {
+            level.mark_snake_positions(snake);
+        }
+
+        GameLogic {
+            level,
+            history: SnakeHistory::default(),
+            snakes,
+            blocks: level_template
+                .block_positions
+                .iter()
+                .map(|position| Block(*position))
+                .collect(),
+            goals: level_template
+                .goals
+                .iter()
+                .map(|(position, snake_index)| Goal(*position, *snake_index))
+                .collect(),
+            fall_out_of_level_limit: level_template.fall_out_of_level_limit(),
+            goal_condition: level_template.goal_condition,
+            snake_count: level_template.initial_snakes.len(),
+        }
+    }
+
+    /// A game continuing from the state of a level being played, used to predict the moves.
+    /// The goal condition is not known, the game is complete when all the snakes exit.
+    pub fn from_level(
+        level: LevelInstance,
+        snakes: Vec<Snake>,
+        blocks: Vec<Block>,
+        goals: Vec<Goal>,
+        fall_out_of_level_limit: i32,
+    ) -> Self {
+        let snake_count = snakes.len();
+        GameLogic {
+            level,
+            history: SnakeHistory::default(),
+            snakes,
+            blocks,
+            goals,
+            fall_out_of_level_limit,
+            goal_condition: GoalCondition::AllSnakes,
+            snake_count,
+        }
+    }
+
+    /// Predict where a snake ends after a move and the falls it causes, the game is left intact.
+    /// Returns None if the move is blocked or is a jump, or if the snake exits the level.
+    pub fn predict_move(&self, snake_index: i32, direction: IVec2) -> Option<Snake> {
+        let mut game = self.clone();
+        let (move_outcome, _) = game.player_move(snake_index, direction);
+        if !matches!(move_outcome, MoveOutcome::Moved { .. }) {
+            return None;
+        }
+
+        game.snakes
+            .into_iter()
+            .find(|snake| snake.index() == snake_index)
+    }
+
+    /// The goals are active once all the food is eaten.
+    pub fn active_goals(&self) -> &[Goal] {
+        let has_food = self
+            .level
+            .positions_of(LevelEntityType::Food)
+            .next()
+            .is_some();
+
+        if has_food {
+            &[]
+        } else {
+            &self.goals
+        }
+    }
+
+    /// The level is complete when enough snakes exited through the goal for the goal condition.
+    pub fn is_complete(&self) -> bool {
+        let exited_count = self.snake_count - self.snakes.len();
+        self.goal_condition.is_met(exited_count, self.snake_count)
+    }
+
+    /// Move a snake then resolve gravity and exit the snakes reaching the goal.
+    /// Once the snakes settle the conveyors carry the snakes resting on them, and the snakes settle again.
+    pub fn player_move(
+        &mut self,
+        snake_index: i32,
+        direction: IVec2,
+    ) -> (MoveOutcome, GravityOutcome) {
+        let goals = self.active_goals().to_vec();
+
+        let mut snakes: Vec<&mut Snake> = self.snakes.iter_mut().collect();
+        let Some(position) = snakes.iter().position(|snake| snake.index() == snake_index) else {
+            return (MoveOutcome::Blocked, GravityOutcome::Stable);
+        };
+        let snake = snakes.remove(position);
+        let mut blocks: Vec<&mut Block> = self.blocks.iter_mut().collect();
+
+        let move_outcome = player_move(
+            &mut self.level,
+            &mut self.history,
+            snake,
+            &mut snakes,
+            &mut blocks,
+            &goals,
+            direction,
+        );
+
+        if !matches!(move_outcome, MoveOutcome::Moved { .. }) {
+            return (move_outcome, GravityOutcome::Stable);
+        }
+
+        let mut gravity_outcome = self.settle();
+        if !matches!(
+            gravity_outcome,
+            GravityOutcome::Stable | GravityOutcome::Fell
+        ) {
+            return (move_outcome, gravity_outcome);
+        }
+
+        let mut snakes: Vec<&mut Snake> = self.snakes.iter_mut().collect();
+        let mut blocks: Vec<&mut Block> = self.blocks.iter_mut().collect();
+        let conveyed =
+            apply_conveyors(&mut self.level, &mut self.history, &mut snakes, &mut blocks);
+        if !conveyed.is_empty() {
+            match self.settle() {
+                GravityOutcome::Stable => {}
+                outcome => gravity_outcome = outcome,
+            }
+        }
+
+        (move_outcome, gravity_outcome)
+    }
+
+    /// Drown the snakes in the water, let the snakes fall and exit the snakes reaching the goal.
+    fn settle(&mut self) -> GravityOutcome {
+        // Moving or being pushed into the water drowns the snake.
+        if self
+            .snakes
+            .iter()
+            .any(|snake| is_in_water(&self.level, snake))
+        {
+            return GravityOutcome::Drowned;
+        }
+
+        // Snakes exiting the level can leave other snakes without ground.
+        let mut gravity_outcome = GravityOutcome::Stable;
+        loop {
+            match self.apply_gravity() {
+                GravityOutcome::Stable => {}
+                GravityOutcome::Fell => gravity_outcome = GravityOutcome::Fell,
+                lost => return lost,
+            }
+
+            if !self.exit_snakes_at_goal() {
+                break;
+            }
+        }
+
+        gravity_outcome
+    }
+
+    /// Let all the snakes and blocks fall until they land.
+    pub fn apply_gravity(&mut self) -> GravityOutcome {
+        let mut outcome = GravityOutcome::Stable;
+
+        loop {
+            let mut blocks: Vec<&mut Block> = self.blocks.iter_mut().collect();
+            drop_blocks(&mut self.level, &mut self.history, &mut blocks);
+
+            let mut snakes: Vec<&mut Snake> = self.snakes.iter_mut().collect();
+            sort_bottom_first(&mut snakes, self.level.gravity(), |snake| &**snake);
+
+            let mut is_falling = vec![false; snakes.len()];
+            let started = start_falling_snakes(
+                &mut self.level,
+                &mut self.history,
+                &mut snakes,
+                &mut is_falling,
+            );
+            if started.is_empty() {
+                return outcome;
+            }
+
+            outcome = GravityOutcome::Fell;
+
+            // The snakes that started falling fall together until they all land.
+            let mut grid_distances = vec![1; snakes.len()];
+            while is_falling.iter().any(|falling| *falling) {
+                for index in 0..snakes.len() {
+                    if !is_falling[index] {
+                        continue;
+                    }
+
+                    let falling_parts = other_falling_parts(&snakes, &is_falling, index);
+                    match fall_one_unit(
+                        &mut self.level,
+                        &mut self.history,
+                        snakes[index],
+                        &falling_parts,
+                        grid_distances[index],
+                        self.fall_out_of_level_limit,
+                    ) {
+                        FallOutcome::Falling => grid_distances[index] += 1,
+                        FallOutcome::Landed => is_falling[index] = false,
+                        FallOutcome::OnSpikes => return GravityOutcome::FellOnSpikes,
+                        FallOutcome::Drowned => return GravityOutcome::Drowned,
+                        FallOutcome::OutOfLevel => return GravityOutcome::FellOutOfLevel,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remove the snakes with their head in an active goal accepting them, returns true if any snake exited.
+    fn exit_snakes_at_goal(&mut self) -> bool {
+        let goals = self.active_goals();
+
+        let Some(position) = self.snakes.iter().position(|snake| {
+            goals
+                .iter()
+                .any(|goal| goal.0 == snake.head_position() && goal.accepts(snake.index()))
+        }) else {
+            return false;
+        };
+
+        let snake = self.snakes.remove(position);
+        self.level.clear_snake_positions(&snake);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gameplay::game_constants_pluggin::{DOWN, LEFT, RIGHT, UP},
+        gameplay::undo::MoveHistoryEvent,
+        level::test_levels::{
+            CONVEYORS, FALL_ON_SPIKE, ONE_WAY_WALLS, PORTALS, PUSH_CHAIN, RAISED_WATER,
+            SNAKE_GOALS, STACKED_SNAKES_FALL,
+        },
+    };
+
+    fn load(level: &str) -> GameLogic {
+        GameLogic::new(&LevelTemplate::parse(level).unwrap())
+    }
+
+    #[test]
+    pub fn test_closed_goals() {
+        // The food out of reach keeps the goal closed.
+        let mut game = load("closed_goals: true\n---\n...o\n....\naAX.\n####");
+        let (outcome, _) = game.player_move(0, RIGHT);
+        assert_eq!(outcome, MoveOutcome::Blocked);
+
+        let mut game = load("...o\n....\naAX.\n####");
+        let (outcome, _) = game.player_move(0, RIGHT);
+        assert!(matches!(outcome, MoveOutcome::Moved { .. }));
+
+        // The goal opens with the last food.
+        let mut game = load("closed_goals: true\n---\n.....\naAoX.\n#####");
+        let goal = IVec2::new(3, 1);
+        assert_eq!(
+            game.level.entity_at(goal),
+            Some(LevelEntityType::ClosedGoal)
+        );
+
+        game.player_move(0, RIGHT);
+        assert_eq!(game.level.entity_at(goal), None);
+
+        game.player_move(0, RIGHT);
+        assert!(game.is_complete());
+    }
+
+    #[test]
+    pub fn test_predict_move() {
+        let game = load("....X..\n..aA...\n.##....\n.......\n#######");
+
+        let snake = game.predict_move(0, RIGHT).unwrap();
+        assert_eq!(snake.head_position(), IVec2::new(4, 1));
+        assert_eq!(snake.tail_position(), IVec2::new(3, 1));
+
+        // The game is left intact.
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(3, 3));
+        assert!(game.level.is_empty(IVec2::new(4, 3)));
+
+        assert!(game.predict_move(0, LEFT).is_none());
+    }
+
+    #[test]
+    pub fn test_is_jump() {
+        let is_jump_up = |level: &str| {
+            let game = load(level);
+            is_jump(&game.level, &game.snakes[0], game.active_goals(), UP)
+        };
+
+        // Standing snakes jump whatever their length.
+        assert!(is_jump_up("X..\n...\n.A.\n.a.\n###"));
+        assert!(is_jump_up("X..\n.A.\n.a.\n.a.\n###"));
+
+        // A bent snake has a part to climb with.
+        assert!(!is_jump_up("X..\n...\n.A.\naa.\n###"));
+
+        // Food and active goals right above are reached by moving up.
+        assert!(!is_jump_up("X..\n.o.\n.A.\n.a.\n###"));
+        assert!(!is_jump_up("...\n.X.\n.A.\n.a.\n###"));
+
+        // Inactive goals and goals for other snakes don't count.
+        assert!(is_jump_up("..o\n.X.\n.A.\n.a.\n###"));
+        assert!(is_jump_up("X..\n.1.\n.A.\n.a.\n###"));
+
+        // Walls above don't prevent the jump.
+        assert!(is_jump_up("X..\n.#.\n.A.\n.a.\n###"));
+        let mut game = load("X..\n.#.\n.A.\n.a.\n###");
+        assert_eq!(game.player_move(0, UP).0, MoveOutcome::Jumped);
+
+        let game = load("X..\n...\n.A.\n.a.\n###");
+        assert!(!is_jump(&game.level, &game.snakes[0], &[], RIGHT));
+    }
+
+    #[test]
+    pub fn test_move_blocked_by_self() {
+        let mut game = load(PUSH_CHAIN);
+
+        let (outcome, _) = game.player_move(0, LEFT);
+        assert_eq!(outcome, MoveOutcome::Blocked);
+    }
+
+    #[test]
+    pub fn test_push_chain() {
+        let mut game = load(PUSH_CHAIN);
+
+        let (outcome, _) = game.player_move(0, RIGHT);
+        assert_eq!(
+            outcome,
+            MoveOutcome::Moved {
+                pushed_snakes: vec![1, 2],
+                reached_goal: false
+            }
+        );
+        assert!(game.snakes[2].occupies_position(IVec2::new(7, 1)));
+    }
+
+    #[test]
+    pub fn test_stacked_snakes_fall_together() {
+        let mut game = load(STACKED_SNAKES_FALL);
+
+        assert_eq!(game.apply_gravity(), GravityOutcome::Fell);
+        assert_eq!(game.snakes[1].head_position(), IVec2::new(4, 1));
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(4, 2));
+    }
+
+    #[test]
+    pub fn test_fall_on_spikes() {
+        let mut game = load(FALL_ON_SPIKE);
+
+        let (_, gravity_outcome) = game.player_move(0, RIGHT);
+        assert_eq!(gravity_outcome, GravityOutcome::FellOnSpikes);
+    }
+
+    #[test]
+    pub fn test_directional_spikes() {
+        let mut game = load("....X..\n..aA...\n.##....\n...!!!.");
+        let (_, gravity_outcome) = game.player_move(0, RIGHT);
+        assert_eq!(gravity_outcome, GravityOutcome::FellOnSpikes);
+
+        // Spikes pointing down or sideways are safe to land on.
+        let mut game = load("....X..\n..aA...\n.##....\n...'{}.");
+        let (_, gravity_outcome) = game.player_move(0, RIGHT);
+        assert_eq!(gravity_outcome, GravityOutcome::Fell);
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(4, 1));
+    }
+
+    #[test]
+    pub fn test_fall_out_of_level() {
+        let mut game = load("..X.\naA..\n#...");
+
+        let (_, gravity_outcome) = game.player_move(0, RIGHT);
+        assert_eq!(gravity_outcome, GravityOutcome::FellOutOfLevel);
+        assert!(game.snakes[0]
+            .parts()
+            .iter()
+            .all(|(position, _)| position.dot(DOWN) > game.fall_out_of_level_limit));
+    }
+
+    #[test]
+    pub fn test_gravity_direction() {
+        let mut game = load("gravity: left\n---\n#...X\n#....\n#..aA\n#####");
+        assert_eq!(game.apply_gravity(), GravityOutcome::Fell);
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(2, 1));
+
+        // Lying on the left wall the snake stands, moving right is a jump.
+        assert_eq!(game.player_move(0, RIGHT).0, MoveOutcome::Jumped);
+
+        let (_, gravity_outcome) = game.player_move(0, UP);
+        assert_eq!(gravity_outcome, GravityOutcome::Fell);
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(1, 2));
+
+        // Nothing stops the fall past the top of the level.
+        let mut game = load("gravity: up\n---\n....X\n.aA..\n#####");
+        assert_eq!(game.apply_gravity(), GravityOutcome::FellOutOfLevel);
+    }
+
+    #[test]
+    pub fn test_drown_in_raised_water() {
+        let mut game = load(RAISED_WATER);
+
+        let (_, gravity_outcome) = game.player_move(0, RIGHT);
+        assert_eq!(gravity_outcome, GravityOutcome::Stable);
+
+        let (_, gravity_outcome) = game.player_move(0, RIGHT);
+        assert_eq!(gravity_outcome, GravityOutcome::Drowned);
+    }
+
+    #[test]
+    pub fn test_one_way_walls() {
+        let mut game = load(ONE_WAY_WALLS);
+
+        // The snake stands and walks on the one-way platform.
+        assert_eq!(game.apply_gravity(), GravityOutcome::Stable);
+        let (_, gravity_outcome) = game.player_move(0, LEFT);
+        assert_eq!(gravity_outcome, GravityOutcome::Stable);
+        let (_, gravity_outcome) = game.player_move(0, LEFT);
+        assert_eq!(gravity_outcome, GravityOutcome::Stable);
+
+        // The one-way wall can only be entered moving right.
+        let (outcome, _) = game.player_move(0, LEFT);
+        assert_eq!(outcome, MoveOutcome::Blocked);
+    }
+
+    #[test]
+    pub fn test_conveyors() {
+        let mut game = load(CONVEYORS);
+
+        // After the move the conveyors carry the snake, pushing the block.
+        let (_, gravity_outcome) = game.player_move(0, RIGHT);
+        assert_eq!(gravity_outcome, GravityOutcome::Stable);
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(4, 2));
+        assert_eq!(game.blocks[0].0, IVec2::new(5, 2));
+        assert!(game
+            .history
+            .move_history
+            .iter()
+            .any(|event| event.event == MoveHistoryEvent::Conveyor(RIGHT)));
+
+        // The block pushed off the conveyors falls, the snake is carried once more and rests on it.
+        game.player_move(0, RIGHT);
+        assert_eq!(game.blocks[0].0, IVec2::new(6, 1));
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(6, 2));
+
+        // Off the conveyors the snake only moves when the player moves it.
+        game.player_move(0, RIGHT);
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(7, 2));
+        assert_eq!(game.snakes[0].tail_position(), IVec2::new(6, 2));
+    }
+
+    #[test]
+    pub fn test_portal() {
+        let mut game = load(PORTALS);
+
+        game.player_move(0, RIGHT);
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(8, 1));
+        assert_eq!(game.snakes[0].tail_position(), IVec2::new(2, 1));
+        assert!(game.level.is_empty(IVec2::new(3, 1)));
+
+        game.player_move(0, RIGHT);
+        assert_eq!(game.snakes[0].head_position(), IVec2::new(9, 1));
+        assert_eq!(game.snakes[0].tail_position(), IVec2::new(8, 1));
+    }
+
+    #[test]
+    pub fn test_portal_exit_blocked() {
+        let mut game = load(".......=..X\n.aA@...@...\n###########");
+
+        // The block falls on the portal exit.
+        game.apply_gravity();
+        assert_eq!(game.blocks[0].0, IVec2::new(7, 1));
+
+        let (outcome, _) = game.player_move(0, RIGHT);
+        assert_eq!(outcome, MoveOutcome::Blocked);
+    }
+
+    #[test]
+    pub fn test_snake_goals() {
+        let mut game = load(SNAKE_GOALS);
+
+        // The goal of the other snake is crossed without exiting.
+        game.player_move(0, RIGHT);
+        let (outcome, _) = game.player_move(0, RIGHT);
+        assert_eq!(
+            outcome,
+            MoveOutcome::Moved {
+                pushed_snakes: vec![],
+                reached_goal: false
+            }
+        );
+
+        game.player_move(0, RIGHT);
+        game.player_move(0, RIGHT);
+        assert_eq!(game.snakes.len(), 1);
+        assert!(!game.is_complete());
+
+        for _ in 0..4 {
+            game.player_move(1, LEFT);
+        }
+        assert!(game.is_complete());
+    }
+}
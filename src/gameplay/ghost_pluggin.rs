@@ -0,0 +1,141 @@
+//! A translucent preview of where the selected snake lands when moving toward the hovered cell.
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::ConditionSet;
+
+use crate::{
+    gameplay::game_constants_pluggin::{
+        to_world, GameConstants, DOWN, GRID_CELL_SIZE, LEFT, RIGHT, UP,
+    },
+    gameplay::game_logic::GameLogic,
+    gameplay::level_pluggin::{Block, Goal, LevelEntity},
+    gameplay::movement_pluggin::{
+        GravityFall, LevelExitAnim, MoveCommand, PushedAnim, SnakeMovedEvent,
+    },
+    gameplay::snake_pluggin::{cursor_grid_position, Active, SelectedSnake, Snake},
+    gameplay::undo::UndoEvent,
+    level::level_instance::LevelInstance,
+    level::level_template::LevelTemplate,
+    GameState,
+};
+
+const GHOST_ALPHA: f32 = 0.35;
+
+pub struct GhostPluggin;
+
+impl Plugin for GhostPluggin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Game)
+                .run_if_resource_exists::<LevelInstance>()
+                .run_if_resource_exists::<LevelTemplate>()
+                .with_system(update_ghost_system)
+                .into(),
+        );
+    }
+}
+
+/// A part of the preview of the selected snake.
+#[derive(Component)]
+struct GhostPart;
+
+/// The move previewed by the ghost, the index of the snake and the direction.
+#[derive(Default)]
+struct PreviewedMove(Option<(i32, IVec2)>);
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn update_ghost_system(
+    mut commands: Commands,
+    constants: Res<GameConstants>,
+    windows: Res<Windows>,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    level_instance: Res<LevelInstance>,
+    level_template: Res<LevelTemplate>,
+    selected_snake_query: Query<&Snake, (With<SelectedSnake>, With<Active>)>,
+    snakes_query: Query<&Snake, With<Active>>,
+    blocks_query: Query<&Block>,
+    goals_query: Query<&Goal>,
+    animations_query: Query<
+        (),
+        Or<(
+            With<MoveCommand>,
+            With<PushedAnim>,
+            With<GravityFall>,
+            With<LevelExitAnim>,
+        )>,
+    >,
+    ghost_parts_query: Query<Entity, With<GhostPart>>,
+    mut snake_moved_event: EventReader<SnakeMovedEvent>,
+    mut undo_event: EventReader<UndoEvent>,
+    mut previewed_move: Local<PreviewedMove>,
+) {
+    // The level only changes with the moves and the undos, or when a new level is loaded.
+    let level_changed = level_instance.is_added()
+        | snake_moved_event.iter().next().is_some()
+        | undo_event.iter().next().is_some();
+
+    // Only preview from a settled level, the level instance is already in the state after the current move.
+    let next_move = if constants.ghost_preview && animations_query.is_empty() {
+        let (camera, camera_transform) = camera.single();
+        let hovered_position = cursor_grid_position(&windows, camera, camera_transform);
+
+        selected_snake_query
+            .get_single()
+            .ok()
+            .zip(hovered_position)
+            .and_then(|(snake, hovered_position)| {
+                let direction = hovered_position - snake.head_position();
+                [UP, DOWN, LEFT, RIGHT]
+                    .contains(&direction)
+                    .then_some((snake.index(), direction))
+            })
+    } else {
+        None
+    };
+
+    if next_move == previewed_move.0 && !level_changed && !constants.is_changed() {
+        return;
+    }
+    previewed_move.0 = next_move;
+
+    for entity in &ghost_parts_query {
+        commands.entity(entity).despawn();
+    }
+
+    let Some((snake_index, direction)) = next_move else {
+        return;
+    };
+
+    let game = GameLogic::from_level(
+        level_instance.clone(),
+        snakes_query.iter().cloned().collect(),
+        blocks_query.iter().copied().collect(),
+        goals_query.iter().copied().collect(),
+        level_template.fall_out_of_level_limit(),
+    );
+
+    let Some(snake) = game.predict_move(snake_index, direction) else {
+        return;
+    };
+
+    let color = constants.palette.snake_colors(snake_index)[0].with_a(GHOST_ALPHA);
+    for (position, _) in snake.parts() {
+        commands.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color,
+                    custom_size: Some(GRID_CELL_SIZE),
+                    ..default()
+                },
+                transform: Transform {
+                    translation: to_world(*position).extend(1.0),
+                    ..default()
+                },
+                ..default()
+            },
+            GhostPart,
+            LevelEntity,
+        ));
+    }
+}
@@ -1,27 +1,33 @@
-use std::f32::consts::PI;
+use std::{f32::consts::PI, time::Duration};
 
 use bevy::{app::AppExit, prelude::*};
 use bevy_prototype_lyon::{
-    prelude::{DrawMode, FillMode, GeometryBuilder, PathBuilder},
+    entity::ShapeBundle,
+    prelude::{DrawMode, FillMode, GeometryBuilder, PathBuilder, StrokeMode},
     shapes,
 };
 use iyes_loopless::prelude::{ConditionHelpers, IntoConditionalSystem};
 
 use crate::{
+    args::{self, Args},
     gameplay::commands::SnakeCommands,
     gameplay::game_constants_pluggin::{to_world, GRID_CELL_SIZE, GRID_TO_WORLD_UNIT},
-    gameplay::movement_pluggin::{GravityFall, SnakeReachGoalEvent},
-    gameplay::snake_pluggin::{Active, SelectedSnake, Snake, SpawnSnakeEvent},
-    gameplay::undo::SnakeHistory,
+    gameplay::movement_pluggin::{GravityFall, MoveInputBuffer, SnakeReachGoalEvent},
+    gameplay::snake_pluggin::{Active, SelectedSnake, Snake, SnakePart, SpawnSnakeEvent},
+    gameplay::undo::{MoveCount, SnakeHistory, UndoCount},
     level::level_instance::{LevelEntityType, LevelInstance},
-    level::level_template::{Cell, LevelTemplate},
+    level::level_template::{Cell, LevelTemplate, SpikeOrientation},
     level::levels::LEVELS,
     level::test_levels::TEST_LEVELS,
+    menus::{pause_menu::Paused, FONT},
     GameState,
 };
 
 use super::{
-    game_constants_pluggin::{GameConstants, FOOD_COLOR, SPIKE_COLOR},
+    game_constants_pluggin::{
+        snake_colors, GameConstants, KeyBindings, Palette, BACKGROUND_COLOR, BLOCK_COLOR,
+        FOOD_COLOR, GOAL_COLOR, PORTAL_COLOR, SHRINK_FOOD_COLOR, SPIKE_COLOR, WALL_COLOR,
+    },
     movement_pluggin::{LevelExitAnim, SnakeExitedLevelEvent},
 };
 
@@ -29,6 +35,15 @@ pub struct StartLevelEventWithIndex(pub usize);
 pub struct StartTestLevelEventWithIndex(pub usize);
 pub struct StartLevelEventWithLevel(pub String);
 pub struct ClearLevelEvent;
+pub struct RestartLevelEvent;
+
+/// Sent when the last snake exits a level, with the stats of the level.
+pub struct LevelCompletedEvent {
+    pub level_id: usize,
+    pub moves: usize,
+    pub undos: usize,
+    pub elapsed: Duration,
+}
 
 #[derive(Component)]
 pub struct LevelEntity;
@@ -36,23 +51,76 @@ pub struct LevelEntity;
 #[derive(Component, Clone, Copy)]
 pub struct Food(pub IVec2);
 
+/// A fruit shrinking the snake eating it.
+#[derive(Component, Clone, Copy)]
+pub struct ShrinkFood(pub IVec2);
+
+/// A spike and the sides it is lethal from.
 #[derive(Component, Clone, Copy)]
-pub struct Spike(pub IVec2);
+pub struct Spike(pub IVec2, pub SpikeOrientation);
+
+/// A goal, the snake index is set for goals only accepting one snake.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Goal(pub IVec2, pub Option<i32>);
 
+impl Goal {
+    pub fn accepts(&self, snake_index: i32) -> bool {
+        self.1.map_or(true, |index| index == snake_index)
+    }
+}
+
+/// A block that snakes can push, it is subject to gravity.
 #[derive(Component, Clone, Copy)]
-pub struct Goal(pub IVec2);
+pub struct Block(pub IVec2);
+
+/// A one-way wall, it can only be entered moving in its direction.
+#[derive(Component, Clone, Copy)]
+pub struct OneWay(pub IVec2);
+
+/// A conveyor, it carries the snakes resting on it in its direction.
+#[derive(Component, Clone, Copy)]
+pub struct Conveyor(pub IVec2);
+
+/// A portal, snakes entering it emerge at its paired portal.
+#[derive(Component, Clone, Copy)]
+pub struct Portal(pub IVec2);
+
+/// Text showing the move count and the level par.
+#[derive(Component)]
+struct MoveCountText;
 
 #[derive(Resource)]
 pub struct CurrentLevelId(pub usize);
 
+/// The string of the loaded level, restarting loads it again whatever its source.
+#[derive(Resource)]
+pub struct LoadedLevel(pub String);
+
+/// Time since startup when the current level started.
+#[derive(Resource)]
+pub struct LevelStartTime(pub Duration);
+
+/// The levels, loaded from their templates, and the checks for their completion.
 pub struct LevelPluggin;
 
+/// The ground, the shapes, colors and animations of the level entities and the move counter.
+pub struct LevelRenderPluggin;
+
 #[derive(Component, Clone, Copy)]
 pub struct Water;
 
-pub static LOAD_LEVEL_STAGE: &str = "LoadLevelStage";
-static PRE_LOAD_LEVEL_LABEL: &str = "PreloadLevel";
-static CHEK_LEVEL_CONDITION_LABEL: &str = "CheckLevelCondition";
+/// The stage loading the levels, before the pre update stage spawns their entities.
+#[derive(StageLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoadLevelStage;
+
+#[derive(SystemLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LevelSystem {
+    /// Pick the level to load from the start level events.
+    PreLoadLevel,
+    /// Check if the level is completed, before the snakes exit it.
+    CheckLevelCondition,
+}
+const BLOCK_SMOOTHING: f32 = 15.0;
 
 impl Plugin for LevelPluggin {
     fn build(&self, app: &mut App) {
@@ -60,28 +128,30 @@ impl Plugin for LevelPluggin {
             .add_event::<StartTestLevelEventWithIndex>()
             .add_event::<StartLevelEventWithLevel>()
             .add_event::<ClearLevelEvent>()
+            .add_event::<RestartLevelEvent>()
+            .add_event::<LevelCompletedEvent>()
             .add_stage_before(
                 CoreStage::PreUpdate,
-                LOAD_LEVEL_STAGE,
+                LoadLevelStage,
                 SystemStage::single_threaded(),
             )
             .add_system_to_stage(
-                LOAD_LEVEL_STAGE,
+                LoadLevelStage,
                 load_level_with_index_system
                     .run_in_state(GameState::Game)
-                    .label(PRE_LOAD_LEVEL_LABEL),
+                    .label(LevelSystem::PreLoadLevel),
             )
             .add_system_to_stage(
-                LOAD_LEVEL_STAGE,
+                LoadLevelStage,
                 load_test_level_with_index_system
                     .run_in_state(GameState::Game)
-                    .label(PRE_LOAD_LEVEL_LABEL),
+                    .label(LevelSystem::PreLoadLevel),
             )
             .add_system_to_stage(
-                LOAD_LEVEL_STAGE,
+                LoadLevelStage,
                 load_level_system
                     .run_in_state(GameState::Game)
-                    .after(PRE_LOAD_LEVEL_LABEL),
+                    .after(LevelSystem::PreLoadLevel),
             )
             .add_system_to_stage(
                 CoreStage::PreUpdate,
@@ -92,21 +162,21 @@ impl Plugin for LevelPluggin {
                 activate_goal_when_all_food_eaten_system
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>()
-                    .label(CHEK_LEVEL_CONDITION_LABEL),
+                    .label(LevelSystem::CheckLevelCondition),
             )
             .add_system_to_stage(
                 CoreStage::PostUpdate,
                 check_for_level_completion_system
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>()
-                    .label(CHEK_LEVEL_CONDITION_LABEL),
+                    .label(LevelSystem::CheckLevelCondition),
             )
             .add_system_to_stage(
                 CoreStage::PostUpdate,
                 start_snake_exit_level_system
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>()
-                    .after(CHEK_LEVEL_CONDITION_LABEL),
+                    .after(LevelSystem::CheckLevelCondition),
             )
             .add_system_to_stage(
                 CoreStage::PostUpdate,
@@ -118,7 +188,38 @@ impl Plugin for LevelPluggin {
                 CoreStage::Last,
                 clear_level_system.run_in_state(GameState::Game),
             )
-            .add_system(rotate_goal_system.run_in_state(GameState::Game));
+            .add_system(
+                keyboard_restart_level_system
+                    .run_in_state(GameState::Game)
+                    .run_if_resource_exists::<LevelInstance>()
+                    .run_unless_resource_exists::<Paused>(),
+            )
+            .add_system(
+                restart_level_system
+                    .run_in_state(GameState::Game)
+                    .run_if_resource_exists::<LoadedLevel>(),
+            );
+    }
+}
+
+impl Plugin for LevelRenderPluggin {
+    fn build(&self, app: &mut App) {
+        app.add_system_to_stage(
+            CoreStage::PreUpdate,
+            spawn_level_ground_system.run_in_state(GameState::Game),
+        )
+        .add_system(add_level_entity_shapes_system.run_in_state(GameState::Game))
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            apply_palette_system.run_in_state(GameState::Game),
+        )
+        .add_system(rotate_goal_system.run_in_state(GameState::Game))
+        .add_system(block_smooth_movement_system.run_in_state(GameState::Game))
+        .add_system(
+            update_move_count_text_system
+                .run_in_state(GameState::Game)
+                .run_if_resource_exists::<MoveCount>(),
+        );
     }
 }
 
@@ -158,6 +259,7 @@ fn load_test_level_with_index_system(
 
 pub fn load_level_system(
     mut commands: Commands,
+    time: Res<Time>,
     mut event_start_level: EventReader<StartLevelEventWithLevel>,
     mut spawn_snake_event: EventWriter<SpawnSnakeEvent>,
 ) {
@@ -168,6 +270,11 @@ pub fn load_level_system(
     let level = LevelTemplate::parse(&event.0).unwrap();
 
     commands.insert_resource(SnakeHistory::default());
+    commands.insert_resource(MoveCount::default());
+    commands.insert_resource(UndoCount::default());
+    commands.insert_resource(LevelStartTime(time.elapsed()));
+    commands.insert_resource(MoveInputBuffer::default());
+    commands.insert_resource(LoadedLevel(event.0.clone()));
     commands.insert_resource(level);
     commands.insert_resource(LevelInstance::new());
 
@@ -178,49 +285,249 @@ fn spawn_level_entities_system(
     mut commands: Commands,
     mut event_start_level: EventReader<StartLevelEventWithLevel>,
     level_template: Res<LevelTemplate>,
-    game_constants: Res<GameConstants>,
     mut level_instance: ResMut<LevelInstance>,
 ) {
     if event_start_level.iter().next().is_none() {
         return;
     }
 
-    // Spawn the ground sprites
+    level_instance.set_water_level(level_template.water_level);
+    level_instance.set_gravity(level_template.gravity, level_template.grid_size());
+
+    for (position, cell) in level_template.grid.iter() {
+        if cell == Cell::Wall {
+            level_instance.mark_position_occupied(position, LevelEntityType::Wall);
+        }
+    }
+
+    for position in &level_template.food_positions {
+        spawn_food(&mut commands, position, &mut level_instance);
+    }
+
+    for position in &level_template.shrink_food_positions {
+        spawn_shrink_food(&mut commands, position, &mut level_instance);
+    }
+
+    for (position, orientation) in &level_template.spike_positions {
+        spawn_spike(&mut commands, position, *orientation, &mut level_instance);
+    }
+
+    for position in &level_template.block_positions {
+        spawn_block(&mut commands, position, &mut level_instance);
+    }
+
+    for (position, direction) in &level_template.one_way_positions {
+        spawn_one_way(&mut commands, position, direction, &mut level_instance);
+    }
+
+    for (position, direction) in &level_template.conveyor_positions {
+        spawn_conveyor(&mut commands, position, direction, &mut level_instance);
+    }
+
+    for (first, second) in &level_template.portal_pairs {
+        spawn_portal(&mut commands, first);
+        spawn_portal(&mut commands, second);
+        level_instance.add_portal_pair(*first, *second);
+    }
+
+    level_instance.close_goals(&level_template);
+    for (goal_position, snake_index) in &level_template.goals {
+        commands.spawn((
+            Goal(*goal_position, *snake_index),
+            level_entity_transform(goal_position),
+            LevelEntity,
+        ));
+    }
+}
+
+/// Spawn the ground of the level and the move counter, the walls never move.
+fn spawn_level_ground_system(
+    mut commands: Commands,
+    mut event_start_level: EventReader<StartLevelEventWithLevel>,
+    level_template: Res<LevelTemplate>,
+    game_constants: Res<GameConstants>,
+    asset_server: Res<AssetServer>,
+) {
+    if event_start_level.iter().next().is_none() {
+        return;
+    }
+
+    // Spawn the move counter.
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font: asset_server.load(FONT),
+                font_size: 24.0,
+                color: Color::BLACK,
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            position: UiRect {
+                top: Val::Px(8.0),
+                left: Val::Px(8.0),
+                ..default()
+            },
+            ..default()
+        }),
+        MoveCountText,
+        LevelEntity,
+    ));
+
+    // Spawn the ground as a single shape.
+    let mut path_builder = PathBuilder::new();
     for (position, cell) in level_template.grid.iter() {
         if cell != Cell::Wall {
             continue;
         }
 
+        let min = to_world(position) - 0.5 * GRID_CELL_SIZE;
+        let max = min + GRID_CELL_SIZE;
+        path_builder.move_to(min);
+        path_builder.line_to(Vec2::new(max.x, min.y));
+        path_builder.line_to(max);
+        path_builder.line_to(Vec2::new(min.x, max.y));
+        path_builder.close();
+    }
+
+    commands.spawn((
+        GeometryBuilder::build_as(
+            &path_builder.build(),
+            DrawMode::Fill(FillMode::color(game_constants.ground_color)),
+            Transform::default(),
+        ),
+        LevelEntity,
+    ));
+}
+
+/// Add the shapes and sprites of the level entities, when the level spawns them or an undo spawns a food back.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn add_level_entity_shapes_system(
+    mut commands: Commands,
+    food_query: Query<(Entity, &Food), Added<Food>>,
+    shrink_food_query: Query<(Entity, &ShrinkFood), Added<ShrinkFood>>,
+    spike_query: Query<(Entity, &Spike), Added<Spike>>,
+    block_query: Query<(Entity, &Block), Added<Block>>,
+    one_way_query: Query<(Entity, &OneWay, &Transform), Added<OneWay>>,
+    conveyor_query: Query<(Entity, &Conveyor, &Transform), Added<Conveyor>>,
+    portal_query: Query<(Entity, &Portal), Added<Portal>>,
+    goal_query: Query<(Entity, &Goal), Added<Goal>>,
+) {
+    for (entity, food) in &food_query {
+        let shape = shapes::Circle {
+            radius: 0.8 * GRID_TO_WORLD_UNIT / 2.0,
+            ..Default::default()
+        };
+
+        commands.entity(entity).insert(GeometryBuilder::build_as(
+            &shape,
+            DrawMode::Fill(FillMode::color(FOOD_COLOR)),
+            Transform::from_translation(to_world(food.0).extend(0.0)),
+        ));
+    }
+
+    for (entity, shrink_food) in &shrink_food_query {
+        let shape = shapes::Circle {
+            radius: 0.6 * GRID_TO_WORLD_UNIT / 2.0,
+            ..Default::default()
+        };
+
+        commands.entity(entity).insert(GeometryBuilder::build_as(
+            &shape,
+            DrawMode::Fill(FillMode::color(SHRINK_FOOD_COLOR)),
+            Transform::from_translation(to_world(shrink_food.0).extend(0.0)),
+        ));
+    }
+
+    for (entity, spike) in &spike_query {
+        commands.entity(entity).insert(spike_shape(spike));
+    }
+
+    for (entity, block) in &block_query {
+        commands.entity(entity).insert(SpriteBundle {
+            sprite: Sprite {
+                color: BLOCK_COLOR,
+                custom_size: Some(0.9 * GRID_CELL_SIZE),
+                ..default()
+            },
+            transform: Transform::from_translation(to_world(block.0).extend(0.0)),
+            ..default()
+        });
+    }
+
+    // One-way walls are drawn as a bar on the side they block.
+    for (entity, one_way, transform) in &one_way_query {
+        let direction = one_way.0;
+        let size = if direction.x != 0 {
+            Vec2::new(0.2, 1.0)
+        } else {
+            Vec2::new(1.0, 0.2)
+        };
+        let offset = 0.4 * direction.as_vec2() * GRID_TO_WORLD_UNIT;
+
+        commands.entity(entity).insert(SpriteBundle {
+            sprite: Sprite {
+                color: WALL_COLOR,
+                custom_size: Some(size * GRID_CELL_SIZE),
+                ..default()
+            },
+            transform: Transform::from_translation(transform.translation + offset.extend(0.0)),
+            ..default()
+        });
+    }
+
+    // A conveyor is a wall with an arrow pointing in its direction.
+    for (entity, conveyor, transform) in &conveyor_query {
+        let direction = conveyor.0.as_vec2();
+        let side = direction.perp();
+        let arrow = shapes::Polygon {
+            points: vec![
+                0.3 * direction * GRID_TO_WORLD_UNIT,
+                (-0.2 * direction + 0.25 * side) * GRID_TO_WORLD_UNIT,
+                (-0.2 * direction - 0.25 * side) * GRID_TO_WORLD_UNIT,
+            ],
+            closed: true,
+        };
+
         commands
-            .spawn(SpriteBundle {
+            .entity(entity)
+            .insert(SpriteBundle {
                 sprite: Sprite {
-                    color: game_constants.ground_color,
+                    color: WALL_COLOR,
                     custom_size: Some(GRID_CELL_SIZE),
                     ..default()
                 },
-                transform: Transform {
-                    translation: to_world(position).extend(0.0),
-                    ..default()
-                },
+                transform: *transform,
                 ..default()
             })
-            .insert(LevelEntity);
-
-        level_instance.mark_position_occupied(position, LevelEntityType::Wall);
+            .with_children(|parent| {
+                parent.spawn((
+                    GeometryBuilder::build_as(
+                        &arrow,
+                        DrawMode::Fill(FillMode::color(BACKGROUND_COLOR)),
+                        Transform::from_xyz(0.0, 0.0, 0.1),
+                    ),
+                    LevelEntity,
+                ));
+            });
     }
 
-    // Spawn the food sprites.
-    for position in &level_template.food_positions {
-        spawn_food(&mut commands, position, &mut level_instance);
-    }
+    for (entity, portal) in &portal_query {
+        let shape = shapes::Circle {
+            radius: 0.4 * GRID_TO_WORLD_UNIT,
+            ..default()
+        };
 
-    // Spawn the spikes sprites.
-    for position in &level_template.spike_positions {
-        spawn_spike(&mut commands, position, &mut level_instance);
+        commands.entity(entity).insert(GeometryBuilder::build_as(
+            &shape,
+            DrawMode::Stroke(StrokeMode::new(PORTAL_COLOR, 4.0)),
+            Transform::from_translation(to_world(portal.0).extend(0.0)),
+        ));
     }
 
-    // Spawn level goal.
-    {
+    // Goals for a single snake have the color of the snake.
+    for (entity, goal) in &goal_query {
         let mut path_builder = PathBuilder::new();
         let subdivisions = 14;
         for i in 0..subdivisions {
@@ -232,75 +539,246 @@ fn spawn_level_entities_system(
         }
         path_builder.close();
 
-        let path = path_builder.build();
+        let color = match goal.1 {
+            Some(snake_index) => snake_colors(snake_index)[0],
+            None => GOAL_COLOR,
+        };
 
-        let goal_world_position = to_world(level_template.goal_position).extend(-1.0);
-
-        commands.spawn((
-            GeometryBuilder::build_as(
-                &path,
-                DrawMode::Fill(FillMode::color(Color::rgb_u8(250, 227, 25))),
-                Transform {
-                    translation: goal_world_position,
-                    ..default()
-                },
-            ),
-            Goal(level_template.goal_position),
-            LevelEntity,
+        commands.entity(entity).insert(GeometryBuilder::build_as(
+            &path_builder.build(),
+            DrawMode::Fill(FillMode::color(color)),
+            Transform::from_translation(to_world(goal.0).extend(-1.0)),
         ));
     }
 }
 
-pub fn spawn_spike(commands: &mut Commands, position: &IVec2, level_instance: &mut LevelInstance) {
+/// The level entities spawn with the default palette, recolor them when they spawn with another palette
+/// and recolor them all when the palette changes.
+#[allow(clippy::type_complexity)]
+fn apply_palette_system(
+    constants: Res<GameConstants>,
+    mut applied_palette: Local<Palette>,
+    mut shapes_query: Query<(
+        &mut DrawMode,
+        ChangeTrackers<DrawMode>,
+        Option<&Food>,
+        Option<&ShrinkFood>,
+        Option<&Spike>,
+        Option<&Portal>,
+        Option<&SnakePart>,
+        Option<&Goal>,
+    )>,
+    mut blocks_query: Query<(&mut Sprite, ChangeTrackers<Sprite>), With<Block>>,
+) {
+    let palette = constants.palette;
+    let recolor_all = *applied_palette != palette;
+    if !recolor_all && palette == Palette::Default {
+        return;
+    }
+    *applied_palette = palette;
+
+    let colors = palette.colors();
+    let food_mode = |color: Color| {
+        if colors.outline_food {
+            DrawMode::Outlined {
+                fill_mode: FillMode::color(color),
+                outline_mode: StrokeMode::new(Color::BLACK, 2.0),
+            }
+        } else {
+            DrawMode::Fill(FillMode::color(color))
+        }
+    };
+
+    for (mut draw_mode, tracker, food, shrink_food, spike, portal, part, goal) in &mut shapes_query
+    {
+        if !recolor_all && !tracker.is_added() {
+            continue;
+        }
+
+        if food.is_some() {
+            *draw_mode = food_mode(colors.food);
+        } else if shrink_food.is_some() {
+            *draw_mode = food_mode(colors.shrink_food);
+        } else if spike.is_some() {
+            *draw_mode = DrawMode::Fill(FillMode::color(colors.spike));
+        } else if portal.is_some() {
+            *draw_mode = DrawMode::Stroke(StrokeMode::new(colors.portal, 4.0));
+        } else if let Some(part) = part {
+            let color = palette.snake_colors(part.snake_index)[part.part_index % 2];
+            *draw_mode = DrawMode::Fill(FillMode::color(color));
+        } else if let Some(snake_index) = goal.and_then(|goal| goal.1) {
+            *draw_mode = DrawMode::Fill(FillMode::color(palette.snake_colors(snake_index)[0]));
+        }
+    }
+
+    for (mut sprite, tracker) in &mut blocks_query {
+        if recolor_all || tracker.is_added() {
+            sprite.color = colors.block;
+        }
+    }
+}
+
+fn spike_shape(spike: &Spike) -> ShapeBundle {
     let mut path_builder = PathBuilder::new();
-    let subdivisions = 8;
-    for i in 0..subdivisions {
-        let angle = 2.0 * PI * i as f32 / (subdivisions as f32);
-        let position = Vec2::new(angle.cos(), angle.sin());
-        let offset = 0.5 + (i % 2) as f32;
-        let radius = 0.3 * GRID_TO_WORLD_UNIT * offset;
-        path_builder.line_to(radius * position);
+    let mut rotation = Quat::IDENTITY;
+    match spike.1 {
+        // A star for the spikes lethal from every side.
+        SpikeOrientation::Omnidirectional => {
+            let subdivisions = 8;
+            for i in 0..subdivisions {
+                let angle = 2.0 * PI * i as f32 / (subdivisions as f32);
+                let position = Vec2::new(angle.cos(), angle.sin());
+                let offset = 0.5 + (i % 2) as f32;
+                let radius = 0.3 * GRID_TO_WORLD_UNIT * offset;
+                path_builder.line_to(radius * position);
+            }
+        }
+        // A row of teeth on the side of the cell, built pointing up and rotated.
+        SpikeOrientation::Pointing(direction) => {
+            let teeth = 3;
+            let half_size = 0.5 * GRID_TO_WORLD_UNIT;
+            let tooth_width = 2.0 * half_size / teeth as f32;
+            path_builder.move_to(Vec2::new(-half_size, -half_size));
+            for i in 0..teeth {
+                let x = -half_size + tooth_width * i as f32;
+                path_builder.line_to(Vec2::new(x + 0.5 * tooth_width, 0.8 * half_size));
+                path_builder.line_to(Vec2::new(x + tooth_width, -half_size));
+            }
+            rotation = Quat::from_rotation_z(Vec2::Y.angle_between(direction.as_vec2()));
+        }
     }
     path_builder.close();
 
-    let path = path_builder.build();
+    GeometryBuilder::build_as(
+        &path_builder.build(),
+        DrawMode::Fill(FillMode::color(SPIKE_COLOR)),
+        Transform {
+            translation: to_world(spike.0).extend(0.0),
+            rotation,
+            ..default()
+        },
+    )
+}
 
-    commands
-        .spawn(GeometryBuilder::build_as(
-            &path,
-            DrawMode::Fill(FillMode::color(SPIKE_COLOR)),
-            Transform {
-                translation: to_world(*position).extend(0.0),
-                ..default()
-            },
-        ))
-        .insert(Spike(*position))
-        .insert(LevelEntity);
+/// The transform of a level entity at its grid position, the render plugin adds the shape of the entity.
+fn level_entity_transform(position: &IVec2) -> TransformBundle {
+    TransformBundle::from_transform(Transform::from_translation(to_world(*position).extend(0.0)))
+}
 
-    level_instance.mark_position_occupied(*position, LevelEntityType::Spike);
+pub fn spawn_spike(
+    commands: &mut Commands,
+    position: &IVec2,
+    orientation: SpikeOrientation,
+    level_instance: &mut LevelInstance,
+) {
+    commands.spawn((
+        Spike(*position, orientation),
+        level_entity_transform(position),
+        LevelEntity,
+    ));
+
+    level_instance.mark_position_occupied(*position, LevelEntityType::Spike(orientation));
 }
 
 pub fn spawn_food(commands: &mut Commands, position: &IVec2, level_instance: &mut LevelInstance) {
-    let shape = shapes::Circle {
-        radius: 0.8 * GRID_TO_WORLD_UNIT / 2.0,
-        ..Default::default()
-    };
-
-    commands
-        .spawn(GeometryBuilder::build_as(
-            &shape,
-            DrawMode::Fill(FillMode::color(FOOD_COLOR)),
-            Transform {
-                translation: to_world(*position).extend(0.0),
-                ..default()
-            },
-        ))
-        .insert(Food(*position))
-        .insert(LevelEntity);
+    commands.spawn((
+        Food(*position),
+        level_entity_transform(position),
+        LevelEntity,
+    ));
 
     level_instance.mark_position_occupied(*position, LevelEntityType::Food);
 }
 
+pub fn spawn_shrink_food(
+    commands: &mut Commands,
+    position: &IVec2,
+    level_instance: &mut LevelInstance,
+) {
+    commands.spawn((
+        ShrinkFood(*position),
+        level_entity_transform(position),
+        LevelEntity,
+    ));
+
+    level_instance.mark_position_occupied(*position, LevelEntityType::ShrinkFood);
+}
+
+pub fn spawn_block(commands: &mut Commands, position: &IVec2, level_instance: &mut LevelInstance) {
+    commands.spawn((
+        Block(*position),
+        level_entity_transform(position),
+        LevelEntity,
+    ));
+
+    level_instance.mark_position_occupied(*position, LevelEntityType::Block);
+}
+
+pub fn spawn_one_way(
+    commands: &mut Commands,
+    position: &IVec2,
+    direction: &IVec2,
+    level_instance: &mut LevelInstance,
+) {
+    commands.spawn((
+        OneWay(*direction),
+        level_entity_transform(position),
+        LevelEntity,
+    ));
+
+    level_instance.add_one_way(*position, *direction);
+}
+
+pub fn spawn_conveyor(
+    commands: &mut Commands,
+    position: &IVec2,
+    direction: &IVec2,
+    level_instance: &mut LevelInstance,
+) {
+    commands.spawn((
+        Conveyor(*direction),
+        level_entity_transform(position),
+        LevelEntity,
+    ));
+
+    level_instance.mark_position_occupied(*position, LevelEntityType::Conveyor(*direction));
+}
+
+pub fn spawn_portal(commands: &mut Commands, position: &IVec2) {
+    commands.spawn((
+        Portal(*position),
+        level_entity_transform(position),
+        LevelEntity,
+    ));
+}
+
+/// Blocks move instantly on the grid, their sprite catches up with their grid position.
+fn block_smooth_movement_system(
+    time: Res<Time>,
+    mut blocks_query: Query<(&Block, &mut Transform)>,
+) {
+    let factor = (BLOCK_SMOOTHING * time.delta_seconds()).min(1.0);
+    for (block, mut transform) in blocks_query.iter_mut() {
+        let target = to_world(block.0).extend(transform.translation.z);
+        transform.translation = transform.translation.lerp(target, factor);
+    }
+}
+
+fn update_move_count_text_system(
+    move_count: Res<MoveCount>,
+    level_template: Res<LevelTemplate>,
+    mut text_query: Query<&mut Text, With<MoveCountText>>,
+) {
+    let Ok(mut text) = text_query.get_single_mut() else {
+        return;
+    };
+
+    text.sections[0].value = match level_template.par {
+        Some(par) => format!("Moves: {} / {}", move_count.0, par),
+        None => format!("Moves: {}", move_count.0),
+    };
+}
+
 pub fn clear_level_system(
     mut event_clear_level: EventReader<ClearLevelEvent>,
     mut commands: Commands,
@@ -316,6 +794,35 @@ pub fn clear_level_system(
 
     commands.remove_resource::<LevelInstance>();
     commands.remove_resource::<SnakeHistory>();
+    commands.remove_resource::<MoveCount>();
+    commands.remove_resource::<UndoCount>();
+    commands.remove_resource::<LevelStartTime>();
+    commands.insert_resource(MoveInputBuffer::default());
+}
+
+fn keyboard_restart_level_system(
+    keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut event_restart_level: EventWriter<RestartLevelEvent>,
+) {
+    if keyboard.any_just_pressed(key_bindings.restart.iter().copied()) {
+        event_restart_level.send(RestartLevelEvent);
+    }
+}
+
+/// Clears the level and loads it again, the history is reset by the load.
+fn restart_level_system(
+    loaded_level: Res<LoadedLevel>,
+    mut event_restart_level: EventReader<RestartLevelEvent>,
+    mut event_clear_level: EventWriter<ClearLevelEvent>,
+    mut event_start_level: EventWriter<StartLevelEventWithLevel>,
+) {
+    if event_restart_level.iter().next().is_none() {
+        return;
+    }
+
+    event_clear_level.send(ClearLevelEvent);
+    event_start_level.send(StartLevelEventWithLevel(loaded_level.0.clone()));
 }
 
 fn activate_goal_when_all_food_eaten_system(
@@ -323,16 +830,14 @@ fn activate_goal_when_all_food_eaten_system(
     food_query: Query<&Food>,
     goal_query: Query<(Entity, Option<&Active>), With<Goal>>,
 ) {
-    let Ok((goal_entity, active)) = goal_query.get_single() else {
-        return;
-    };
-
-    if food_query.is_empty() {
-        if active.is_none() {
-            commands.entity(goal_entity).insert(Active);
+    for (goal_entity, active) in &goal_query {
+        if food_query.is_empty() {
+            if active.is_none() {
+                commands.entity(goal_entity).insert(Active);
+            }
+        } else if active.is_some() {
+            commands.entity(goal_entity).remove::<Active>();
         }
-    } else if active.is_some() {
-        commands.entity(goal_entity).remove::<Active>();
     }
 }
 
@@ -340,16 +845,14 @@ fn rotate_goal_system(
     time: Res<Time>,
     mut goal_query: Query<(&mut Transform, Option<&Active>), With<Goal>>,
 ) {
-    let Ok((mut transform, active)) = goal_query.get_single_mut() else {
-        return;
-    };
-
-    if active.is_some() {
-        transform.rotate_local_z(time.delta_seconds() * 0.7);
-        transform.scale = (1.6 + 0.3 * (time.elapsed_seconds() * 1.0).sin()) * Vec3::ONE;
-    } else {
-        transform.rotate_local_z(time.delta_seconds() * 0.3);
-        transform.scale = Vec3::ONE;
+    for (mut transform, active) in &mut goal_query {
+        if active.is_some() {
+            transform.rotate_local_z(time.delta_seconds() * 0.7);
+            transform.scale = (1.6 + 0.3 * (time.elapsed_seconds() * 1.0).sin()) * Vec3::ONE;
+        } else {
+            transform.rotate_local_z(time.delta_seconds() * 0.3);
+            transform.scale = Vec3::ONE;
+        }
     }
 }
 
@@ -359,13 +862,11 @@ pub fn check_for_level_completion_system(
     snakes_query: Query<(Entity, &Snake), (With<Active>, Without<LevelExitAnim>)>,
     goal_query: Query<&Goal, With<Active>>,
 ) {
-    let Ok(goal) = goal_query.get_single() else {
-        return;
-    };
-
-    let snake_at_exit = snakes_query
-        .iter()
-        .find(|(_, snake)| goal.0 == snake.head_position());
+    let snake_at_exit = snakes_query.iter().find(|(_, snake)| {
+        goal_query
+            .iter()
+            .any(|goal| goal.0 == snake.head_position() && goal.accepts(snake.index()))
+    });
     if snake_at_exit.is_none() {
         return;
     }
@@ -394,8 +895,7 @@ pub fn start_snake_exit_level_system(
             .remove::<SelectedSnake>()
             .remove::<GravityFall>();
 
-        SnakeCommands::new(level_instance.as_mut(), history.as_mut())
-            .exit_level(snake, entity, gravity);
+        SnakeCommands::new(level_instance.as_mut(), history.as_mut()).exit_level(snake, gravity);
 
         // Select another snake if the snake was selected.
         if selected_snake.is_some() {
@@ -418,19 +918,44 @@ pub fn start_snake_exit_level_system(
     snake_reach_goal_event.clear();
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn finish_snake_exit_level_system(
+    time: Res<Time>,
     level_id: Res<CurrentLevelId>,
+    move_count: Res<MoveCount>,
+    undo_count: Res<UndoCount>,
+    level_start_time: Res<LevelStartTime>,
+    level_template: Res<LevelTemplate>,
     snake_reach_goal_event: EventReader<SnakeExitedLevelEvent>,
+    mut event_level_completed: EventWriter<LevelCompletedEvent>,
     mut event_start_level: EventWriter<StartLevelEventWithIndex>,
     mut event_clear_level: EventWriter<ClearLevelEvent>,
     mut exit: EventWriter<AppExit>,
+    args: Res<Args>,
     snakes_query: Query<&Snake, With<Active>>,
 ) {
     if snake_reach_goal_event.is_empty() {
         return;
     }
 
-    if snakes_query.is_empty() {
+    let snake_count = level_template.initial_snakes.len();
+    let exited_count = snake_count - snakes_query.iter().count();
+    if level_template
+        .goal_condition
+        .is_met(exited_count, snake_count)
+    {
+        event_level_completed.send(LevelCompletedEvent {
+            level_id: level_id.0,
+            moves: move_count.0,
+            undos: undo_count.0,
+            elapsed: time.elapsed() - level_start_time.0,
+        });
+
+        // The automated tests start the next test case themselves.
+        if matches!(args.command, Some(args::Commands::Test { .. })) {
+            return;
+        }
+
         if level_id.0 == LEVELS.len() - 1 {
             exit.send(AppExit);
         } else {
@@ -439,3 +964,33 @@ pub fn finish_snake_exit_level_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::event::Events;
+
+    use super::*;
+
+    #[test]
+    pub fn test_restart_loads_the_loaded_level() {
+        // A test level loaded with the id of another level of the game.
+        let mut app = App::new();
+        app.add_event::<RestartLevelEvent>()
+            .add_event::<ClearLevelEvent>()
+            .add_event::<StartLevelEventWithLevel>()
+            .insert_resource(CurrentLevelId(0))
+            .insert_resource(LoadedLevel(TEST_LEVELS[1].to_owned()))
+            .add_system(restart_level_system);
+
+        app.world.send_event(RestartLevelEvent);
+        app.update();
+
+        let events = app.world.resource::<Events<StartLevelEventWithLevel>>();
+        let levels: Vec<&str> = events
+            .iter_current_update_events()
+            .map(|event| event.0.as_str())
+            .collect();
+        assert_eq!(levels, vec![TEST_LEVELS[1]]);
+        assert!(!app.world.resource::<Events<ClearLevelEvent>>().is_empty());
+    }
+}
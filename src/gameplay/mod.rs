@@ -1,7 +1,11 @@
 pub mod camera_plugin;
 pub mod commands;
 pub mod game_constants_pluggin;
+pub mod game_logic;
+pub mod ghost_pluggin;
 pub mod level_pluggin;
 pub mod movement_pluggin;
+pub mod save_pluggin;
 pub mod snake_pluggin;
+pub mod solver;
 pub mod undo;
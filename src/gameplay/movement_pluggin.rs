@@ -1,20 +1,23 @@
-use bevy::prelude::*;
+use bevy::{math::Vec2Swizzles, prelude::*};
 use bevy_kira_audio::{Audio, AudioControl};
 use bevy_tweening::{
-    component_animator_system, AnimationSystem, Animator, EaseFunction, Lens, Tween,
+    lens::TransformScaleLens, Animator, EaseFunction, Lens, Tween, TweenCompleted,
 };
 use iyes_loopless::prelude::{ConditionSet, IntoConditionalSystem};
 use rand::prelude::*;
 
 use crate::{
-    gameplay::commands::SnakeCommands,
     gameplay::game_constants_pluggin::*,
-    gameplay::level_pluggin::Food,
-    gameplay::snake_pluggin::{
-        respawn_snake_on_fall_system, Active, SelectedSnake, Snake, SpawnSnakeEvent,
+    gameplay::game_logic::{self, FallOutcome, MoveOutcome},
+    gameplay::level_pluggin::{Block, Food, ShrinkFood},
+    gameplay::snake_pluggin::{Active, SelectedSnake, Snake, SpawnSnakeEvent},
+    gameplay::undo::{
+        keyboard_undo_system, player_undo_system, redo_event_system, undo_event_system,
+        undo_to_move_event_system, MoveCount, PlayerUndoEvent, RedoEvent, SnakeHistory, UndoEvent,
+        UndoToMoveEvent,
     },
-    gameplay::undo::{keyboard_undo_system, undo_event_system, SnakeHistory, UndoEvent},
     level::{level_instance::LevelInstance, level_template::LevelTemplate},
+    menus::pause_menu::Paused,
     Assets, GameState,
 };
 
@@ -23,11 +26,6 @@ use super::{
     snake_pluggin::{DespawnSnakePartEvent, PartClipper, SnakeEye, SnakePart, SnakePartBundle},
 };
 
-const MOVE_UP_KEYS: [KeyCode; 2] = [KeyCode::W, KeyCode::Up];
-const MOVE_LEFT_KEYS: [KeyCode; 2] = [KeyCode::A, KeyCode::Left];
-const MOVE_DOWN_KEYS: [KeyCode; 2] = [KeyCode::S, KeyCode::Down];
-const MOVE_RIGHT_KEYS: [KeyCode; 2] = [KeyCode::D, KeyCode::Right];
-
 #[derive(Component, Default)]
 pub struct MoveCommand {
     velocity: f32,
@@ -41,6 +39,7 @@ pub struct PushedAnim {
     pub lerp_time: f32,
 }
 
+/// A falling snake, `relative_y` is the offset of the snake from its grid position against the gravity.
 #[derive(Component, Copy, Clone)]
 pub struct GravityFall {
     velocity: f32,
@@ -67,110 +66,176 @@ impl Lens<PartGrowAnim> for GrowPartLens {
     }
 }
 
+/// Food eaten by a snake, it has left the game and is despawned once its shrink animation completes.
+#[derive(Component)]
+pub struct EatenFoodAnim;
+
+const EATEN_FOOD_TWEEN_COMPLETED: u64 = 1;
+
+/// Falls of up to this number of cells land without bouncing.
+const LANDING_BOUNCE_MIN_DISTANCE: i32 = 1;
+/// The squash of the snake per cell fallen past the minimum distance, and the maximum squash.
+const LANDING_BOUNCE_STRENGTH_PER_CELL: f32 = 0.06;
+const LANDING_BOUNCE_MAX_STRENGTH: f32 = 0.3;
+
+/// The moves of the snakes, their falls and the undos, driven by the move and undo events.
 pub struct MovementPluggin;
 
+/// The keyboard controls of the moves and the undos, they send the events played by the movement plugin.
+pub struct MovementInputPluggin;
+
 pub struct MoveCommandEvent(pub IVec2);
 
+/// The last move requested while the selected snake was busy moving or falling.
+/// It is applied as soon as the snake becomes idle, only one move is kept.
+#[derive(Resource, Default)]
+pub struct MoveInputBuffer(pub Option<IVec2>);
+
 pub struct SnakeMovedEvent;
 
+/// A food or shrink food was eaten at the position.
+pub struct FoodEatenEvent(pub IVec2);
+
 pub struct SnakeReachGoalEvent(pub Entity);
 
 pub struct SnakeExitedLevelEvent;
 
-const KEYBOARD_INPUT: &str = "KEYBOARD_INPUT";
-const UNDO: &str = "UNDO";
-const SNAKE_MOVEMENT: &str = "SNAKE_MOVEMENT";
-const SNAKE_GROW: &str = "SNAKE_GROW";
-const SNAKE_FALL: &str = "SNAKE_FALL";
-const SMOOTH_MOVEMENT: &str = "SMOOTH_MOVEMENT";
+/// The movement systems of the update stage, in the order they run.
+#[derive(SystemLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MovementSystem {
+    KeyboardInput,
+    Undo,
+    Conveyor,
+    SnakeMovement,
+    SnakeGrow,
+    SnakeFall,
+    SmoothMovement,
+}
 
 impl Plugin for MovementPluggin {
     fn build(&self, app: &mut App) {
-        app.add_event::<SpawnSnakeEvent>()
-            .add_event::<SnakeMovedEvent>()
+        app.add_event::<SnakeMovedEvent>()
+            .add_event::<FoodEatenEvent>()
             .add_event::<MoveCommandEvent>()
+            .init_resource::<MoveInputBuffer>()
             .add_event::<SnakeReachGoalEvent>()
             .add_event::<SnakeExitedLevelEvent>()
-            .add_event::<crate::gameplay::undo::UndoEvent>()
+            .add_event::<UndoEvent>()
+            .add_event::<PlayerUndoEvent>()
+            .add_event::<RedoEvent>()
+            .add_event::<UndoToMoveEvent>()
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>()
-                    .label(KEYBOARD_INPUT)
-                    .with_system(keyboard_undo_system)
-                    .with_system(keyboard_move_command_system)
+                    .run_unless_resource_exists::<Paused>()
+                    .label(MovementSystem::KeyboardInput)
+                    .with_system(undo_to_move_event_system)
+                    .with_system(player_undo_system)
                     .into(),
             )
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>()
-                    .label(UNDO)
-                    .after(KEYBOARD_INPUT)
+                    .label(MovementSystem::Undo)
+                    .after(MovementSystem::KeyboardInput)
                     .with_system(undo_event_system)
+                    .with_system(redo_event_system)
                     .into(),
             )
+            .add_system(
+                conveyor_system
+                    .run_in_state(GameState::Game)
+                    .run_if_resource_exists::<LevelInstance>()
+                    .label(MovementSystem::Conveyor)
+                    .after(MovementSystem::Undo),
+            )
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>()
-                    .label(SNAKE_MOVEMENT)
-                    .after(UNDO)
+                    .label(MovementSystem::SnakeMovement)
+                    .after(MovementSystem::Conveyor)
                     .with_system(snake_movement_control_system)
                     .into(),
             )
             .add_system(
                 grow_snake_on_move_system
                     .run_in_state(GameState::Game)
-                    .label(SNAKE_GROW)
-                    .after(SNAKE_MOVEMENT),
+                    .label(MovementSystem::SnakeGrow)
+                    .after(MovementSystem::SnakeMovement),
+            )
+            .add_system(
+                shrink_snake_on_move_system
+                    .run_in_state(GameState::Game)
+                    .label(MovementSystem::SnakeGrow)
+                    .after(MovementSystem::SnakeMovement),
             )
             .add_system(
                 gravity_system
                     .run_in_state(GameState::Game)
-                    .label(SNAKE_FALL)
-                    .after(SNAKE_GROW),
+                    .label(MovementSystem::SnakeFall)
+                    .after(MovementSystem::SnakeGrow),
+            )
+            .add_system(
+                block_gravity_system
+                    .run_in_state(GameState::Game)
+                    .run_if_resource_exists::<LevelInstance>()
+                    .after(MovementSystem::SnakeFall),
             )
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>()
-                    .label(SMOOTH_MOVEMENT)
-                    .after(SNAKE_FALL)
+                    .label(MovementSystem::SmoothMovement)
+                    .after(MovementSystem::SnakeFall)
                     .with_system(snake_smooth_movement_system)
                     .with_system(snake_push_anim_system)
                     .with_system(snake_exit_level_anim_system)
-                    .with_system(respawn_snake_on_fall_system)
                     .into(),
             )
-            .add_system(
-                component_animator_system::<PartGrowAnim>
+            .add_system(despawn_eaten_food_system.run_in_state(GameState::Game));
+
+        // The commands of the moves and undos are applied at the end of the update stage.
+        if cfg!(debug_assertions) {
+            app.add_system_to_stage(
+                CoreStage::PostUpdate,
+                validate_level_system
                     .run_in_state(GameState::Game)
-                    .label(AnimationSystem::AnimationUpdate),
+                    .run_if_resource_exists::<LevelInstance>(),
             );
+        }
     }
 }
 
-fn min_distance_to_ground(level: &LevelInstance, snake: &Snake) -> i32 {
-    snake
-        .parts()
-        .iter()
-        .map(|(position, _)| level.get_distance_to_ground(*position, snake.index()))
-        .min()
-        .unwrap()
+impl Plugin for MovementInputPluggin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            ConditionSet::new()
+                .run_in_state(GameState::Game)
+                .run_if_resource_exists::<LevelInstance>()
+                .run_unless_resource_exists::<Paused>()
+                .label(MovementSystem::KeyboardInput)
+                .with_system(keyboard_undo_system)
+                .with_system(keyboard_move_command_system)
+                .into(),
+        );
+    }
 }
 
 pub fn keyboard_move_command_system(
     keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
     mut move_command_event: EventWriter<MoveCommandEvent>,
 ) {
-    let new_direction = if keyboard.any_just_pressed(MOVE_UP_KEYS) {
+    let new_direction = if keyboard.any_just_pressed(key_bindings.up.iter().copied()) {
         Some(UP)
-    } else if keyboard.any_just_pressed(MOVE_LEFT_KEYS) {
+    } else if keyboard.any_just_pressed(key_bindings.left.iter().copied()) {
         Some(LEFT)
-    } else if keyboard.any_just_pressed(MOVE_DOWN_KEYS) {
+    } else if keyboard.any_just_pressed(key_bindings.down.iter().copied()) {
         Some(DOWN)
-    } else if keyboard.any_just_pressed(MOVE_RIGHT_KEYS) {
+    } else if keyboard.any_just_pressed(key_bindings.right.iter().copied()) {
         Some(RIGHT)
     } else {
         None
@@ -192,115 +257,214 @@ type WithMovementControlSystemFilter = (
 
 #[allow(clippy::too_many_arguments)]
 pub fn snake_movement_control_system(
-    assets: Res<Assets>,
-    audio: Res<Audio>,
     mut level_instance: ResMut<LevelInstance>,
     constants: Res<GameConstants>,
     mut snake_history: ResMut<SnakeHistory>,
+    mut move_count: ResMut<MoveCount>,
     mut move_command_event: EventReader<MoveCommandEvent>,
+    mut move_input_buffer: ResMut<MoveInputBuffer>,
     mut snake_reach_goal_event: EventWriter<SnakeReachGoalEvent>,
     mut commands: Commands,
     mut snake_moved_event: EventWriter<SnakeMovedEvent>,
     mut selected_snake_query: Query<(Entity, &mut Snake), WithMovementControlSystemFilter>,
     mut other_snakes_query: Query<(Entity, &mut Snake), Without<SelectedSnake>>,
-    foods_query: Query<&Food>,
+    mut blocks_query: Query<&mut Block>,
     goal_query: Query<&Goal, With<Active>>,
 ) {
+    let new_direction = move_command_event.iter().last().map(|event| event.0);
+
     let Ok((snake_entity, mut snake)) = selected_snake_query.get_single_mut() else {
+        // The snake is busy, keep the move for when it becomes idle.
+        if new_direction.is_some() {
+            move_input_buffer.0 = new_direction;
+        }
         return;
     };
 
-    let Some(MoveCommandEvent(direction)) = move_command_event.iter().next() else {
+    let buffered_direction = move_input_buffer.0.take();
+    let Some(direction) = new_direction.or(buffered_direction) else {
         return;
     };
 
-    let new_position = snake.head_position() + *direction;
-
-    // Check that we have enough parts to go up.
-    let is_goal = if let Ok(goal) = goal_query.get_single() {
-        goal.0 == new_position
-    } else {
-        false
+    let mut other_snakes: Vec<(Entity, Mut<Snake>)> = other_snakes_query.iter_mut().collect();
+    let mut other_snake_refs: Vec<&mut Snake> = other_snakes
+        .iter_mut()
+        .map(|(_, other_snake)| other_snake.as_mut())
+        .collect();
+
+    let mut blocks: Vec<Mut<Block>> = blocks_query.iter_mut().collect();
+    let mut block_refs: Vec<&mut Block> = blocks.iter_mut().map(|block| block.as_mut()).collect();
+
+    let goals: Vec<Goal> = goal_query.iter().copied().collect();
+
+    let outcome = game_logic::player_move(
+        &mut level_instance,
+        &mut snake_history,
+        snake.as_mut(),
+        &mut other_snake_refs,
+        &mut block_refs,
+        &goals,
+        direction,
+    );
+
+    let (pushed_snakes, reached_goal) = match outcome {
+        MoveOutcome::Blocked => return,
+        MoveOutcome::Jumped => {
+            commands.entity(snake_entity).insert(GravityFall {
+                velocity: constants.jump_velocity,
+                relative_y: 0.0,
+                grid_distance: 0,
+            });
+            return;
+        }
+        MoveOutcome::Moved {
+            pushed_snakes,
+            reached_goal,
+        } => (pushed_snakes, reached_goal),
     };
 
-    if *direction == IVec2::Y
-        && snake.is_standing()
-        && !level_instance.is_food(new_position)
-        && !is_goal
-    {
-        commands.entity(snake_entity).insert(GravityFall {
-            velocity: constants.jump_velocity,
-            relative_y: 0.0,
-            grid_distance: 0,
-        });
-        return;
-    }
+    move_count.0 += 1;
 
-    // Check for collition with self and walls.
-    if snake.occupies_position(new_position) || level_instance.is_wall_or_spike(new_position) {
-        return;
+    if reached_goal {
+        snake_reach_goal_event.send(SnakeReachGoalEvent(snake_entity));
     }
 
-    // Find if there is a snake in the way.
-    let (other_snake_entity, mut other_snake) = level_instance
-        .is_snake(new_position)
-        .and_then(|other_snake_id| {
-            other_snakes_query
-                .iter_mut()
-                .find(|(_, snake)| snake.index() == other_snake_id)
-        })
-        .unzip();
-
-    if let Some(other_snake) = &mut other_snake {
-        if !level_instance.can_push_snake(other_snake.as_ref(), *direction) {
-            return;
-        }
-    };
+    snake_moved_event.send(SnakeMovedEvent);
 
-    let other_snake = other_snake.as_mut().map(|some| some.as_mut());
+    // Smooth move animation starts, instant moves start at the end of the animation.
+    let lerp_time = if constants.instant_moves { 1.0 } else { 0.0 };
+    commands.entity(snake_entity).insert(MoveCommand {
+        velocity: constants.move_velocity,
+        lerp_time,
+    });
 
-    // Any food?
-    let food = foods_query.iter().find(|food| food.0 == new_position);
+    let pushed_snake_entities = other_snakes
+        .iter()
+        .filter(|(_, other_snake)| pushed_snakes.contains(&other_snake.index()))
+        .map(|(entity, _)| *entity);
 
-    // Finaly move the snake forward and commit the state.
-    let mut snake_commands = SnakeCommands::new(&mut level_instance, &mut snake_history);
+    for pushed_snake_entity in pushed_snake_entities {
+        commands.entity(pushed_snake_entity).insert(PushedAnim {
+            direction: direction.as_vec2(),
+            velocity: constants.move_velocity,
+            lerp_time,
+        });
+    }
+}
 
-    snake_commands
-        .player_move(snake.as_mut(), *direction)
-        .pushing_snake(other_snake)
-        .eating_food(food)
-        .execute();
+/// Once the snakes settle after a player move, the conveyors carry the snakes resting on them.
+/// The conveyors are not applied if the move is undone before the snakes settle.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn conveyor_system(
+    constants: Res<GameConstants>,
+    move_count: Res<MoveCount>,
+    mut level: ResMut<LevelInstance>,
+    mut snake_history: ResMut<SnakeHistory>,
+    mut snake_moved_event: EventReader<SnakeMovedEvent>,
+    mut undo_event: EventReader<UndoEvent>,
+    mut commands: Commands,
+    mut snakes_query: Query<(Entity, &mut Snake), (With<Active>, Without<LevelExitAnim>)>,
+    mut blocks_query: Query<&mut Block>,
+    animations_query: Query<
+        (),
+        Or<(
+            With<MoveCommand>,
+            With<PushedAnim>,
+            With<GravityFall>,
+            With<LevelExitAnim>,
+        )>,
+    >,
+    mut pending_conveyors: Local<bool>,
+) {
+    // A new level starts without pending conveyors.
+    if move_count.is_added() {
+        *pending_conveyors = false;
+    }
+    if snake_moved_event.iter().next().is_some() {
+        *pending_conveyors = true;
+    }
+    if undo_event.iter().next().is_some() {
+        *pending_conveyors = false;
+    }
 
-    if let Ok(goal) = goal_query.get_single() {
-        if snake.head_position() == goal.0 {
-            snake_reach_goal_event.send(SnakeReachGoalEvent(snake_entity));
-        }
+    if !*pending_conveyors || !animations_query.is_empty() {
+        return;
     }
+    *pending_conveyors = false;
 
-    snake_moved_event.send(SnakeMovedEvent);
+    let mut snakes: Vec<(Entity, Mut<Snake>)> = snakes_query.iter_mut().collect();
+    let mut snake_refs: Vec<&mut Snake> =
+        snakes.iter_mut().map(|(_, snake)| snake.as_mut()).collect();
 
-    // Smooth move animation starts.
-    commands.entity(snake_entity).insert(MoveCommand {
-        velocity: constants.move_velocity,
-        lerp_time: 0.0,
-    });
+    let mut blocks: Vec<Mut<Block>> = blocks_query.iter_mut().collect();
+    let mut block_refs: Vec<&mut Block> = blocks.iter_mut().map(|block| block.as_mut()).collect();
+
+    let moved_snakes = game_logic::apply_conveyors(
+        &mut level,
+        &mut snake_history,
+        &mut snake_refs,
+        &mut block_refs,
+    );
+
+    let lerp_time = if constants.instant_moves { 1.0 } else { 0.0 };
+    for (snake_index, direction) in moved_snakes {
+        let Some((snake_entity, _)) = snakes
+            .iter()
+            .find(|(_, snake)| snake.index() == snake_index)
+        else {
+            continue;
+        };
 
-    if let Some(other_snake_entity) = other_snake_entity {
-        commands.entity(other_snake_entity).insert(PushedAnim {
+        commands.entity(*snake_entity).insert(PushedAnim {
             direction: direction.as_vec2(),
             velocity: constants.move_velocity,
-            lerp_time: 0.0,
+            lerp_time,
         });
     }
+}
 
-    audio
-        .play(assets.move_effect_2.clone())
-        .with_playback_rate(1.0 + rand::thread_rng().gen_range(-0.05..0.1))
-        .with_volume(2.0);
+pub fn sound_effects_system(
+    assets: Res<Assets>,
+    audio: Res<Audio>,
+    constants: Res<GameConstants>,
+    mut snake_moved_event: EventReader<SnakeMovedEvent>,
+    mut food_eaten_event: EventReader<FoodEatenEvent>,
+    mut snake_reach_goal_event: EventReader<SnakeReachGoalEvent>,
+) {
+    // Read the events even when muted, the sounds of past events are not played on unmute.
+    let snake_moved = snake_moved_event.iter().next().is_some();
+    let food_eaten = food_eaten_event.iter().next().is_some();
+    let snake_reached_goal = snake_reach_goal_event.iter().next().is_some();
+    if !constants.sound_effects {
+        return;
+    }
+
+    if let (true, Some(move_effect)) = (snake_moved, &assets.move_effect_2) {
+        audio
+            .play(move_effect.clone())
+            .with_playback_rate(1.0 + rand::thread_rng().gen_range(-0.05..0.1))
+            .with_volume(constants.move_effect_volume);
+    }
+
+    if let (true, Some(eat_effect)) = (food_eaten, &assets.eat_effect) {
+        audio
+            .play(eat_effect.clone())
+            .with_playback_rate(1.6)
+            .with_volume(constants.eat_effect_volume);
+    }
+
+    if let (true, Some(goal_effect)) = (snake_reached_goal, &assets.goal_effect) {
+        audio
+            .play(goal_effect.clone())
+            .with_playback_rate(0.7)
+            .with_volume(constants.goal_effect_volume);
+    }
 }
 
 pub fn grow_snake_on_move_system(
+    constants: Res<GameConstants>,
     mut snake_moved_event: EventReader<SnakeMovedEvent>,
+    mut food_eaten_event: EventWriter<FoodEatenEvent>,
     mut commands: Commands,
     snake_query: Query<(Entity, &Snake), With<SelectedSnake>>,
     foods_query: Query<(Entity, &Food), With<Food>>,
@@ -318,11 +482,21 @@ pub fn grow_snake_on_move_system(
             continue;
         }
 
-        commands.entity(food_entity).despawn();
+        start_eaten_food_anim(&mut commands, &constants, food_entity);
+        commands.entity(food_entity).remove::<Food>();
+        food_eaten_event.send(FoodEatenEvent(food.0));
+
+        if constants.instant_moves {
+            commands.entity(snake_entity).with_children(|parent| {
+                parent.spawn(SnakePartBundle::new(snake.index(), snake.len() - 1));
+            });
+            continue;
+        }
 
+        // The part overshoots its length at the end of the growth for a small pop.
         let grow_tween = Tween::new(
-            EaseFunction::QuadraticInOut,
-            std::time::Duration::from_secs_f32(0.2),
+            EaseFunction::BackOut,
+            constants.animation_duration(0.2),
             GrowPartLens,
         );
 
@@ -334,115 +508,342 @@ pub fn grow_snake_on_move_system(
     }
 }
 
+/// Despawn the shrink food eaten by the snake and the part the snake lost.
+pub fn shrink_snake_on_move_system(
+    constants: Res<GameConstants>,
+    mut snake_moved_event: EventReader<SnakeMovedEvent>,
+    mut food_eaten_event: EventWriter<FoodEatenEvent>,
+    mut commands: Commands,
+    mut despawn_snake_part_event: EventWriter<DespawnSnakePartEvent>,
+    snake_query: Query<&Snake, With<SelectedSnake>>,
+    shrink_foods_query: Query<(Entity, &ShrinkFood)>,
+) {
+    if snake_moved_event.iter().next().is_none() {
+        return;
+    }
+
+    let Ok(snake) = snake_query.get_single() else {
+        return;
+    };
+
+    for (shrink_food_entity, shrink_food) in &shrink_foods_query {
+        if shrink_food.0 != snake.head_position() {
+            continue;
+        }
+
+        start_eaten_food_anim(&mut commands, &constants, shrink_food_entity);
+        commands.entity(shrink_food_entity).remove::<ShrinkFood>();
+        food_eaten_event.send(FoodEatenEvent(shrink_food.0));
+
+        despawn_snake_part_event.send(DespawnSnakePartEvent(SnakePart {
+            snake_index: snake.index(),
+            part_index: snake.len(),
+        }));
+    }
+}
+
+/// The food is removed from the game right away, only the visual shrinks before being despawned.
+fn start_eaten_food_anim(commands: &mut Commands, constants: &GameConstants, food_entity: Entity) {
+    if constants.instant_moves {
+        commands.entity(food_entity).despawn();
+        return;
+    }
+
+    let shrink_tween = Tween::new(
+        EaseFunction::BackIn,
+        constants.animation_duration(0.25),
+        TransformScaleLens {
+            start: Vec3::ONE,
+            end: Vec3::ZERO,
+        },
+    )
+    .with_completed_event(EATEN_FOOD_TWEEN_COMPLETED);
+
+    commands
+        .entity(food_entity)
+        .insert((Animator::new(shrink_tween), EatenFoodAnim));
+}
+
+fn despawn_eaten_food_system(
+    mut commands: Commands,
+    mut tween_completed_event: EventReader<TweenCompleted>,
+    eaten_food_query: Query<(), With<EatenFoodAnim>>,
+) {
+    for event in tween_completed_event.iter() {
+        if event.user_data == EATEN_FOOD_TWEEN_COMPLETED && eaten_food_query.contains(event.entity)
+        {
+            commands.entity(event.entity).despawn();
+        }
+    }
+}
+
+/// How much a snake is squashed when landing after a fall of `grid_distance` cells, zero for short falls.
+fn landing_bounce_strength(grid_distance: i32) -> f32 {
+    let distance = (grid_distance - LANDING_BOUNCE_MIN_DISTANCE).max(0);
+    (distance as f32 * LANDING_BOUNCE_STRENGTH_PER_CELL).min(LANDING_BOUNCE_MAX_STRENGTH)
+}
+
+/// Squash the parts of a snake that landed and let them spring back to their shape.
+fn start_landing_bounce<'a>(
+    commands: &mut Commands,
+    constants: &GameConstants,
+    parts: impl Iterator<Item = &'a Entity>,
+    head_direction: IVec2,
+    gravity: IVec2,
+    grid_distance: i32,
+) {
+    let strength = landing_bounce_strength(grid_distance);
+    if !constants.landing_bounce || constants.instant_moves || strength <= 0.0 {
+        return;
+    }
+
+    // The parts are in the frame of the head, the gravity is along their y axis when the head is across the gravity.
+    let squash = Vec2::new(1.0 + strength, 1.0 - strength);
+    let squash = if head_direction.dot(gravity) == 0 {
+        squash
+    } else {
+        squash.yx()
+    };
+
+    for &part in parts {
+        let bounce_tween = Tween::new(
+            EaseFunction::ElasticOut,
+            constants.animation_duration(0.35),
+            TransformScaleLens {
+                start: squash.extend(1.0),
+                end: Vec3::ONE,
+            },
+        );
+        commands.entity(part).insert(Animator::new(bounce_tween));
+    }
+}
+
+/// Debug check that the level occupancy matches the snakes, once the snakes settle after a change of the level.
+#[allow(clippy::type_complexity)]
+fn validate_level_system(
+    level: Res<LevelInstance>,
+    snakes_query: Query<&Snake, With<Active>>,
+    animations_query: Query<
+        (),
+        Or<(
+            With<MoveCommand>,
+            With<PushedAnim>,
+            With<GravityFall>,
+            With<LevelExitAnim>,
+        )>,
+    >,
+    mut pending_validation: Local<bool>,
+) {
+    *pending_validation |= level.is_changed();
+    if !*pending_validation || !animations_query.is_empty() {
+        return;
+    }
+    *pending_validation = false;
+
+    let snakes: Vec<Snake> = snakes_query.iter().cloned().collect();
+    if let Err(positions) = level.validate_against(&snakes) {
+        error!("Level out of sync with the snakes at {:?}", positions);
+    }
+}
+
 #[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn gravity_system(
     time: Res<Time>,
     constants: Res<GameConstants>,
+    level_template: Res<LevelTemplate>,
     mut level: ResMut<LevelInstance>,
     mut snake_history: ResMut<SnakeHistory>,
     mut trigger_undo_event: EventWriter<UndoEvent>,
     mut snake_reach_goal_event: EventReader<SnakeReachGoalEvent>,
     mut commands: Commands,
     mut query: Query<
-        (
-            Entity,
-            &mut Snake,
-            Option<&mut GravityFall>,
-            Option<&SelectedSnake>,
-        ),
+        (Entity, &mut Snake, Option<&mut GravityFall>),
         (With<Active>, Without<LevelExitAnim>),
     >,
+    children_query: Query<&Children>,
+    parts_query: Query<(), With<SnakePart>>,
 ) {
-    let mut sorted_snakes: Vec<(
-        Entity,
-        Mut<Snake>,
-        Option<Mut<GravityFall>>,
-        Option<&SelectedSnake>,
-    )> = query.iter_mut().collect();
+    let snakes_reaching_goal: Vec<Entity> =
+        snake_reach_goal_event.iter().map(|event| event.0).collect();
 
-    sorted_snakes.sort_by_key(|(_, _, _, selected_snake)| selected_snake.is_none());
+    let mut sorted_snakes: Vec<(Entity, Mut<Snake>, Option<Mut<GravityFall>>)> = query
+        .iter_mut()
+        .filter(|(snake_entity, _, _)| !snakes_reaching_goal.contains(snake_entity))
+        .collect();
 
-    for (snake_entity, mut snake, gravity_fall, _) in sorted_snakes.into_iter() {
-        if snake_reach_goal_event
-            .iter()
-            .any(|event| event.0 == snake_entity)
-        {
+    game_logic::sort_bottom_first(&mut sorted_snakes, level.gravity(), |(_, snake, _)| {
+        snake.as_ref()
+    });
+
+    // The level and the history are only marked changed when a snake moves, lands or dies.
+    let level_instance = level.bypass_change_detection();
+    let history = snake_history.bypass_change_detection();
+    let mut level_changed = false;
+
+    // Only the snakes that move are marked changed, their meshes are rebuilt from the changes.
+    let mut snake_entities = Vec::with_capacity(sorted_snakes.len());
+    let mut snakes: Vec<&mut Snake> = Vec::with_capacity(sorted_snakes.len());
+    let mut gravity_falls = Vec::with_capacity(sorted_snakes.len());
+    for (snake_entity, snake, gravity_fall) in sorted_snakes.iter_mut() {
+        snake_entities.push(*snake_entity);
+        snakes.push(snake.bypass_change_detection());
+        gravity_falls.push(gravity_fall);
+    }
+
+    let mut is_falling: Vec<bool> = gravity_falls
+        .iter()
+        .map(|gravity_fall| gravity_fall.is_some())
+        .collect();
+    let mut moved = vec![false; snakes.len()];
+    let mut undo = false;
+
+    // Update the falling snakes.
+    'falling_snakes: for index in 0..snakes.len() {
+        let Some(gravity_fall) = gravity_falls[index].as_mut() else {
             continue;
-        }
+        };
 
-        match gravity_fall {
-            Some(mut gravity_fall) => {
-                gravity_fall.velocity -= constants.gravity * time.delta_seconds();
-                gravity_fall.relative_y += gravity_fall.velocity * time.delta_seconds();
+        let delta = constants.animation_delta(&time);
+        gravity_fall.velocity -= constants.gravity * delta;
+        gravity_fall.relative_y += gravity_fall.velocity * delta;
+        if constants.instant_moves {
+            gravity_fall.relative_y = -1.0;
+        }
 
-                // While relative y is positive, we haven't moved fully into the cell.
-                if gravity_fall.relative_y >= 0.0 {
-                    continue;
+        // While relative y is positive, we haven't moved fully into the cell.
+        // Instant moves fall cell after cell until the snake lands.
+        while gravity_fall.relative_y < 0.0 {
+            let falling_parts = game_logic::other_falling_parts(&snakes, &is_falling, index);
+            match game_logic::fall_one_unit(
+                level_instance,
+                history,
+                snakes[index],
+                &falling_parts,
+                gravity_fall.grid_distance,
+                level_template.fall_out_of_level_limit(),
+            ) {
+                FallOutcome::Falling => {
+                    gravity_fall.relative_y = if constants.instant_moves {
+                        -1.0
+                    } else {
+                        GRID_TO_WORLD_UNIT
+                    };
+                    gravity_fall.grid_distance += 1;
+                    moved[index] = true;
                 }
-
-                // Check if we fell on spikes, if, so trigger undo.
-                for (position, _) in snake.parts() {
-                    if !level.is_spike(*position) {
-                        continue;
+                FallOutcome::Landed => {
+                    commands
+                        .entity(snake_entities[index])
+                        .remove::<GravityFall>();
+                    is_falling[index] = false;
+                    level_changed |= gravity_fall.grid_distance > 0;
+
+                    if let Ok(children) = children_query.get(snake_entities[index]) {
+                        let parts = children
+                            .iter()
+                            .filter(|&&child| parts_query.contains(child));
+                        start_landing_bounce(
+                            &mut commands,
+                            &constants,
+                            parts,
+                            snakes[index].head_direction(),
+                            level_instance.gravity(),
+                            gravity_fall.grid_distance,
+                        );
                     }
+                    break;
+                }
+                FallOutcome::OnSpikes | FallOutcome::Drowned | FallOutcome::OutOfLevel => {
+                    commands
+                        .entity(snake_entities[index])
+                        .remove::<GravityFall>();
+
+                    level_changed = true;
+                    undo = true;
+                    break 'falling_snakes;
+                }
+            }
+        }
+    }
 
-                    let mut snake_commands = SnakeCommands::new(&mut level, &mut snake_history);
-                    snake_commands.stop_falling_on_spikes(snake.as_ref());
+    // Snakes moved or pushed into the water drown.
+    if !undo
+        && snakes
+            .iter()
+            .any(|snake| game_logic::is_in_water(level_instance, snake))
+    {
+        undo = true;
+    }
 
-                    commands.entity(snake_entity).remove::<GravityFall>();
+    // Check if the snakes on the ground start falling.
+    if !undo {
+        let started =
+            game_logic::start_falling_snakes(level_instance, history, &mut snakes, &mut is_falling);
 
-                    trigger_undo_event.send(UndoEvent);
-                    return;
-                }
+        for index in started {
+            commands.entity(snake_entities[index]).insert(GravityFall {
+                velocity: 0.0,
+                relative_y: GRID_TO_WORLD_UNIT,
+                grid_distance: 1,
+            });
+            moved[index] = true;
+        }
+    }
 
-                // keep falling..
-                if min_distance_to_ground(&level, &snake) > 1 {
-                    gravity_fall.relative_y = GRID_TO_WORLD_UNIT;
-                    gravity_fall.grid_distance += 1;
+    level_changed |= moved.contains(&true);
+    for ((_, snake, _), moved) in sorted_snakes.iter_mut().zip(moved) {
+        if moved {
+            snake.set_changed();
+        }
+    }
 
-                    snake.fall_one_unit();
-                } else {
-                    // ..or stop falling animation.
-                    commands.entity(snake_entity).remove::<GravityFall>();
+    if level_changed {
+        level.set_changed();
+        snake_history.set_changed();
+    }
 
-                    // Nothing to do if we fell less than an unit, meaning we stayed at the same place.
-                    if gravity_fall.grid_distance == 0 {
-                        return;
-                    }
+    if undo {
+        trigger_undo_event.send(UndoEvent);
+    }
+}
 
-                    let mut snake_commands = SnakeCommands::new(&mut level, &mut snake_history);
-                    snake_commands.stop_falling(snake.as_ref());
-                }
-            }
-            None => {
-                // Check if snake is on the ground and spawn gravity fall if not.
-                let min_distance_to_ground = min_distance_to_ground(&level, &snake);
-                if min_distance_to_ground > 1 {
-                    let mut snake_commands = SnakeCommands::new(&mut level, &mut snake_history);
-                    snake_commands.start_falling(snake.as_ref());
-
-                    snake.fall_one_unit();
-
-                    commands.entity(snake_entity).insert(GravityFall {
-                        velocity: 0.0,
-                        relative_y: GRID_TO_WORLD_UNIT,
-                        grid_distance: 1,
-                    });
-                }
-            }
-        }
+/// Blocks fall instantly once nothing supports them, they only move on the grid and their sprite catches up.
+pub fn block_gravity_system(
+    mut level: ResMut<LevelInstance>,
+    mut snake_history: ResMut<SnakeHistory>,
+    falling_snakes: Query<(With<Snake>, With<GravityFall>)>,
+    mut blocks_query: Query<&mut Block>,
+) {
+    // Falling snakes don't occupy their cells, wait for them to land.
+    if !falling_snakes.is_empty() {
+        return;
+    }
+
+    let mut blocks: Vec<Mut<Block>> = blocks_query.iter_mut().collect();
+    let mut block_refs: Vec<&mut Block> = blocks.iter_mut().map(|block| block.as_mut()).collect();
+
+    // Only mark the level changed when a block drops.
+    let dropped = game_logic::drop_blocks(
+        level.bypass_change_detection(),
+        snake_history.bypass_change_detection(),
+        &mut block_refs,
+    );
+    if dropped {
+        level.set_changed();
+        snake_history.set_changed();
     }
 }
 
 fn snake_smooth_movement_system(
     time: Res<Time>,
+    constants: Res<GameConstants>,
     mut commands: Commands,
     mut query: Query<(Entity, &mut MoveCommand)>,
 ) {
     for (entity, mut move_command) in query.iter_mut() {
         move_command.lerp_time +=
-            move_command.velocity * GRID_TO_WORLD_UNIT_INVERSE * time.delta_seconds();
-        if move_command.lerp_time > 1.0 {
+            move_command.velocity * GRID_TO_WORLD_UNIT_INVERSE * constants.animation_delta(&time);
+        if constants.instant_moves || move_command.lerp_time > 1.0 {
             commands.entity(entity).remove::<MoveCommand>();
         }
     }
@@ -450,13 +851,14 @@ fn snake_smooth_movement_system(
 
 pub fn snake_push_anim_system(
     time: Res<Time>,
+    constants: Res<GameConstants>,
     mut commands: Commands,
     mut push_anim_query: Query<(Entity, &mut PushedAnim)>,
 ) {
     for (entity, mut move_command) in push_anim_query.iter_mut() {
         move_command.lerp_time +=
-            move_command.velocity * GRID_TO_WORLD_UNIT_INVERSE * time.delta_seconds();
-        if move_command.lerp_time > 1.0 {
+            move_command.velocity * GRID_TO_WORLD_UNIT_INVERSE * constants.animation_delta(&time);
+        if constants.instant_moves || move_command.lerp_time > 1.0 {
             commands.entity(entity).remove::<PushedAnim>();
         }
     }
@@ -465,7 +867,6 @@ pub fn snake_push_anim_system(
 #[allow(clippy::too_many_arguments)]
 pub fn snake_exit_level_anim_system(
     constants: Res<GameConstants>,
-    level: Res<LevelTemplate>,
     mut commands: Commands,
     mut event_despawn_snake_parts: EventWriter<DespawnSnakePartEvent>,
     mut event_snake_exited_level: EventWriter<SnakeExitedLevelEvent>,
@@ -480,13 +881,16 @@ pub fn snake_exit_level_anim_system(
     eye_query: Query<(Entity, &Parent, &GlobalTransform), With<SnakeEye>>,
 ) {
     for (entity, mut snake, mut level_exit, move_command, children) in anim_query.iter_mut() {
+        // The snake exits through the goal where its head was when it reached it.
+        let goal_position = level_exit.initial_snake_position[0].0;
+
         for &child in children {
             let Ok((entity, part, modifier)) = snake_part_query.get_mut(child) else {
                 continue;
             };
 
             if modifier.is_some() {
-                if (snake.parts()[part.part_index].0 - level.goal_position)
+                if (snake.parts()[part.part_index].0 - goal_position)
                     .abs()
                     .max_element()
                     > 1
@@ -498,16 +902,16 @@ pub fn snake_exit_level_anim_system(
                     if parent.get() != entity {
                         continue;
                     }
-                    let offset = transform.translation().truncate() - to_world(level.goal_position);
+                    let offset = transform.translation().truncate() - to_world(goal_position);
                     let distance = offset.dot(snake.parts()[part.part_index].1.as_vec2());
 
                     if distance > 0.0 {
                         commands.entity(eye_entity).despawn();
                     }
                 }
-            } else if snake.parts()[part.part_index].0 == level.goal_position {
+            } else if snake.parts()[part.part_index].0 == goal_position {
                 commands.entity(entity).insert(PartClipper {
-                    clip_position: level.goal_position,
+                    clip_position: goal_position,
                 });
             }
         }
@@ -537,3 +941,21 @@ pub fn snake_exit_level_anim_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_landing_bounce_strength() {
+        assert_eq!(landing_bounce_strength(0), 0.0);
+        assert_eq!(landing_bounce_strength(LANDING_BOUNCE_MIN_DISTANCE), 0.0);
+
+        let short_fall = landing_bounce_strength(LANDING_BOUNCE_MIN_DISTANCE + 1);
+        let long_fall = landing_bounce_strength(LANDING_BOUNCE_MIN_DISTANCE + 3);
+        assert!(short_fall > 0.0);
+        assert!(long_fall > short_fall);
+
+        assert_eq!(landing_bounce_strength(100), LANDING_BOUNCE_MAX_STRENGTH);
+    }
+}
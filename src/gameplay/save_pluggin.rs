@@ -0,0 +1,166 @@
+//! Saves the progress of the player between runs, in a json file on desktop and in the local storage on the web.
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gameplay::level_pluggin::LevelCompletedEvent,
+    level::levels::LEVELS,
+    menus::select_level_menu::{CompletedLevels, NextLevel},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+const SAVE_FILE: &str = "bird_snake_save.json";
+
+#[cfg(target_arch = "wasm32")]
+const SAVE_STORAGE_KEY: &str = "bird_snake_save";
+
+#[derive(Resource, Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct SaveData {
+    /// The indices of the completed levels, sorted.
+    pub completed_levels: Vec<usize>,
+    /// The last level the player completed, saves written before the rename use `last_level`.
+    #[serde(alias = "last_level")]
+    pub last_completed_level: Option<usize>,
+}
+
+impl SaveData {
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<SaveData> {
+        serde_json::from_str(json)
+    }
+
+    /// The level to start when playing, the one after the last level completed.
+    pub fn resume_level(&self) -> usize {
+        match self.last_completed_level {
+            Some(level) => (level + 1).min(LEVELS.len() - 1),
+            None => 0,
+        }
+    }
+
+    fn complete_level(&mut self, level_id: usize) {
+        if let Err(index) = self.completed_levels.binary_search(&level_id) {
+            self.completed_levels.insert(index, level_id);
+        }
+        self.last_completed_level = Some(level_id);
+    }
+
+    /// Load the save, a missing save is an empty one.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load() -> anyhow::Result<SaveData> {
+        match std::fs::read_to_string(SAVE_FILE) {
+            Ok(json) => Ok(SaveData::from_json(&json)?),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(SaveData::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) -> anyhow::Result<()> {
+        std::fs::write(SAVE_FILE, self.to_json()?)?;
+        Ok(())
+    }
+
+    /// Load the save, a missing save is an empty one.
+    #[cfg(target_arch = "wasm32")]
+    pub fn load() -> anyhow::Result<SaveData> {
+        match local_storage()?.get_item(SAVE_STORAGE_KEY) {
+            Ok(Some(json)) => Ok(SaveData::from_json(&json)?),
+            Ok(None) => Ok(SaveData::default()),
+            Err(error) => Err(anyhow::anyhow!(
+                "Failed to read the local storage: {:?}",
+                error
+            )),
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn save(&self) -> anyhow::Result<()> {
+        local_storage()?
+            .set_item(SAVE_STORAGE_KEY, &self.to_json()?)
+            .map_err(|error| anyhow::anyhow!("Failed to write the local storage: {:?}", error))
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> anyhow::Result<web_sys::Storage> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| anyhow::anyhow!("The local storage is not available."))
+}
+
+/// Loads the save at startup to fill the completed levels and the level to play,
+/// and writes it each time a level is completed.
+pub struct SavePluggin;
+
+impl Plugin for SavePluggin {
+    fn build(&self, app: &mut App) {
+        let save_data = SaveData::load().unwrap_or_else(|error| {
+            error!("Failed to load the save: {}", error);
+            SaveData::default()
+        });
+
+        app.insert_resource(CompletedLevels(
+            save_data.completed_levels.iter().copied().collect(),
+        ))
+        .insert_resource(NextLevel(save_data.resume_level()))
+        .insert_resource(save_data)
+        .add_system(save_on_level_completed_system);
+    }
+}
+
+fn save_on_level_completed_system(
+    mut save_data: ResMut<SaveData>,
+    mut event_level_completed: EventReader<LevelCompletedEvent>,
+) {
+    let mut changed = false;
+    for event in event_level_completed.iter() {
+        save_data.complete_level(event.level_id);
+        changed = true;
+    }
+
+    if !changed {
+        return;
+    }
+
+    if let Err(error) = save_data.save() {
+        error!("Failed to write the save: {}", error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_save_round_trip() {
+        let mut save_data = SaveData::default();
+        save_data.complete_level(3);
+        save_data.complete_level(1);
+        save_data.complete_level(3);
+
+        assert_eq!(save_data.completed_levels, vec![1, 3]);
+        assert_eq!(save_data.last_completed_level, Some(3));
+
+        let json = save_data.to_json().unwrap();
+        assert_eq!(SaveData::from_json(&json).unwrap(), save_data);
+
+        let old_save =
+            SaveData::from_json(r#"{"completed_levels": [1], "last_level": 1}"#).unwrap();
+        assert_eq!(old_save.last_completed_level, Some(1));
+    }
+
+    #[test]
+    pub fn test_resume_level() {
+        assert_eq!(SaveData::default().resume_level(), 0);
+
+        let save_data = SaveData {
+            completed_levels: vec![],
+            last_completed_level: Some(LEVELS.len() - 1),
+        };
+        assert_eq!(save_data.resume_level(), LEVELS.len() - 1);
+    }
+}
@@ -1,32 +1,42 @@
-use bevy::{math::Vec3Swizzles, prelude::*, transform::TransformSystem};
+use bevy::{math::Vec3Swizzles, prelude::*, transform::TransformSystem, utils::HashSet};
 use bevy_prototype_lyon::{
     entity::ShapeBundle,
-    prelude::{DrawMode, FillMode, Path, PathBuilder, ShapePlugin},
+    prelude::{DrawMode, FillMode, Path, PathBuilder},
 };
+use bevy_tweening::{component_animator_system, AnimationSystem};
 use iyes_loopless::prelude::{ConditionHelpers, IntoConditionalSystem};
 use std::{collections::VecDeque, mem};
 
 use crate::{
-    gameplay::commands::SnakeCommands,
     gameplay::game_constants_pluggin::{
-        to_grid, to_world, GRID_TO_WORLD_UNIT, SNAKE_COLORS, SNAKE_EYE_SIZE,
+        snake_colors, to_grid, to_world, KeyBindings, GRID_TO_WORLD_UNIT, SNAKE_EYE_OFFSET,
+        SNAKE_EYE_SIZE,
     },
     gameplay::level_pluggin::LevelEntity,
-    gameplay::movement_pluggin::{GravityFall, MoveCommand, PushedAnim},
-    gameplay::undo::{SnakeHistory, UndoEvent},
+    gameplay::movement_pluggin::{GravityFall, LevelExitAnim, MoveCommand, PushedAnim},
     level::level_instance::{LevelEntityType, LevelInstance},
-    level::level_template::{LevelTemplate, SnakeTemplate},
+    level::level_template::{snake_template_from_positions, LevelTemplate, SnakeTemplate},
+    menus::pause_menu::Paused,
     GameState,
 };
 
 use super::movement_pluggin::PartGrowAnim;
 
+/// The snakes of the level, spawned from the level template and despawned when they leave the level.
 pub struct SnakePluggin;
 
+/// The transforms, eyes and meshes of the snakes, they follow the grid positions of the snakes and their animations.
+pub struct SnakeRenderPluggin;
+
+#[derive(SystemLabel, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SnakeSystem {
+    /// The transforms of the snake parts are set before their meshes are built.
+    SnakeTransform,
+}
+
 impl Plugin for SnakePluggin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(ShapePlugin)
-            .add_event::<SpawnSnakeEvent>()
+        app.add_event::<SpawnSnakeEvent>()
             .add_event::<DespawnSnakePartEvent>()
             .add_event::<DespawnSnakeEvent>()
             .add_event::<DespawnSnakePartsEvent>()
@@ -36,20 +46,15 @@ impl Plugin for SnakePluggin {
                     .run_in_state(GameState::Game)
                     .run_if_resource_exists::<LevelInstance>(),
             )
-            .add_system(select_snake_mouse_system.run_in_state(GameState::Game))
-            .add_system_to_stage(
-                CoreStage::PostUpdate,
-                update_snake_transforms_system
+            .add_system(
+                select_snake_mouse_system
                     .run_in_state(GameState::Game)
-                    .label("SnakeTransform")
-                    .before(TransformSystem::TransformPropagate),
+                    .run_unless_resource_exists::<Paused>(),
             )
-            .add_system_to_stage(
-                CoreStage::PostUpdate,
-                update_snake_parts_mesh_system
+            .add_system(
+                cycle_selected_snake_system
                     .run_in_state(GameState::Game)
-                    .after("SnakeTransform")
-                    .before(TransformSystem::TransformPropagate),
+                    .run_unless_resource_exists::<Paused>(),
             )
             .add_system_to_stage(
                 CoreStage::PostUpdate,
@@ -68,6 +73,36 @@ impl Plugin for SnakePluggin {
     }
 }
 
+impl Plugin for SnakeRenderPluggin {
+    fn build(&self, app: &mut App) {
+        app.add_system(
+            component_animator_system::<PartGrowAnim>
+                .run_in_state(GameState::Game)
+                .label(AnimationSystem::AnimationUpdate),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_snake_transforms_system
+                .run_in_state(GameState::Game)
+                .label(SnakeSystem::SnakeTransform)
+                .before(TransformSystem::TransformPropagate),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_snake_eyes_system
+                .run_in_state(GameState::Game)
+                .before(TransformSystem::TransformPropagate),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_snake_parts_mesh_system
+                .run_in_state(GameState::Game)
+                .after(SnakeSystem::SnakeTransform)
+                .before(TransformSystem::TransformPropagate),
+        );
+    }
+}
+
 #[derive(PartialEq, Eq)]
 pub struct DespawnSnakePartEvent(pub SnakePart);
 
@@ -89,8 +124,11 @@ pub struct SnakePart {
     pub part_index: usize,
 }
 
+/// An eye of a snake, on the left (1.0) or right (-1.0) side of the head when looking forward.
 #[derive(Component)]
-pub struct SnakeEye;
+pub struct SnakeEye {
+    side: f32,
+}
 
 #[derive(Bundle)]
 pub struct SnakePartBundle {
@@ -101,7 +139,8 @@ pub struct SnakePartBundle {
 
 impl SnakePartBundle {
     pub fn new(snake_index: i32, part_index: usize) -> Self {
-        let color = SNAKE_COLORS[snake_index as usize][part_index % 2];
+        // Levels can have more snakes than colors, reuse the colors in that case.
+        let color = snake_colors(snake_index)[part_index % 2];
 
         SnakePartBundle {
             shape: ShapeBundle {
@@ -122,7 +161,7 @@ pub struct PartClipper {
     pub clip_position: IVec2,
 }
 
-#[derive(Component, Debug)]
+#[derive(Component, Debug, Clone)]
 pub struct Snake {
     parts: VecDeque<(IVec2, IVec2)>,
     index: i32,
@@ -131,6 +170,29 @@ pub struct Snake {
 pub struct SpawnSnakeEvent;
 
 impl Snake {
+    pub fn from_template(snake_template: &SnakeTemplate, index: i32) -> Self {
+        Snake {
+            parts: VecDeque::from(snake_template.clone()),
+            index,
+        }
+    }
+
+    /// A straight snake with its head at `head` facing `direction`, the body extends behind the head.
+    pub fn straight(head: IVec2, direction: IVec2, length: usize, index: i32) -> Self {
+        Snake {
+            parts: (0..length as i32)
+                .map(|part_index| (head - part_index * direction, direction))
+                .collect(),
+            index,
+        }
+    }
+
+    /// A snake from the positions of its parts from the head to the tail, the directions are inferred like in the levels.
+    /// There must be at least two parts.
+    pub fn from_positions(positions: &[IVec2], index: i32) -> Self {
+        Snake::from_template(&snake_template_from_positions(positions), index)
+    }
+
     pub fn parts(&self) -> &VecDeque<(IVec2, IVec2)> {
         &self.parts
     }
@@ -149,8 +211,12 @@ impl Snake {
     }
 
     pub fn move_forward(&mut self, direction: IVec2) {
-        self.parts
-            .push_front((self.head_position() + direction, direction));
+        self.move_head_to(self.head_position() + direction, direction);
+    }
+
+    /// Move the head to a position facing a direction, the rest of the snake follows.
+    pub fn move_head_to(&mut self, position: IVec2, direction: IVec2) {
+        self.parts.push_front((position, direction));
         self.parts.pop_back();
     }
 
@@ -172,6 +238,11 @@ impl Snake {
         self.parts.pop_back();
     }
 
+    /// Put back a tail removed by shrinking.
+    pub fn restore_tail(&mut self, tail: (IVec2, IVec2)) {
+        self.parts.push_back(tail);
+    }
+
     pub fn tail(&self) -> (IVec2, IVec2) {
         *self.parts.back().unwrap()
     }
@@ -180,19 +251,21 @@ impl Snake {
         self.parts.back().unwrap().0
     }
 
-    pub fn is_standing(&self) -> bool {
-        (self.parts.front().unwrap().0.y - self.parts.back().unwrap().0.y)
-            == (self.len() - 1) as i32
+    pub fn tail_direction(&self) -> IVec2 {
+        self.parts.back().unwrap().1
+    }
+
+    /// The snake is straight with its head on top, opposite to the gravity.
+    pub fn is_standing(&self, gravity: IVec2) -> bool {
+        (self.head_position() - self.tail_position()).dot(-gravity) == (self.len() - 1) as i32
     }
 
     pub fn occupies_position(&self, position: IVec2) -> bool {
         self.parts.iter().any(|part| part.0 == position)
     }
 
-    pub fn fall_one_unit(&mut self) {
-        for (position, _) in self.parts.iter_mut() {
-            *position += IVec2::NEG_Y;
-        }
+    pub fn fall_one_unit(&mut self, gravity: IVec2) {
+        self.translate(gravity);
     }
 
     pub fn translate(&mut self, offset: IVec2) {
@@ -213,10 +286,7 @@ pub fn spawn_snake(
     snake_index: i32,
 ) -> Entity {
     let mut spawn_command = commands.spawn((
-        Snake {
-            parts: VecDeque::from(snake_template.clone()),
-            index: snake_index,
-        },
+        Snake::from_template(snake_template, snake_index),
         SpatialBundle { ..default() },
         LevelEntity,
         Active,
@@ -227,21 +297,7 @@ pub fn spawn_snake(
             let mut entity = parent.spawn(SnakePartBundle::new(snake_index, index));
 
             if index == 0 {
-                entity.with_children(|parent| {
-                    parent.spawn((
-                        SpriteBundle {
-                            sprite: Sprite {
-                                color: Color::BLACK,
-                                custom_size: Some(SNAKE_EYE_SIZE),
-                                ..default()
-                            },
-                            transform: Transform::from_xyz(5.0, 5.0, 1.0),
-                            ..default()
-                        },
-                        LevelEntity,
-                        SnakeEye,
-                    ));
-                });
+                entity.with_children(spawn_snake_eyes);
             }
         }
     });
@@ -253,6 +309,59 @@ pub fn spawn_snake(
     spawn_command.id()
 }
 
+/// Spawn the two eyes of a snake as children of its head, their placement is updated with the head direction.
+fn spawn_snake_eyes(parent: &mut ChildBuilder) {
+    for side in [1.0, -1.0] {
+        parent.spawn((
+            SpriteBundle {
+                sprite: Sprite {
+                    color: Color::BLACK,
+                    custom_size: Some(SNAKE_EYE_SIZE),
+                    ..default()
+                },
+                transform: Transform::from_translation(eye_offset(side).extend(1.0)),
+                ..default()
+            },
+            LevelEntity,
+            SnakeEye { side },
+        ));
+    }
+}
+
+/// The offset of an eye from the center of the head, in the frame of the head facing right.
+fn eye_offset(side: f32) -> Vec2 {
+    Vec2::new(SNAKE_EYE_OFFSET.x, side * SNAKE_EYE_OFFSET.y)
+}
+
+/// Is an eye visible for a head direction, snakes seen from the side show their upper eye
+/// and snakes facing up or down show both eyes.
+fn is_eye_visible(side: f32, head_direction: IVec2) -> bool {
+    // The left of the head is the direction rotated by a quarter turn, as in the snake transform.
+    let left = IVec2::new(-head_direction.y, head_direction.x);
+    left.y == 0 || side * left.y as f32 > 0.0
+}
+
+/// Show the eyes on the visible side of the heads, the eyes rotate with the snakes.
+pub fn update_snake_eyes_system(
+    snake_query: Query<&Snake>,
+    parts_query: Query<&Parent, With<SnakePart>>,
+    mut eyes_query: Query<(&SnakeEye, &Parent, &mut Visibility)>,
+) {
+    for (eye, part_parent, mut visibility) in &mut eyes_query {
+        let Ok(snake_parent) = parts_query.get(part_parent.get()) else {
+            continue;
+        };
+        let Ok(snake) = snake_query.get(snake_parent.get()) else {
+            continue;
+        };
+
+        let is_visible = is_eye_visible(eye.side, snake.head_direction());
+        if visibility.is_visible != is_visible {
+            visibility.is_visible = is_visible;
+        }
+    }
+}
+
 const FOWARD_LEFT: IVec2 = IVec2::new(1, 1);
 const FOWARD_RIGHT: IVec2 = IVec2::new(1, -1);
 const BACK_RIGHT: IVec2 = IVec2::new(-1, -1);
@@ -262,6 +371,7 @@ const CORNERS: [IVec2; 4] = [FOWARD_LEFT, FOWARD_RIGHT, BACK_RIGHT, BACK_LEFT];
 
 #[allow(clippy::type_complexity)]
 pub fn update_snake_transforms_system(
+    level: Option<Res<LevelInstance>>,
     mut snake_query: Query<
         (
             &Snake,
@@ -273,8 +383,11 @@ pub fn update_snake_transforms_system(
         With<Active>,
     >,
 ) {
+    // The falls are offset against the gravity.
+    let gravity = level.map_or(IVec2::NEG_Y, |level| level.gravity());
+    let up = -gravity.as_vec2();
     for (snake, mut transform, move_command, pushed_anim, fall) in &mut snake_query {
-        let fall_offset = fall.map_or(Vec2::ZERO, |gravity_fall| gravity_fall.relative_y * Vec2::Y);
+        let fall_offset = fall.map_or(Vec2::ZERO, |gravity_fall| gravity_fall.relative_y * up);
 
         let push_offset = pushed_anim.map_or(Vec2::ZERO, |command| {
             let initial_offset = -GRID_TO_WORLD_UNIT * command.direction;
@@ -297,26 +410,61 @@ pub fn update_snake_transforms_system(
     }
 }
 
+/// The mesh of a part is only rebuilt when the part is new, the snake moved or while it is animated.
+/// The parts animated last frame are rebuilt once more to settle in their final shape.
 #[allow(clippy::type_complexity)]
 fn update_snake_parts_mesh_system(
+    mut animated_last_frame: Local<HashSet<Entity>>,
     mut snake_parts_query: Query<(
+        Entity,
         &mut Path,
         &SnakePart,
+        ChangeTrackers<SnakePart>,
         Option<&PartClipper>,
         Option<&PartGrowAnim>,
         &Parent,
     )>,
-    snake_query: Query<(&Snake, &Transform, Option<&MoveCommand>), With<Active>>,
+    snake_query: Query<
+        (
+            &Snake,
+            ChangeTrackers<Snake>,
+            &Transform,
+            Option<&MoveCommand>,
+            Option<&PushedAnim>,
+            Option<&GravityFall>,
+        ),
+        With<Active>,
+    >,
 ) {
-    for (mut path, part, clipper, part_grow, parent) in snake_parts_query.iter_mut() {
-        let Ok((snake, transform, move_command)) = snake_query.get(parent.get()) else {
+    let mut animated = HashSet::new();
+
+    for (entity, mut path, part, part_tracker, clipper, part_grow, parent) in
+        snake_parts_query.iter_mut()
+    {
+        let Ok(snake_item) = snake_query.get(parent.get()) else {
             continue;
         };
+        let (snake, snake_tracker, transform, move_command, pushed_anim, fall) = snake_item;
 
         if part.part_index > snake.len() - 1 {
             continue;
         }
 
+        let is_animated = move_command.is_some()
+            || pushed_anim.is_some()
+            || fall.is_some()
+            || part_grow.is_some()
+            || clipper.is_some();
+
+        if is_animated {
+            animated.insert(entity);
+        } else if !part_tracker.is_added()
+            && !snake_tracker.is_changed()
+            && !animated_last_frame.contains(&entity)
+        {
+            continue;
+        }
+
         let mut path_builder = PathBuilder::new();
 
         let next_part = snake.parts.get(part.part_index + 1);
@@ -455,6 +603,8 @@ fn update_snake_parts_mesh_system(
 
         *path = path_builder.build();
     }
+
+    *animated_last_frame = animated;
 }
 
 pub fn set_snake_active(commands: &mut Commands, snake: &Snake, snake_entity: Entity) {
@@ -466,20 +616,7 @@ pub fn set_snake_active(commands: &mut Commands, snake: &Snake, snake_entity: En
                 let mut entity = parent.spawn(SnakePartBundle::new(snake.index(), index));
 
                 if index == 0 {
-                    entity.with_children(|parent| {
-                        parent.spawn((
-                            SpriteBundle {
-                                sprite: Sprite {
-                                    color: Color::BLACK,
-                                    custom_size: Some(SNAKE_EYE_SIZE),
-                                    ..default()
-                                },
-                                transform: Transform::from_xyz(5.0, 5.0, 1.0),
-                                ..default()
-                            },
-                            LevelEntity,
-                        ));
-                    });
+                    entity.with_children(spawn_snake_eyes);
                 }
             }
         });
@@ -509,6 +646,23 @@ pub fn spawn_snake_system(
     }
 }
 
+/// The grid position under the mouse cursor, if the cursor is in the window.
+pub fn cursor_grid_position(
+    windows: &Windows,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<IVec2> {
+    let window = windows.get_primary()?;
+    let mouse_position = window.cursor_position()?;
+
+    let window_size = Vec2::new(window.width(), window.height());
+    let ndc = (mouse_position / window_size) * 2.0 - Vec2::ONE;
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
+    let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
+
+    Some(to_grid(world_pos.xy()))
+}
+
 pub fn select_snake_mouse_system(
     buttons: Res<Input<MouseButton>>,
     windows: Res<Windows>,
@@ -521,23 +675,11 @@ pub fn select_snake_mouse_system(
         return;
     }
 
-    let window = windows.get_primary().unwrap();
-
-    let Some(mouse_position) = window.cursor_position() else {
-        return;
-    };
-
     let (camera, camera_transform) = camera.single();
-    let mouse_world_position = {
-        let window_size = Vec2::new(window.width(), window.height());
-        let ndc = (mouse_position / window_size) * 2.0 - Vec2::ONE;
-        let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix().inverse();
-        let world_pos = ndc_to_world.project_point3(ndc.extend(-1.0));
-
-        world_pos.xy()
+    let Some(mouse_grid_position) = cursor_grid_position(&windows, camera, camera_transform) else {
+        return;
     };
 
-    let mouse_grid_position = to_grid(mouse_world_position);
     let selected_snake_entity = selected_snake.single();
 
     for (entity, snake) in unselected_snakes.iter() {
@@ -553,25 +695,50 @@ pub fn select_snake_mouse_system(
     }
 }
 
-pub fn respawn_snake_on_fall_system(
-    mut snake_history: ResMut<SnakeHistory>,
-    mut level: ResMut<LevelInstance>,
-    mut trigger_undo_event: EventWriter<UndoEvent>,
+type WithSelectableSnakeFilter = (
+    With<Active>,
+    Without<SelectedSnake>,
+    Without<GravityFall>,
+    Without<LevelExitAnim>,
+);
+
+/// The cycle snake key selects the next snake by ascending index, wrapping around to the first one.
+/// Snakes falling or exiting the level are skipped.
+pub fn cycle_selected_snake_system(
+    keyboard: Res<Input<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
     mut commands: Commands,
-    mut snake_query: Query<(Entity, &Snake), With<GravityFall>>,
+    selected_snake: Query<(Entity, &Snake), With<SelectedSnake>>,
+    selectable_snakes: Query<(Entity, &Snake), WithSelectableSnakeFilter>,
 ) {
-    for (snake_entity, snake) in snake_query.iter_mut() {
-        if snake.head_position().y >= -2 {
-            return;
-        }
+    if !keyboard.any_just_pressed(key_bindings.cycle_snake.iter().copied()) {
+        return;
+    }
 
-        let mut snake_commands = SnakeCommands::new(&mut level, &mut snake_history);
-        snake_commands.stop_falling(snake);
+    let selected = selected_snake.get_single().ok();
+    let selected_index = selected.map_or(-1, |(_, snake)| snake.index());
+
+    let next_snake = selectable_snakes
+        .iter()
+        .filter(|(_, snake)| snake.index() > selected_index)
+        .min_by_key(|(_, snake)| snake.index())
+        .or_else(|| {
+            selectable_snakes
+                .iter()
+                .min_by_key(|(_, snake)| snake.index())
+        });
 
-        commands.entity(snake_entity).remove::<GravityFall>();
+    let Some((next_snake_entity, _)) = next_snake else {
+        return;
+    };
 
-        trigger_undo_event.send(UndoEvent);
+    if let Some((selected_snake_entity, _)) = selected {
+        commands
+            .entity(selected_snake_entity)
+            .remove::<SelectedSnake>();
     }
+
+    commands.entity(next_snake_entity).insert(SelectedSnake);
 }
 
 fn despawn_snake_system(
@@ -638,3 +805,54 @@ fn despawn_snake_parts_system(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameplay::game_constants_pluggin::{DOWN, LEFT, RIGHT, UP};
+
+    #[test]
+    pub fn test_eye_visibility() {
+        // Facing right the left eye is up, facing left the right eye is up.
+        assert!(is_eye_visible(1.0, RIGHT));
+        assert!(!is_eye_visible(-1.0, RIGHT));
+        assert!(is_eye_visible(-1.0, LEFT));
+        assert!(!is_eye_visible(1.0, LEFT));
+
+        for side in [1.0, -1.0] {
+            assert!(is_eye_visible(side, UP));
+            assert!(is_eye_visible(side, DOWN));
+        }
+    }
+
+    #[test]
+    pub fn test_straight_snake() {
+        let snake = Snake::straight(IVec2::new(3, 1), RIGHT, 3, 0);
+        assert_eq!(
+            snake.parts(),
+            &VecDeque::from([
+                (IVec2::new(3, 1), RIGHT),
+                (IVec2::new(2, 1), RIGHT),
+                (IVec2::new(1, 1), RIGHT),
+            ])
+        );
+
+        assert!(Snake::straight(IVec2::new(1, 2), UP, 2, 0).is_standing(DOWN));
+        assert!(!Snake::straight(IVec2::new(1, 2), UP, 2, 0).is_standing(LEFT));
+        assert!(Snake::straight(IVec2::new(1, 2), LEFT, 2, 0).is_standing(RIGHT));
+    }
+
+    #[test]
+    pub fn test_snake_from_positions() {
+        let level = LevelTemplate::parse("X...\n.A..\n.aa.\n####").unwrap();
+        let positions = [IVec2::new(1, 2), IVec2::new(1, 1), IVec2::new(2, 1)];
+        let snake = Snake::from_positions(&positions, 0);
+
+        assert_eq!(
+            snake.parts(),
+            Snake::from_template(&level.initial_snakes[0], 0).parts()
+        );
+        assert_eq!(snake.head_direction(), UP);
+        assert_eq!(snake.tail_direction(), LEFT);
+    }
+}
@@ -0,0 +1,271 @@
+//! Breadth first search over the game states of a level to find a solution with the least moves.
+//! Used to check that the levels are solvable.
+
+use std::collections::{HashSet, VecDeque};
+
+use bevy::prelude::*;
+use thiserror::Error;
+
+use crate::{
+    gameplay::game_constants_pluggin::{DOWN, LEFT, RIGHT, UP},
+    gameplay::game_logic::{GameLogic, GravityOutcome, MoveOutcome},
+    level::game_state::GameState,
+    level::level_template::LevelTemplate,
+    level::levels::LEVELS,
+};
+
+pub const DEFAULT_NODE_BUDGET: usize = 200_000;
+
+const DIRECTIONS: [IVec2; 4] = [RIGHT, LEFT, UP, DOWN];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum SolverError {
+    #[error("The level has no solution, {0} states explored.")]
+    Unsolvable(usize),
+
+    #[error("No solution found within the budget of {0} states.")]
+    BudgetExceeded(usize),
+}
+
+/// Difficulty metrics of a level, measured by the search of its shortest solution.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LevelStats {
+    /// The number of moves of the shortest solution.
+    pub solution_length: usize,
+    /// The number of distinct game states reached before finding the solution.
+    pub states_explored: usize,
+    /// The average number of new game states reached by the moves from an explored state.
+    pub branching_factor: f32,
+    /// The number of explored states where every move is blocked, lethal or leads to a known state.
+    pub dead_ends: usize,
+}
+
+fn game_state(game: &GameLogic) -> GameState {
+    GameState::new(&game.level, &game.snakes)
+}
+
+/// Find the shortest sequence of moves, as snake indices and directions, completing the level.
+/// The search stops after exploring `node_budget` states.
+pub fn solve(
+    level_template: &LevelTemplate,
+    node_budget: usize,
+) -> Result<Vec<(i32, IVec2)>, SolverError> {
+    search(level_template, node_budget).map(|(moves, _)| moves)
+}
+
+/// Score the difficulty of a level from the search of its shortest solution.
+pub fn analyze_level(level_template: &LevelTemplate) -> Result<LevelStats, SolverError> {
+    search(level_template, DEFAULT_NODE_BUDGET).map(|(_, stats)| stats)
+}
+
+/// Breadth first search of the shortest solution, with the statistics of the states explored to find it.
+fn search(
+    level_template: &LevelTemplate,
+    node_budget: usize,
+) -> Result<(Vec<(i32, IVec2)>, LevelStats), SolverError> {
+    let mut game = GameLogic::new(level_template);
+    if !matches!(
+        game.apply_gravity(),
+        GravityOutcome::Stable | GravityOutcome::Fell
+    ) {
+        return Err(SolverError::Unsolvable(0));
+    }
+
+    // The moves are stored once per node as the move from the parent node.
+    let mut nodes: Vec<(usize, (i32, IVec2))> = vec![(0, (0, IVec2::ZERO))];
+    let mut visited = HashSet::from([game_state(&game)]);
+    let mut queue = VecDeque::from([(game, 0)]);
+
+    // The states expanded, the new states reached from them and the expanded states without new state.
+    let mut expanded = 0;
+    let mut children = 0;
+    let mut dead_ends = 0;
+
+    while let Some((game, node)) = queue.pop_front() {
+        expanded += 1;
+        let children_before = children;
+
+        for snake_index in game.snakes.iter().map(|snake| snake.index()) {
+            for direction in DIRECTIONS {
+                let mut next_game = game.clone();
+                let (move_outcome, gravity_outcome) = next_game.player_move(snake_index, direction);
+
+                if !matches!(move_outcome, MoveOutcome::Moved { .. })
+                    || !matches!(
+                        gravity_outcome,
+                        GravityOutcome::Stable | GravityOutcome::Fell
+                    )
+                {
+                    continue;
+                }
+
+                if !visited.insert(game_state(&next_game)) {
+                    continue;
+                }
+
+                nodes.push((node, (snake_index, direction)));
+                let next_node = nodes.len() - 1;
+                children += 1;
+
+                if next_game.is_complete() {
+                    let moves = moves_to_node(&nodes, next_node);
+                    let stats = LevelStats {
+                        solution_length: moves.len(),
+                        states_explored: children + 1,
+                        branching_factor: children as f32 / expanded as f32,
+                        dead_ends,
+                    };
+                    return Ok((moves, stats));
+                }
+
+                if nodes.len() >= node_budget {
+                    return Err(SolverError::BudgetExceeded(node_budget));
+                }
+
+                queue.push_back((next_game, next_node));
+            }
+        }
+
+        if children == children_before {
+            dead_ends += 1;
+        }
+    }
+
+    Err(SolverError::Unsolvable(nodes.len()))
+}
+
+fn moves_to_node(nodes: &[(usize, (i32, IVec2))], mut node: usize) -> Vec<(i32, IVec2)> {
+    let mut moves = vec![];
+    while node != 0 {
+        let (parent, player_move) = nodes[node];
+        moves.push(player_move);
+        node = parent;
+    }
+    moves.reverse();
+    moves
+}
+
+fn format_move((snake_index, direction): (i32, IVec2)) -> String {
+    let snake = (b'A' + snake_index as u8) as char;
+    let direction = match direction {
+        RIGHT => "right",
+        LEFT => "left",
+        UP => "up",
+        _ => "down",
+    };
+    format!("{} {}", snake, direction)
+}
+
+/// Solve a level, or all the levels if no level is given, and print the solutions.
+pub fn print_solutions(level: Option<usize>, node_budget: usize) {
+    let levels: Vec<usize> = match level {
+        Some(level) if level >= LEVELS.len() => {
+            println!(
+                "No level {}, the level index should be less than {}.",
+                level,
+                LEVELS.len()
+            );
+            return;
+        }
+        Some(level) => vec![level],
+        None => (0..LEVELS.len()).collect(),
+    };
+
+    for level in levels {
+        let level_template = match LevelTemplate::parse(LEVELS[level]) {
+            Ok(level_template) => level_template,
+            Err(error) => {
+                println!("Level {}: {}", level, error);
+                continue;
+            }
+        };
+
+        match search(&level_template, node_budget) {
+            Ok((moves, stats)) => {
+                let moves: Vec<String> = moves.into_iter().map(format_move).collect();
+                println!(
+                    "Level {}: {} moves: {}",
+                    level,
+                    moves.len(),
+                    moves.join(", ")
+                );
+                println!(
+                    "  {} states explored, branching factor {:.2}, {} dead ends",
+                    stats.states_explored, stats.branching_factor, stats.dead_ends
+                );
+            }
+            Err(error) => println!("Level {}: {}", level, error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::test_levels::SNAKE_GOALS;
+
+    fn solve_level(level: &str) -> Result<Vec<(i32, IVec2)>, SolverError> {
+        solve(&LevelTemplate::parse(level).unwrap(), DEFAULT_NODE_BUDGET)
+    }
+
+    #[test]
+    pub fn test_solve_shortest() {
+        let moves = solve_level(SNAKE_GOALS).unwrap();
+        assert_eq!(
+            moves,
+            vec![
+                (0, RIGHT),
+                (0, RIGHT),
+                (0, RIGHT),
+                (0, RIGHT),
+                (1, LEFT),
+                (1, LEFT),
+                (1, LEFT),
+                (1, LEFT),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_solve_through_portal() {
+        let moves = solve_level(".aA@.@X\n#######").unwrap();
+        assert_eq!(moves, vec![(0, RIGHT), (0, RIGHT)]);
+    }
+
+    #[test]
+    pub fn test_unsolvable() {
+        assert!(matches!(
+            solve_level("X....\n.....\n.aA..\n#####"),
+            Err(SolverError::Unsolvable(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_analyze_level() {
+        let stats = analyze_level(&LevelTemplate::parse(".aA@.@X\n#######").unwrap()).unwrap();
+        assert_eq!(stats.solution_length, 2);
+        assert!(stats.states_explored >= 3);
+        assert!(stats.branching_factor > 0.0);
+
+        let harder_stats = analyze_level(&LevelTemplate::parse(SNAKE_GOALS).unwrap()).unwrap();
+        assert_eq!(harder_stats.solution_length, 8);
+        assert!(harder_stats.states_explored > stats.states_explored);
+    }
+
+    #[test]
+    pub fn test_dead_ends() {
+        // Moving up into the pocket leaves the snake standing, unable to jump under the ceiling.
+        let level = "########\n####.###\n#X..Aa##\n########";
+        let stats = analyze_level(&LevelTemplate::parse(level).unwrap()).unwrap();
+        assert_eq!(stats.solution_length, 3);
+        assert_eq!(stats.dead_ends, 1);
+    }
+
+    #[test]
+    pub fn test_budget_exceeded() {
+        assert_eq!(
+            solve(&LevelTemplate::parse(SNAKE_GOALS).unwrap(), 2),
+            Err(SolverError::BudgetExceeded(2))
+        );
+    }
+}
@@ -1,20 +1,24 @@
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    gameplay::level_pluggin::spawn_food,
-    gameplay::movement_pluggin::GravityFall,
-    gameplay::snake_pluggin::{set_snake_active, DespawnSnakePartEvent, Snake, SnakePart},
+    gameplay::game_constants_pluggin::KeyBindings,
+    gameplay::level_pluggin::{spawn_food, spawn_shrink_food, Block},
+    gameplay::movement_pluggin::{GravityFall, MoveCommandEvent, MoveInputBuffer},
+    gameplay::snake_pluggin::{
+        set_snake_active, Active, SelectedSnake, Snake, SnakePart, SnakePartBundle,
+    },
     level::level_instance::{LevelEntityType, LevelInstance},
     level::level_template::SnakeTemplate,
 };
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum LevelEntityUpdateEvent {
     ClearPosition(IVec2, LevelEntityType),
     FillPosition(IVec2),
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct BeginFall {
     // The initial position of the snake before falling.
     pub parts: SnakeTemplate,
@@ -24,19 +28,22 @@ pub struct BeginFall {
 }
 
 /// History event marking that a snake stops falling, with distance fallen.
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct EndFall {
     pub walkable_updates: Vec<LevelEntityUpdateEvent>,
 }
 
-#[derive(Clone, Eq, PartialEq, Debug)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum MoveHistoryEvent {
-    /// A history event that marks a player move action.
-    PlayerSnakeMove,
+    /// A history event that marks a player move action, with the direction of the move.
+    PlayerSnakeMove(IVec2),
 
     /// History event for the snake moving one tile in a direction, storing the old tails for undo.
     SnakeMoveForward((IVec2, IVec2)),
 
+    /// History event for the snake head going through a portal, storing the old tail for undo.
+    Teleport((IVec2, IVec2)),
+
     /// History event for moving a snake with an offset fex: pushing.
     PassiveSnakeMove(IVec2),
 
@@ -49,11 +56,24 @@ pub enum MoveHistoryEvent {
     /// History event when a snake eats a food and the food is despawned.
     Eat(IVec2),
 
+    /// History event marking that a snake shrank, storing the removed tail.
+    Shrink((IVec2, IVec2)),
+
+    /// History event when a snake eats a shrink food and the shrink food is despawned.
+    EatShrinkFood(IVec2),
+
     /// History event for a snake exiting the level through the goal.
-    ExitLevel(Entity),
+    /// The snake entity is found from the snake index so that the history can be serialized.
+    ExitLevel,
+
+    /// History event for a block pushed or falling, storing its old and new positions.
+    BlockMove(IVec2, IVec2),
+
+    /// History event for a snake carried by a conveyor or pushed by a carried snake, with the offset of the move.
+    Conveyor(IVec2),
 }
 
-#[derive(Clone)]
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub struct SnakeHistoryEvent {
     pub event: MoveHistoryEvent,
     pub snake_index: i32,
@@ -62,13 +82,79 @@ pub struct SnakeHistoryEvent {
 
 pub struct UndoEvent;
 
-/// A struct storing history events that can be undone.
+/// An undo asked by the player with the undo key or button, it is counted in the undos of the level.
+/// The undos after a snake dies are sent as `UndoEvent` and are not counted.
+pub struct PlayerUndoEvent;
+
+/// Number of moves made by the player in the current level, undone moves are not counted.
+#[derive(Resource, Default)]
+pub struct MoveCount(pub usize);
+
+/// Number of undos asked by the player in the current level, a jump back in the history counts as one.
 #[derive(Resource, Default)]
+pub struct UndoCount(pub usize);
+
+pub struct RedoEvent;
+
+/// Undo the player moves until the given number of moves is left, to jump back in the history.
+pub struct UndoToMoveEvent(pub usize);
+
+/// A struct storing history events that can be undone.
+/// Undone player moves are kept as a snake index and a direction so that they can be redone.
+/// The history can be saved as bytes and restored to resume a level.
+#[derive(Resource, Default, Clone, Serialize, Deserialize)]
 pub struct SnakeHistory {
     pub move_history: Vec<SnakeHistoryEvent>,
+    redo_stack: Vec<(i32, IVec2)>,
 }
 
 impl SnakeHistory {
+    /// Push the marker of a player move.
+    /// Redoing a move consumes it from the redo stack, any other move makes the redo stack obsolete.
+    pub fn push_player_move(&mut self, snake_index: i32, direction: IVec2) {
+        if self.redo_stack.last() == Some(&(snake_index, direction)) {
+            self.redo_stack.pop();
+        } else {
+            self.redo_stack.clear();
+        }
+
+        self.push(MoveHistoryEvent::PlayerSnakeMove(direction), snake_index);
+    }
+
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<SnakeHistory> {
+        bincode::deserialize(bytes)
+    }
+
+    /// The player moves in the history, as snake indices and directions.
+    /// Replaying them from the start of the level rebuilds the snakes state.
+    pub fn player_moves(&self) -> Vec<(i32, IVec2)> {
+        self.move_history
+            .iter()
+            .filter_map(|event| match event.event {
+                MoveHistoryEvent::PlayerSnakeMove(direction) => {
+                    Some((event.snake_index, direction))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// True if there is a player move to undo.
+    pub fn can_undo(&self) -> bool {
+        self.move_history
+            .iter()
+            .any(|event| matches!(event.event, MoveHistoryEvent::PlayerSnakeMove(_)))
+    }
+
+    /// The next move to redo if any, as a snake index and a direction.
+    pub fn next_redo(&self) -> Option<(i32, IVec2)> {
+        self.redo_stack.last().copied()
+    }
+
     pub fn push(&mut self, event: MoveHistoryEvent, snake_index: i32) {
         self.move_history.push(SnakeHistoryEvent {
             event,
@@ -90,34 +176,49 @@ impl SnakeHistory {
         });
     }
 
+    /// Undo the events of the last player move.
+    /// Returns the index of the snake that made the move, None if there was no player move to undo.
     pub fn undo_last(
         &mut self,
-        snakes: &mut [Mut<Snake>],
+        snakes: &mut [(Entity, Mut<Snake>)],
+        blocks: &mut [Mut<Block>],
         level: &mut LevelInstance,
         commands: &mut Commands,
-        despawn_snake_part_event: &mut EventWriter<DespawnSnakePartEvent>,
-    ) {
-        let mut snakes: Vec<&mut Snake> = snakes.iter_mut().map(|snake| snake.as_mut()).collect();
-
+        parts_query: &Query<(Entity, &SnakePart)>,
+    ) -> Option<i32> {
         // Undo the stack until we reach the last player action.
         while let Some(top) = self.move_history.pop() {
-            if MoveHistoryEvent::PlayerSnakeMove == top.event {
-                return;
+            if let MoveHistoryEvent::PlayerSnakeMove(direction) = top.event {
+                self.redo_stack.push((top.snake_index, direction));
+                return Some(top.snake_index);
+            }
+
+            // Blocks are not tied to a snake.
+            if let MoveHistoryEvent::BlockMove(from, to) = top.event {
+                let block = blocks
+                    .iter_mut()
+                    .find(|block| block.0 == to)
+                    .expect("Missing block in query");
+                block.0 = from;
+
+                level.undo_updates(&top.walkable_updates);
+                continue;
             }
 
-            let snake: &mut Snake = snakes
+            let (snake_entity, snake) = snakes
                 .iter_mut()
-                .find(|snake| snake.index() == top.snake_index)
+                .find(|(_, snake)| snake.index() == top.snake_index)
                 .expect("Missing snake in query");
 
             match top.event {
-                MoveHistoryEvent::PlayerSnakeMove => {
+                MoveHistoryEvent::PlayerSnakeMove(_) => {
                     unreachable!("Should be handled as early return above.")
                 }
-                MoveHistoryEvent::SnakeMoveForward(old_tail) => {
+                MoveHistoryEvent::SnakeMoveForward(old_tail)
+                | MoveHistoryEvent::Teleport(old_tail) => {
                     snake.move_back(&old_tail);
                 }
-                MoveHistoryEvent::PassiveSnakeMove(offset) => {
+                MoveHistoryEvent::PassiveSnakeMove(offset) | MoveHistoryEvent::Conveyor(offset) => {
                     snake.translate(-offset);
                 }
                 MoveHistoryEvent::BeginFall(begin) => {
@@ -127,32 +228,58 @@ impl SnakeHistory {
                     };
                 }
                 MoveHistoryEvent::Grow => {
-                    despawn_snake_part_event.send(DespawnSnakePartEvent(SnakePart {
+                    // Despawn the part with the commands of this system rather than with an event,
+                    // the part is gone before the systems of the next stage see the shorter snake.
+                    let tail_part = SnakePart {
                         snake_index: snake.index(),
                         part_index: snake.len() - 1,
-                    }));
+                    };
+                    for (part_entity, part) in parts_query {
+                        if *part == tail_part {
+                            commands.entity(part_entity).despawn_recursive();
+                        }
+                    }
 
                     snake.shrink();
                 }
                 MoveHistoryEvent::Eat(position) => {
                     spawn_food(commands, &position, level);
                 }
-                MoveHistoryEvent::ExitLevel(snake_entity) => {
-                    set_snake_active(commands, snake, snake_entity);
+                MoveHistoryEvent::Shrink(old_tail) => {
+                    snake.restore_tail(old_tail);
+
+                    let part = SnakePartBundle::new(snake.index(), snake.len() - 1);
+                    commands.entity(*snake_entity).with_children(|parent| {
+                        parent.spawn(part);
+                    });
+                }
+                MoveHistoryEvent::EatShrinkFood(position) => {
+                    spawn_shrink_food(commands, &position, level);
+                }
+                MoveHistoryEvent::ExitLevel => {
+                    set_snake_active(commands, snake, *snake_entity);
+                }
+                MoveHistoryEvent::BlockMove(..) => {
+                    unreachable!("Should be handled above.")
                 }
             }
 
             level.undo_updates(&top.walkable_updates);
         }
+
+        None
     }
 }
 
+/// The undo key undoes the last move, shift + the undo key redoes it.
 pub fn keyboard_undo_system(
     keyboard: Res<Input<KeyCode>>,
-    mut trigger_undo_event: EventWriter<UndoEvent>,
+    key_bindings: Res<KeyBindings>,
+    mut player_undo_event: EventWriter<PlayerUndoEvent>,
+    mut trigger_redo_event: EventWriter<RedoEvent>,
     falling_snakes: Query<(With<Snake>, With<GravityFall>)>,
 ) {
-    if !keyboard.just_pressed(KeyCode::Back) {
+    if !keyboard.any_just_pressed(key_bindings.undo.iter().copied()) {
         return;
     }
 
@@ -160,31 +287,306 @@ pub fn keyboard_undo_system(
         return;
     }
 
+    if keyboard.any_pressed([KeyCode::LShift, KeyCode::RShift]) {
+        trigger_redo_event.send(RedoEvent);
+    } else {
+        player_undo_event.send(PlayerUndoEvent);
+    }
+}
+
+/// Count the undos of the player and undo the last move.
+pub fn player_undo_system(
+    mut player_undo_event: EventReader<PlayerUndoEvent>,
+    snake_history: Res<SnakeHistory>,
+    mut undo_count: ResMut<UndoCount>,
+    mut trigger_undo_event: EventWriter<UndoEvent>,
+) {
+    if player_undo_event.iter().next().is_none() {
+        return;
+    }
+
+    if !snake_history.move_history.is_empty() {
+        undo_count.0 += 1;
+    }
     trigger_undo_event.send(UndoEvent);
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn undo_event_system(
     mut trigger_undo_event: EventReader<UndoEvent>,
     mut snake_history: ResMut<SnakeHistory>,
+    mut move_count: ResMut<MoveCount>,
     mut level: ResMut<LevelInstance>,
-    mut despawn_snake_part_event: EventWriter<DespawnSnakePartEvent>,
+    mut move_input_buffer: ResMut<MoveInputBuffer>,
     mut commands: Commands,
-    mut query: Query<&mut Snake>,
+    mut query: Query<(Entity, &mut Snake)>,
+    mut blocks_query: Query<&mut Block>,
+    parts_query: Query<(Entity, &SnakePart)>,
+    selected_snake_query: Query<Entity, With<SelectedSnake>>,
 ) {
     if trigger_undo_event.iter().next().is_none() {
         return;
     }
 
+    // A move buffered before the undo would be replayed on the restored snakes.
+    move_input_buffer.0 = None;
+
     if snake_history.move_history.is_empty() {
         return;
     }
 
-    let mut snakes: Vec<Mut<Snake>> = query.iter_mut().collect();
+    let mut snakes: Vec<(Entity, Mut<Snake>)> = query.iter_mut().collect();
+    let mut blocks: Vec<Mut<Block>> = blocks_query.iter_mut().collect();
 
-    snake_history.undo_last(
+    let Some(snake_index) = snake_history.undo_last(
         &mut snakes,
+        &mut blocks,
         &mut level,
         &mut commands,
-        &mut despawn_snake_part_event,
-    );
+        &parts_query,
+    ) else {
+        return;
+    };
+
+    move_count.0 = move_count.0.saturating_sub(1);
+
+    // The snake that made the move was selected when it moved, even if it exited the level since.
+    let moved_snake = snakes
+        .iter()
+        .find(|(_, snake)| snake.index() == snake_index)
+        .map(|(entity, _)| *entity);
+    let Some(snake_entity) = moved_snake else {
+        return;
+    };
+
+    if selected_snake_query.contains(snake_entity) {
+        return;
+    }
+
+    for entity in &selected_snake_query {
+        commands.entity(entity).remove::<SelectedSnake>();
+    }
+    commands.entity(snake_entity).insert(SelectedSnake);
+}
+
+/// Undo one move per frame until the history is back to the requested number of player moves.
+pub fn undo_to_move_event_system(
+    mut undo_to_move_event: EventReader<UndoToMoveEvent>,
+    mut target_move_count: Local<Option<usize>>,
+    snake_history: Res<SnakeHistory>,
+    mut undo_count: ResMut<UndoCount>,
+    mut trigger_undo_event: EventWriter<UndoEvent>,
+) {
+    if let Some(event) = undo_to_move_event.iter().last() {
+        *target_move_count = Some(event.0);
+        if snake_history.player_moves().len() > event.0 {
+            undo_count.0 += 1;
+        }
+    }
+
+    let Some(move_count) = *target_move_count else {
+        return;
+    };
+
+    if snake_history.player_moves().len() > move_count {
+        trigger_undo_event.send(UndoEvent);
+    } else {
+        *target_move_count = None;
+    }
+}
+
+/// Redo the last undone move by replaying it, the snake that moved is selected first.
+/// Selecting a snake takes effect on the next frame so the redo stays pending until then.
+pub fn redo_event_system(
+    mut trigger_redo_event: EventReader<RedoEvent>,
+    mut pending_redo: Local<bool>,
+    snake_history: Res<SnakeHistory>,
+    mut move_command_event: EventWriter<MoveCommandEvent>,
+    mut commands: Commands,
+    snakes_query: Query<(Entity, &Snake, Option<&SelectedSnake>), With<Active>>,
+) {
+    if trigger_redo_event.iter().next().is_some() {
+        *pending_redo = true;
+    }
+
+    if !*pending_redo {
+        return;
+    }
+
+    let Some((snake_index, direction)) = snake_history.next_redo() else {
+        *pending_redo = false;
+        return;
+    };
+
+    let Some((snake_entity, _, selected)) = snakes_query
+        .iter()
+        .find(|(_, snake, _)| snake.index() == snake_index) else {
+        *pending_redo = false;
+        return;
+    };
+
+    if selected.is_some() {
+        move_command_event.send(MoveCommandEvent(direction));
+        *pending_redo = false;
+        return;
+    }
+
+    for (entity, _, selected) in &snakes_query {
+        if selected.is_some() {
+            commands.entity(entity).remove::<SelectedSnake>();
+        }
+    }
+    commands.entity(snake_entity).insert(SelectedSnake);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+
+    use super::*;
+    use crate::{
+        gameplay::{
+            game_constants_pluggin::RIGHT,
+            game_logic::{GameLogic, MoveOutcome},
+        },
+        level::{level_template::LevelTemplate, test_levels::PUSH_CHAIN},
+    };
+
+    /// An app running the undo systems on the level and history of the game, the snakes are left to spawn.
+    fn undo_app(game: &mut GameLogic) -> App {
+        let mut app = App::new();
+        app.add_event::<UndoEvent>()
+            .add_event::<PlayerUndoEvent>()
+            .insert_resource(game.level.clone())
+            .insert_resource(mem::take(&mut game.history))
+            .init_resource::<MoveCount>()
+            .init_resource::<UndoCount>()
+            .init_resource::<MoveInputBuffer>()
+            .add_system(player_undo_system)
+            .add_system(undo_event_system.after(player_undo_system));
+        app
+    }
+
+    /// Move the first snake then undo the move with the undo system, the snake and the level should be restored exactly
+    /// and the move buffered before the undo dropped.
+    fn assert_undo_restores_state(level: &str, direction: IVec2) {
+        let mut game = GameLogic::new(&LevelTemplate::parse(level).unwrap());
+        let initial_parts = game.snakes[0].parts().clone();
+        let initial_cells = game.level.occupied_cells().clone();
+
+        let (outcome, _) = game.player_move(0, direction);
+        assert!(matches!(outcome, MoveOutcome::Moved { .. }));
+
+        let mut app = undo_app(&mut game);
+        app.insert_resource(MoveInputBuffer(Some(direction)));
+        app.world.spawn(game.snakes[0].clone());
+
+        app.world.send_event(UndoEvent);
+        app.update();
+
+        let mut snakes = app.world.query::<&Snake>();
+        assert_eq!(snakes.single(&app.world).parts(), &initial_parts);
+        assert_eq!(
+            app.world.resource::<LevelInstance>().occupied_cells(),
+            &initial_cells
+        );
+        assert_eq!(app.world.resource::<MoveInputBuffer>().0, None);
+    }
+
+    #[test]
+    pub fn test_history_bytes_round_trip() {
+        let mut game = GameLogic::new(&LevelTemplate::parse(PUSH_CHAIN).unwrap());
+        game.player_move(0, RIGHT);
+        game.player_move(0, RIGHT);
+
+        let bytes = game.history.to_bytes().unwrap();
+        let history = SnakeHistory::from_bytes(&bytes).unwrap();
+
+        assert_eq!(history.move_history, game.history.move_history);
+        assert_eq!(history.player_moves(), vec![(0, RIGHT), (0, RIGHT)]);
+    }
+
+    #[test]
+    pub fn test_undo_eat() {
+        assert_undo_restores_state("....X\n.aAo.\n#####", RIGHT);
+    }
+
+    #[test]
+    pub fn test_undo_shrink() {
+        assert_undo_restores_state("....X\naaA*.\n#####", RIGHT);
+    }
+
+    #[test]
+    pub fn test_only_player_undos_are_counted() {
+        let mut game = GameLogic::new(&LevelTemplate::parse(PUSH_CHAIN).unwrap());
+        game.player_move(0, RIGHT);
+        game.player_move(0, RIGHT);
+
+        let mut app = undo_app(&mut game);
+        app.world.spawn(game.snakes[0].clone());
+
+        // The undo after a snake dies is not counted.
+        app.world.send_event(UndoEvent);
+        app.update();
+        assert_eq!(app.world.resource::<UndoCount>().0, 0);
+
+        app.world.send_event(PlayerUndoEvent);
+        app.update();
+        assert_eq!(app.world.resource::<UndoCount>().0, 1);
+        let history = app.world.resource::<SnakeHistory>();
+        assert!(history.player_moves().is_empty());
+    }
+
+    #[test]
+    pub fn test_undo_selects_moved_snake() {
+        let mut game = GameLogic::new(&LevelTemplate::parse("......X\naA..Bb.\n#######").unwrap());
+        game.player_move(0, RIGHT);
+
+        let mut app = undo_app(&mut game);
+        let moved_snake = app.world.spawn(game.snakes[0].clone()).id();
+        let other_snake = app
+            .world
+            .spawn((game.snakes[1].clone(), SelectedSnake))
+            .id();
+
+        app.world.send_event(UndoEvent);
+        app.update();
+
+        assert!(app.world.entity(moved_snake).contains::<SelectedSnake>());
+        assert!(!app.world.entity(other_snake).contains::<SelectedSnake>());
+    }
+
+    #[test]
+    pub fn test_undo_grow_despawns_part_in_same_frame() {
+        let mut game = GameLogic::new(&LevelTemplate::parse("......X\n.aAoo..\n#######").unwrap());
+        game.player_move(0, RIGHT);
+        game.player_move(0, RIGHT);
+        assert_eq!(game.snakes[0].len(), 4);
+
+        let mut app = undo_app(&mut game);
+        let snake_entity = app.world.spawn(game.snakes[0].clone()).id();
+        for part_index in 0..game.snakes[0].len() {
+            let part = app
+                .world
+                .spawn(SnakePart {
+                    snake_index: 0,
+                    part_index,
+                })
+                .id();
+            app.world.entity_mut(snake_entity).push_children(&[part]);
+        }
+
+        // After each frame the parts match the snake, no part indexes past its end.
+        for _ in 0..2 {
+            app.world.send_event(UndoEvent);
+            app.update();
+
+            let snake_len = app.world.query::<&Snake>().single(&app.world).len();
+            let mut parts = app.world.query::<&SnakePart>();
+            assert_eq!(parts.iter(&app.world).count(), snake_len);
+            assert!(parts
+                .iter(&app.world)
+                .all(|part| part.part_index < snake_len));
+        }
+    }
 }
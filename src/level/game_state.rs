@@ -0,0 +1,71 @@
+use bevy::prelude::*;
+
+use crate::{
+    gameplay::snake_pluggin::Snake,
+    level::level_instance::{LevelEntityType, LevelInstance},
+};
+
+/// The state of a level that changes while playing, the walls and other static cells are not part of it.
+/// The content is sorted so that equal boards are equal and hash equally whatever the order of the entities.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GameState {
+    /// The parts of the snakes left in the level with their direction, ordered by snake index.
+    snakes: Vec<(i32, Vec<(IVec2, IVec2)>)>,
+    food: Vec<IVec2>,
+    shrink_food: Vec<IVec2>,
+    blocks: Vec<IVec2>,
+}
+
+impl GameState {
+    pub fn new<'a>(level: &LevelInstance, snakes: impl IntoIterator<Item = &'a Snake>) -> Self {
+        let mut snakes: Vec<(i32, Vec<(IVec2, IVec2)>)> = snakes
+            .into_iter()
+            .map(|snake| (snake.index(), snake.parts().iter().copied().collect()))
+            .collect();
+        snakes.sort_by_key(|(snake_index, _)| *snake_index);
+
+        let positions_of = |entity_type: LevelEntityType| {
+            let mut positions: Vec<IVec2> = level.positions_of(entity_type).collect();
+            positions.sort_by_key(|position| (position.x, position.y));
+            positions
+        };
+
+        GameState {
+            snakes,
+            food: positions_of(LevelEntityType::Food),
+            shrink_food: positions_of(LevelEntityType::ShrinkFood),
+            blocks: positions_of(LevelEntityType::Block),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gameplay::game_constants_pluggin::RIGHT,
+        gameplay::game_logic::GameLogic,
+        level::{level_template::LevelTemplate, test_levels::PUSH_CHAIN},
+    };
+
+    #[test]
+    pub fn test_snake_order_is_normalized() {
+        let game = GameLogic::new(&LevelTemplate::parse(PUSH_CHAIN).unwrap());
+
+        let state = GameState::new(&game.level, &game.snakes);
+        let reversed_state = GameState::new(&game.level, game.snakes.iter().rev());
+        assert_eq!(state, reversed_state);
+    }
+
+    #[test]
+    pub fn test_moves_change_state() {
+        let mut game = GameLogic::new(&LevelTemplate::parse("....X\n.aAo.\n#####").unwrap());
+        let initial_state = GameState::new(&game.level, &game.snakes);
+
+        game.player_move(0, RIGHT);
+        let state = GameState::new(&game.level, &game.snakes);
+        assert_ne!(state, initial_state);
+        assert!(state.food.is_empty());
+        assert_eq!(state.snakes[0].1.len(), 3);
+    }
+}
@@ -0,0 +1,580 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use game_grid::*;
+
+const NEIGHBOR_OFFSETS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+const SURROUNDING_OFFSETS: [IVec2; 8] = [
+    IVec2::new(-1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 0),
+    IVec2::new(1, 0),
+    IVec2::new(-1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(1, 1),
+];
+
+/// Helpers on top of `game_grid::Grid` used by the level code.
+pub trait GridExt<Cell> {
+    /// Create a new grid of the same size by transforming each cell, the original grid is left intact.
+    /// Panics if the grid is empty, there is no cell to create the new grid from.
+    fn map<U, F>(&self, f: F) -> Grid<U>
+    where
+        U: GridCell,
+        F: Fn(Cell) -> U;
+
+    /// Position of the first cell matching the predicate.
+    fn find(&self, predicate: impl Fn(&Cell) -> bool) -> Option<IVec2>;
+
+    /// Positions of all the cells matching the predicate.
+    fn find_all(&self, predicate: impl Fn(&Cell) -> bool) -> Vec<IVec2>;
+
+    /// Number of cells matching the predicate.
+    fn count(&self, predicate: impl Fn(&Cell) -> bool) -> usize;
+
+    /// Mutable access to a single cell, None if the position is out of bounds.
+    fn get_mut(&mut self, position: IVec2) -> Option<&mut Cell>;
+
+    /// Iterate over the cells with their positions, the cells can be modified in place.
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (IVec2, &mut Cell)> + '_>;
+
+    /// Iterate over the rows from y = 0, each row is the slice of its cells in x order.
+    fn rows(&self) -> Box<dyn Iterator<Item = &[Cell]> + '_>;
+
+    /// Swap the content of two cells, None and the grid unchanged if any of the positions is out of bounds.
+    fn swap(&mut self, a: IVec2, b: IVec2) -> Option<()>;
+
+    /// Render the grid as text with a custom char per cell, rows are separated by a new line like `Display`.
+    fn format_with(&self, f: impl Fn(Cell, IVec2) -> char) -> String;
+
+    /// Create a larger grid with a border of `value` cells around the content,
+    /// each cell keeps its position offset by `border` on both axes.
+    ///
+    /// ```
+    /// use bird_snake::level::{grid_ext::GridExt, level_template::Cell};
+    /// use game_grid::Grid;
+    ///
+    /// let grid = "#o\n+#".parse::<Grid<Cell>>().unwrap();
+    /// let padded = grid.pad(1, Cell::Empty);
+    ///
+    /// assert_eq!((padded.width(), padded.height()), (4, 4));
+    /// assert_eq!(padded.to_string(), "    \n #o \n +# \n    ");
+    /// ```
+    fn pad(&self, border: usize, value: Cell) -> Grid<Cell>;
+
+    /// Create a grid with the width and height swapped, the cell at (x, y) moves to (y, x).
+    /// Combined with `flip_x` and `flip_y` it gives the rotations and mirrors of the grid.
+    fn transpose(&self) -> Grid<Cell>;
+
+    /// Crop or extend the grid to the new size, new cells are set to `value`.
+    /// Cells keep their position, content outside the new size is lost.
+    fn resize(&mut self, width: usize, height: usize, value: Cell);
+
+    /// Copy the inclusive rectangle between `min` and `max` into a new grid, the rectangle is clamped to the grid bounds.
+    /// Returns None if the rectangle is inverted or does not overlap the grid.
+    fn sub_grid(&self, min: IVec2, max: IVec2) -> Option<Grid<Cell>>;
+
+    /// The 4-connected positions reachable from `seed` through cells matching the predicate, `seed` included.
+    /// Empty if the seed is out of bounds or doesn't match the predicate.
+    fn flood_fill(&self, seed: IVec2, predicate: impl Fn(&Cell) -> bool) -> Vec<IVec2>;
+
+    /// The shortest 4-connected path from `start` to `goal` through passable cells, both ends included.
+    /// None if there is no path or if one of the ends is out of bounds or not passable.
+    fn shortest_path(
+        &self,
+        start: IVec2,
+        goal: IVec2,
+        passable: impl Fn(&Cell) -> bool,
+    ) -> Option<Vec<IVec2>>;
+
+    /// One step of a cellular automaton, each cell of the new grid is the rule applied to the cell
+    /// and its in-bounds surrounding cells, diagonals included. The original grid is left intact.
+    ///
+    /// Conway's Game of Life with the walls as the living cells, a blinker turns from a row to a column:
+    /// ```
+    /// use bird_snake::level::{grid_ext::GridExt, level_template::Cell};
+    /// use game_grid::Grid;
+    ///
+    /// let life = |cell: Cell, neighbors: &[Cell]| {
+    ///     let alive = neighbors.iter().filter(|cell| **cell == Cell::Wall).count();
+    ///     match (cell, alive) {
+    ///         (Cell::Wall, 2) | (_, 3) => Cell::Wall,
+    ///         _ => Cell::Empty,
+    ///     }
+    /// };
+    ///
+    /// let blinker = "...\n###\n...".parse::<Grid<Cell>>().unwrap();
+    /// assert_eq!(blinker.step(life).to_string(), " # \n # \n # ");
+    /// ```
+    fn step(&self, rule: impl Fn(Cell, &[Cell]) -> Cell) -> Grid<Cell>;
+}
+
+impl<Cell: GridCell + Copy> GridExt<Cell> for Grid<Cell> {
+    fn map<U, F>(&self, f: F) -> Grid<U>
+    where
+        U: GridCell,
+        F: Fn(Cell) -> U,
+    {
+        let mut cells = self.cells().map(|cell| f(*cell));
+        let first_cell = cells.next().expect("Can't map an empty grid.");
+
+        // The cells are stored row after row in both grids.
+        let mut grid = Grid::new(self.width(), self.height(), first_cell);
+        for (new_cell, cell) in grid.mut_cells().into_iter().skip(1).zip(cells) {
+            *new_cell = cell;
+        }
+        grid
+    }
+
+    fn find(&self, predicate: impl Fn(&Cell) -> bool) -> Option<IVec2> {
+        self.iter()
+            .find(|(_, cell)| predicate(cell))
+            .map(|(position, _)| position)
+    }
+
+    fn find_all(&self, predicate: impl Fn(&Cell) -> bool) -> Vec<IVec2> {
+        self.iter()
+            .filter(|(_, cell)| predicate(cell))
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    fn count(&self, predicate: impl Fn(&Cell) -> bool) -> usize {
+        self.cells().filter(|cell| predicate(cell)).count()
+    }
+
+    fn get_mut(&mut self, position: IVec2) -> Option<&mut Cell> {
+        if !self.is_in_bounds(position) {
+            return None;
+        }
+
+        let index = self.index_for_position(position);
+        self.mut_cells().into_iter().into_slice().get_mut(index)
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (IVec2, &mut Cell)> + '_> {
+        // The cells are stored row after row.
+        let width = self.width();
+        Box::new(
+            self.mut_cells()
+                .into_iter()
+                .enumerate()
+                .map(move |(index, cell)| {
+                    let position = IVec2::new((index % width) as i32, (index / width) as i32);
+                    (position, cell)
+                }),
+        )
+    }
+
+    fn rows(&self) -> Box<dyn Iterator<Item = &[Cell]> + '_> {
+        // The cells are stored row after row.
+        Box::new(self.cells().as_slice().chunks(self.width()))
+    }
+
+    fn swap(&mut self, a: IVec2, b: IVec2) -> Option<()> {
+        if !self.is_in_bounds(a) || !self.is_in_bounds(b) {
+            return None;
+        }
+
+        let cell_a = self.cell_at(a);
+        let cell_b = self.cell_at(b);
+        self.set_cell(a, cell_b);
+        self.set_cell(b, cell_a);
+        Some(())
+    }
+
+    fn format_with(&self, f: impl Fn(Cell, IVec2) -> char) -> String {
+        let mut output = String::with_capacity((self.width() + 1) * self.height());
+        for (y, row) in self.rows().enumerate() {
+            if y > 0 {
+                output.push('\n');
+            }
+
+            for (x, cell) in row.iter().enumerate() {
+                output.push(f(*cell, IVec2::new(x as i32, y as i32)));
+            }
+        }
+        output
+    }
+
+    fn pad(&self, border: usize, value: Cell) -> Grid<Cell> {
+        let mut grid = Grid::new(self.width() + 2 * border, self.height() + 2 * border, value);
+
+        let offset = IVec2::splat(border as i32);
+        for (position, cell) in self.iter::<IVec2>() {
+            grid.set_cell(position + offset, cell);
+        }
+        grid
+    }
+
+    fn transpose(&self) -> Grid<Cell> {
+        let Some(first_cell) = self.cells().next() else {
+            return self.clone();
+        };
+
+        let mut grid = Grid::new(self.height(), self.width(), *first_cell);
+        for (position, cell) in self.iter::<IVec2>() {
+            grid.set_cell(IVec2::new(position.y, position.x), cell);
+        }
+        grid
+    }
+
+    fn resize(&mut self, width: usize, height: usize, value: Cell) {
+        let mut grid = Grid::new(width, height, value);
+        for (position, cell) in self.iter::<IVec2>() {
+            if grid.is_in_bounds(position) {
+                grid.set_cell(position, cell);
+            }
+        }
+        *self = grid;
+    }
+
+    fn sub_grid(&self, min: IVec2, max: IVec2) -> Option<Grid<Cell>> {
+        let min = min.max(IVec2::ZERO);
+        let max = max.min(IVec2::new(
+            self.width() as i32 - 1,
+            self.height() as i32 - 1,
+        ));
+
+        if min.x > max.x || min.y > max.y {
+            return None;
+        }
+
+        let size = max - min + IVec2::ONE;
+        let mut grid = Grid::new(size.x as usize, size.y as usize, self.cell_at(min));
+        for y in 0..size.y {
+            for x in 0..size.x {
+                let position = IVec2::new(x, y);
+                grid.set_cell(position, self.cell_at(min + position));
+            }
+        }
+        Some(grid)
+    }
+
+    fn flood_fill(&self, seed: IVec2, predicate: impl Fn(&Cell) -> bool) -> Vec<IVec2> {
+        let is_open =
+            |position: IVec2| self.is_in_bounds(position) && predicate(&self.cell_at(position));
+
+        if !is_open(seed) {
+            return vec![];
+        }
+
+        let mut region = vec![];
+        let mut visited = HashSet::from([seed]);
+        let mut stack = vec![seed];
+        while let Some(position) = stack.pop() {
+            region.push(position);
+
+            for offset in NEIGHBOR_OFFSETS {
+                let neighbor = position + offset;
+                if is_open(neighbor) && visited.insert(neighbor) {
+                    stack.push(neighbor);
+                }
+            }
+        }
+        region
+    }
+
+    fn shortest_path(
+        &self,
+        start: IVec2,
+        goal: IVec2,
+        passable: impl Fn(&Cell) -> bool,
+    ) -> Option<Vec<IVec2>> {
+        let is_open =
+            |position: IVec2| self.is_in_bounds(position) && passable(&self.cell_at(position));
+
+        if !is_open(start) || !is_open(goal) {
+            return None;
+        }
+
+        // Breadth first search, the parent of each visited position is kept to rebuild the path.
+        let mut parents = HashMap::from([(start, start)]);
+        let mut queue = VecDeque::from([start]);
+        while let Some(position) = queue.pop_front() {
+            if position == goal {
+                let mut path = vec![goal];
+                let mut current = goal;
+                while current != start {
+                    current = parents[&current];
+                    path.push(current);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            for offset in NEIGHBOR_OFFSETS {
+                let neighbor = position + offset;
+                if is_open(neighbor) && !parents.contains_key(&neighbor) {
+                    parents.insert(neighbor, position);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn step(&self, rule: impl Fn(Cell, &[Cell]) -> Cell) -> Grid<Cell> {
+        let mut grid = self.clone();
+        let mut neighbors = Vec::with_capacity(SURROUNDING_OFFSETS.len());
+        for (position, cell) in self.iter::<IVec2>() {
+            neighbors.clear();
+            neighbors.extend(
+                SURROUNDING_OFFSETS
+                    .iter()
+                    .map(|offset| position + *offset)
+                    .filter(|neighbor| self.is_in_bounds(*neighbor))
+                    .map(|neighbor| self.cell_at(neighbor)),
+            );
+
+            grid.set_cell(position, rule(cell, &neighbors));
+        }
+        grid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::level_template::Cell;
+
+    /// A cell type without default value, grids can be mapped to it.
+    #[derive(GridCell, Copy, Clone, Debug, PartialEq, Eq)]
+    enum Solid {
+        #[cell('#')]
+        Solid,
+
+        #[cell('.')]
+        Open,
+    }
+
+    #[test]
+    pub fn test_map() {
+        let grid = "#o.\n+#X".parse::<Grid<Cell>>().unwrap();
+        let solid = grid.map(|cell| match cell {
+            Cell::Wall | Cell::Spike(_) => Solid::Solid,
+            _ => Solid::Open,
+        });
+
+        assert_eq!(solid.width(), 3);
+        assert_eq!(solid.height(), 2);
+        for (position, cell) in grid.iter::<IVec2>() {
+            let expected = if matches!(cell, Cell::Wall | Cell::Spike(_)) {
+                Solid::Solid
+            } else {
+                Solid::Open
+            };
+            assert_eq!(solid.cell_at(position), expected, "{}", position);
+        }
+        assert_eq!(solid.to_string(), "#..\n##.");
+    }
+
+    #[test]
+    pub fn test_find() {
+        let grid = "#o.\n+#o".parse::<Grid<Cell>>().unwrap();
+
+        // The cells are searched row after row from y = 0.
+        assert_eq!(
+            grid.find(|cell| *cell == Cell::Food),
+            Some(IVec2::new(1, 0))
+        );
+        assert_eq!(grid.find(|cell| *cell == Cell::Goal), None);
+    }
+
+    #[test]
+    pub fn test_find_all() {
+        let grid = "#o.\n+#o".parse::<Grid<Cell>>().unwrap();
+
+        assert_eq!(
+            grid.find_all(|cell| *cell == Cell::Wall),
+            vec![IVec2::new(0, 0), IVec2::new(1, 1)]
+        );
+        assert!(grid.find_all(|cell| *cell == Cell::Goal).is_empty());
+    }
+
+    #[test]
+    pub fn test_get_mut() {
+        let mut grid = "#o.\n+#X".parse::<Grid<Cell>>().unwrap();
+
+        *grid.get_mut(IVec2::new(2, 1)).unwrap() = Cell::Food;
+        assert_eq!(grid.to_string(), "#o \n+#o");
+
+        assert!(grid.get_mut(IVec2::new(3, 0)).is_none());
+        assert!(grid.get_mut(IVec2::new(0, 2)).is_none());
+        assert!(grid.get_mut(IVec2::new(-1, 0)).is_none());
+    }
+
+    #[test]
+    pub fn test_swap() {
+        let mut grid = "#o.\n+#X".parse::<Grid<Cell>>().unwrap();
+
+        assert_eq!(grid.swap(IVec2::new(1, 0), IVec2::new(2, 1)), Some(()));
+        assert_eq!(grid.to_string(), "#X \n+#o");
+
+        assert_eq!(grid.swap(IVec2::new(0, 0), IVec2::new(0, 0)), Some(()));
+        assert_eq!(grid.to_string(), "#X \n+#o");
+
+        assert_eq!(grid.swap(IVec2::new(0, 0), IVec2::new(3, 0)), None);
+        assert_eq!(grid.swap(IVec2::new(0, -1), IVec2::new(1, 1)), None);
+        assert_eq!(grid.to_string(), "#X \n+#o");
+    }
+
+    #[test]
+    pub fn test_iter_mut() {
+        let mut grid = "#o.\n+#X".parse::<Grid<Cell>>().unwrap();
+        let expected: Vec<(IVec2, Cell)> = grid.iter().collect();
+
+        let positions: Vec<(IVec2, Cell)> = grid
+            .iter_mut()
+            .map(|(position, cell)| (position, *cell))
+            .collect();
+        assert_eq!(positions, expected);
+
+        for (position, cell) in grid.iter_mut() {
+            if position.x == 0 {
+                *cell = Cell::Empty;
+            }
+        }
+        assert_eq!(grid.to_string(), " o.\n #X");
+    }
+
+    #[test]
+    pub fn test_rows() {
+        let grid = "#o.\n+#X".parse::<Grid<Cell>>().unwrap();
+
+        let rows: Vec<&[Cell]> = grid.rows().collect();
+        assert_eq!(rows.len(), grid.height());
+        assert_eq!(rows[0], &[Cell::Wall, Cell::Food, Cell::Empty]);
+        assert_eq!(rows[1], &[Cell::Spike('+'), Cell::Wall, Cell::Goal]);
+    }
+
+    #[test]
+    pub fn test_format_with() {
+        let grid = "#o.\n+#X".parse::<Grid<Cell>>().unwrap();
+
+        // One line per row from y = 0, the walls are written as their x coordinate.
+        let text = grid.format_with(|cell, position| match cell {
+            Cell::Empty => '.',
+            Cell::Wall => char::from(b'0' + position.x as u8),
+            _ => char::from(cell),
+        });
+        assert_eq!(text, "0o.\n+1X");
+    }
+
+    #[test]
+    pub fn test_pad() {
+        let grid = "#o\n+#".parse::<Grid<Cell>>().unwrap();
+        let padded = grid.pad(1, Cell::Empty);
+
+        assert_eq!(padded.width(), 4);
+        assert_eq!(padded.height(), 4);
+        assert_eq!(padded.to_string(), "    \n #o \n +# \n    ");
+    }
+
+    #[test]
+    pub fn test_transpose() {
+        let grid = "#o.\n+#X".parse::<Grid<Cell>>().unwrap();
+        let transposed = grid.transpose();
+
+        assert_eq!(transposed.width(), 2);
+        assert_eq!(transposed.height(), 3);
+        assert_eq!(transposed.to_string(), "#+\no#\n X");
+        assert_eq!(transposed.transpose().to_string(), grid.to_string());
+    }
+
+    #[test]
+    pub fn test_resize() {
+        let mut grid = "#o\n+#".parse::<Grid<Cell>>().unwrap();
+
+        grid.resize(3, 1, Cell::Wall);
+        assert_eq!(grid.width(), 3);
+        assert_eq!(grid.height(), 1);
+        assert_eq!(grid.to_string(), "#o#");
+    }
+
+    #[test]
+    pub fn test_sub_grid() {
+        let grid = "#o.\n+#X\n...".parse::<Grid<Cell>>().unwrap();
+
+        let sub_grid = grid.sub_grid(IVec2::new(1, 0), IVec2::new(2, 1)).unwrap();
+        assert_eq!(sub_grid.width(), 2);
+        assert_eq!(sub_grid.height(), 2);
+        assert_eq!(sub_grid.to_string(), "o \n#X");
+
+        let clamped = grid.sub_grid(IVec2::new(-5, 2), IVec2::new(5, 5)).unwrap();
+        assert_eq!(clamped.width(), 3);
+        assert_eq!(clamped.height(), 1);
+
+        assert!(grid.sub_grid(IVec2::new(2, 2), IVec2::new(1, 1)).is_none());
+    }
+
+    #[test]
+    pub fn test_flood_fill() {
+        let grid = "..#.\n###.\n....".parse::<Grid<Cell>>().unwrap();
+        let is_empty = |cell: &Cell| *cell == Cell::Empty;
+
+        let mut region = grid.flood_fill(IVec2::new(0, 0), is_empty);
+        region.sort_by_key(|position| (position.x, position.y));
+        assert_eq!(region, vec![IVec2::new(0, 0), IVec2::new(1, 0)]);
+
+        assert_eq!(grid.flood_fill(IVec2::new(3, 0), is_empty).len(), 6);
+        assert!(grid.flood_fill(IVec2::new(2, 0), is_empty).is_empty());
+        assert!(grid.flood_fill(IVec2::new(-1, 0), is_empty).is_empty());
+    }
+
+    #[test]
+    pub fn test_shortest_path() {
+        let maze = ".#...\n.#.#.\n...#.\n####.".parse::<Grid<Cell>>().unwrap();
+        let is_empty = |cell: &Cell| *cell == Cell::Empty;
+
+        let path = maze
+            .shortest_path(IVec2::new(0, 0), IVec2::new(4, 3), is_empty)
+            .unwrap();
+        assert_eq!(path.len(), 12);
+        assert_eq!(path.first(), Some(&IVec2::new(0, 0)));
+        assert_eq!(path.last(), Some(&IVec2::new(4, 3)));
+        assert!(path.windows(2).all(|step| {
+            let offset = (step[1] - step[0]).abs();
+            offset.x + offset.y == 1
+        }));
+
+        assert_eq!(
+            maze.shortest_path(IVec2::new(0, 0), IVec2::new(0, 0), is_empty),
+            Some(vec![IVec2::new(0, 0)])
+        );
+        assert!(maze
+            .shortest_path(IVec2::new(0, 0), IVec2::new(1, 0), is_empty)
+            .is_none());
+
+        let walled = "..#..".parse::<Grid<Cell>>().unwrap();
+        assert!(walled
+            .shortest_path(IVec2::new(0, 0), IVec2::new(4, 0), is_empty)
+            .is_none());
+    }
+
+    #[test]
+    pub fn test_step_game_of_life() {
+        // Walls are the living cells, a blinker oscillates between a row and a column.
+        let life = |cell: Cell, neighbors: &[Cell]| {
+            let alive = neighbors.iter().filter(|cell| **cell == Cell::Wall).count();
+            match (cell, alive) {
+                (Cell::Wall, 2) | (_, 3) => Cell::Wall,
+                _ => Cell::Empty,
+            }
+        };
+
+        let blinker = ".....\n.....\n.###.\n.....\n....."
+            .parse::<Grid<Cell>>()
+            .unwrap();
+
+        let next = blinker.step(life);
+        assert_eq!(next.to_string(), "     \n  #  \n  #  \n  #  \n     ");
+        assert_eq!(next.step(life).to_string(), blinker.to_string());
+    }
+}
@@ -0,0 +1,120 @@
+use std::str::FromStr;
+
+use game_grid::*;
+use thiserror::Error;
+
+/// Layers are separated by an empty line, a line of spaces is a row of the grid.
+const LAYER_SEPARATOR: &str = "\n\n";
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum GridStackError {
+    #[error("Expected {expected} grid layers, found {found}.")]
+    LayerCount { expected: usize, found: usize },
+
+    #[error("Invalid grid in layer {0}: {1}")]
+    InvalidGrid(usize, String),
+
+    #[error("Layer {layer} is {width}x{height} but the first layer is {expected_width}x{expected_height}.")]
+    SizeMismatch {
+        layer: usize,
+        width: usize,
+        height: usize,
+        expected_width: usize,
+        expected_height: usize,
+    },
+}
+
+/// Aligned grids of different cell types parsed from a single string, like a terrain and an entity layer.
+pub struct GridStack;
+
+impl GridStack {
+    /// Parse two layers separated by an empty line, both layers must have the same size.
+    pub fn parse<A, B>(text: &str) -> Result<(Grid<A>, Grid<B>), GridStackError>
+    where
+        Grid<A>: FromStr,
+        Grid<B>: FromStr,
+        <Grid<A> as FromStr>::Err: ToString,
+        <Grid<B> as FromStr>::Err: ToString,
+    {
+        let text = text.replace("\r\n", "\n");
+        let layers: Vec<&str> = text.split(LAYER_SEPARATOR).collect();
+        if layers.len() != 2 {
+            return Err(GridStackError::LayerCount {
+                expected: 2,
+                found: layers.len(),
+            });
+        }
+
+        let first = parse_layer::<A>(layers[0], 0)?;
+        let second = parse_layer::<B>(layers[1], 1)?;
+
+        if (second.width(), second.height()) != (first.width(), first.height()) {
+            return Err(GridStackError::SizeMismatch {
+                layer: 1,
+                width: second.width(),
+                height: second.height(),
+                expected_width: first.width(),
+                expected_height: first.height(),
+            });
+        }
+
+        Ok((first, second))
+    }
+}
+
+fn parse_layer<Cell>(layer: &str, index: usize) -> Result<Grid<Cell>, GridStackError>
+where
+    Grid<Cell>: FromStr,
+    <Grid<Cell> as FromStr>::Err: ToString,
+{
+    layer
+        .parse::<Grid<Cell>>()
+        .map_err(|error| GridStackError::InvalidGrid(index, error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::level_template::Cell;
+
+    #[derive(GridCell, Copy, Clone, Debug, PartialEq, Eq, Default)]
+    enum Entity {
+        #[cell('.')]
+        #[default]
+        None,
+
+        #[cell('A')]
+        Player,
+    }
+
+    #[test]
+    pub fn test_parse_layers() {
+        let (terrain, entities) =
+            GridStack::parse::<Cell, Entity>("#..#\n####\n\n.A..\n....").unwrap();
+
+        assert_eq!(terrain.to_string(), "#  #\n####");
+        assert_eq!(entities.to_string(), ".A..\n....");
+    }
+
+    #[test]
+    pub fn test_layers_size_mismatch() {
+        assert_eq!(
+            GridStack::parse::<Cell, Entity>("#..#\n####\n\n.A.\n...").err(),
+            Some(GridStackError::SizeMismatch {
+                layer: 1,
+                width: 3,
+                height: 2,
+                expected_width: 4,
+                expected_height: 2,
+            })
+        );
+
+        assert_eq!(
+            GridStack::parse::<Cell, Entity>("#..#").err(),
+            Some(GridStackError::LayerCount {
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+}
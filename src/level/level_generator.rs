@@ -0,0 +1,161 @@
+//! Random levels for endless play, generated from a seed and only kept when the solver finds a solution.
+
+use bevy::prelude::*;
+use game_grid::*;
+use rand::prelude::*;
+use thiserror::Error;
+
+use crate::{
+    gameplay::game_constants_pluggin::{DOWN, RIGHT},
+    gameplay::solver::solve,
+    level::grid_ext::GridExt,
+    level::level_template::{Cell, LevelTemplate},
+};
+
+/// Maximum number of game states the solver explores per candidate level.
+pub const GENERATOR_NODE_BUDGET: usize = 20_000;
+
+const MAX_ATTEMPTS: usize = 1000;
+/// Levels solved in less moves are too easy to be kept.
+const MIN_SOLUTION_MOVES: usize = 4;
+const MIN_WIDTH: usize = 8;
+const MAX_WIDTH: usize = 12;
+const MIN_HEIGHT: usize = 6;
+const MAX_HEIGHT: usize = 8;
+const WALL_DENSITY: f64 = 0.25;
+const MAX_FOOD: usize = 2;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum GeneratorError {
+    #[error("No solvable level found in {0} attempts.")]
+    NoSolvableLevel(usize),
+}
+
+/// Generate a solvable level with one snake, one goal and some food, the same seed gives the same level.
+/// The par of the level is the length of its shortest solution.
+pub fn generate_level(seed: u64, node_budget: usize) -> Result<LevelTemplate, GeneratorError> {
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for _ in 0..MAX_ATTEMPTS {
+        let Some(mut level) = random_level(&mut rng) else {
+            continue;
+        };
+
+        let Ok(moves) = solve(&level, node_budget) else {
+            continue;
+        };
+
+        if moves.len() < MIN_SOLUTION_MOVES {
+            continue;
+        }
+
+        level.title = Some(format!("Generated {}", seed));
+        level.par = Some(moves.len());
+        return Ok(level);
+    }
+
+    Err(GeneratorError::NoSolvableLevel(MAX_ATTEMPTS))
+}
+
+/// A candidate level, random walls over a solid ground with a snake standing on a wall.
+/// The goal and the food are in the region connected to the snake, ignoring gravity.
+fn random_level(rng: &mut StdRng) -> Option<LevelTemplate> {
+    let width = rng.gen_range(MIN_WIDTH..=MAX_WIDTH);
+    let height = rng.gen_range(MIN_HEIGHT..=MAX_HEIGHT);
+
+    let mut grid = Grid::new(width, height, Cell::Empty);
+    for (position, cell) in grid.iter_mut() {
+        if position.y == 0 || rng.gen_bool(WALL_DENSITY) {
+            *cell = Cell::Wall;
+        }
+    }
+
+    // Isolated walls are noise, the remaining walls form platforms.
+    let grid = grid.step(|cell, neighbors| {
+        if cell == Cell::Wall && !neighbors.contains(&Cell::Wall) {
+            Cell::Empty
+        } else {
+            cell
+        }
+    });
+
+    let is_empty =
+        |position: IVec2| grid.is_in_bounds(position) && grid.cell_at(position) == Cell::Empty;
+    let snake_positions: Vec<IVec2> = grid
+        .iter::<IVec2>()
+        .map(|(position, _)| position)
+        .filter(|position| {
+            is_empty(*position)
+                && is_empty(*position + RIGHT)
+                && grid.cell_at(*position + DOWN) == Cell::Wall
+        })
+        .collect();
+    let tail = *snake_positions.choose(rng)?;
+    let head = tail + RIGHT;
+
+    let mut free_positions: Vec<IVec2> = grid
+        .flood_fill(head, |cell| *cell == Cell::Empty)
+        .into_iter()
+        .filter(|position| *position != head && *position != tail)
+        .collect();
+    free_positions.shuffle(rng);
+
+    let food_count = rng.gen_range(0..=MAX_FOOD);
+    let (goal, food) = free_positions.split_first()?;
+
+    let mut grid = grid;
+    grid.set_cell(tail, Cell::SnakePart('a'));
+    grid.set_cell(head, Cell::SnakeHead('A'));
+    grid.set_cell(*goal, Cell::Goal);
+    for position in food.iter().take(food_count) {
+        grid.set_cell(*position, Cell::Food);
+    }
+
+    let level_string = grid.flip_y().format_with(|cell, _| match cell {
+        Cell::Empty => '.',
+        _ => char::from(cell),
+    });
+    LevelTemplate::parse(&level_string).ok()
+}
+
+/// Generate a level and print it as a constant to paste in the levels.
+/// Without a seed a random one is used, it is printed to generate the level again.
+pub fn print_generated_level(seed: Option<u64>, node_budget: usize) {
+    let seed = seed.unwrap_or_else(random);
+
+    match generate_level(seed, node_budget) {
+        Ok(level) => {
+            println!("Seed {}, par {}:", seed, level.par.unwrap_or_default());
+            println!("const LEVEL_SEED_{}: &str = \"{}\";", seed, level);
+        }
+        Err(error) => println!("Seed {}: {}", seed, error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_generated_level_is_solvable() {
+        let level = generate_level(7, GENERATOR_NODE_BUDGET).unwrap();
+
+        assert_eq!(level.initial_snakes.len(), 1);
+        assert_eq!(level.goals.len(), 1);
+
+        let moves = solve(&level, GENERATOR_NODE_BUDGET).unwrap();
+        assert!(moves.len() >= MIN_SOLUTION_MOVES);
+        assert_eq!(level.par, Some(moves.len()));
+
+        // The printed level parses back to the same level.
+        let parsed = LevelTemplate::parse(&level.to_string()).unwrap();
+        assert_eq!(parsed.to_string(), level.to_string());
+    }
+
+    #[test]
+    pub fn test_same_seed_same_level() {
+        let level = generate_level(42, GENERATOR_NODE_BUDGET).unwrap();
+        let other_level = generate_level(42, GENERATOR_NODE_BUDGET).unwrap();
+        assert_eq!(level.to_string(), other_level.to_string());
+    }
+}
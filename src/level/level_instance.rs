@@ -1,39 +1,222 @@
 use std::collections::VecDeque;
 
 use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
 
-use crate::gameplay::{snake_pluggin::Snake, undo::LevelEntityUpdateEvent};
+use crate::{
+    gameplay::{snake_pluggin::Snake, undo::LevelEntityUpdateEvent},
+    level::level_template::{LevelTemplate, SpikeOrientation},
+};
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum LevelEntityType {
     Food,
-    Spike,
+    ShrinkFood,
+    Spike(SpikeOrientation),
     Wall,
+    Block,
+    /// A goal blocking the way until all the food is eaten, in levels with closed goals.
+    ClosedGoal,
+    /// A conveyor carrying the snakes resting on it in its direction, solid like a wall.
+    Conveyor(IVec2),
+    Snake(i32),
+}
+
+/// How the content of a cell interacts with the snakes, the occupancy predicates are derived from it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Occupancy {
+    Empty,
+    /// Walls, closed goals and conveyors, blocking everything.
+    Solid,
+    Spike(SpikeOrientation),
+    Food,
+    ShrinkFood,
+    Block,
     Snake(i32),
 }
 
-#[derive(Resource)]
+/// The snakes and blocks moved by a push.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct PushedEntities {
+    pub snakes: Vec<i32>,
+    pub blocks: Vec<IVec2>,
+}
+
+#[derive(Resource, Clone)]
 pub struct LevelInstance {
     occupied_cells: HashMap<IVec2, LevelEntityType>,
+    water_level: Option<i32>,
+    gravity: IVec2,
+    /// The size of the level grid, falls past its edge in the direction of the gravity have no ground.
+    grid_size: IVec2,
+    one_way_cells: HashMap<IVec2, IVec2>,
+    portals: HashMap<IVec2, IVec2>,
 }
 
 impl LevelInstance {
     pub fn new() -> Self {
         LevelInstance {
             occupied_cells: HashMap::new(),
+            water_level: None,
+            gravity: IVec2::NEG_Y,
+            grid_size: IVec2::ZERO,
+            one_way_cells: HashMap::new(),
+            portals: HashMap::new(),
+        }
+    }
+
+    /// Set the height of the lethal water, the cells below it are water.
+    pub fn set_water_level(&mut self, water_level: Option<i32>) {
+        self.water_level = water_level;
+    }
+
+    pub fn is_water(&self, position: IVec2) -> bool {
+        matches!(self.water_level, Some(water_level) if position.y < water_level)
+    }
+
+    /// Set the direction the snakes and blocks fall in and the size of the level grid they fall out of.
+    pub fn set_gravity(&mut self, gravity: IVec2, grid_size: IVec2) {
+        self.gravity = gravity;
+        self.grid_size = grid_size;
+    }
+
+    pub fn gravity(&self) -> IVec2 {
+        self.gravity
+    }
+
+    /// The distance along the gravity of the last cells of the grid, the cells at y = 0 when falling down.
+    pub fn gravity_edge(&self) -> i32 {
+        (self.grid_size - IVec2::ONE)
+            .max(IVec2::ZERO)
+            .dot(self.gravity.max(IVec2::ZERO))
+    }
+
+    /// Add a one-way wall, one-way walls don't occupy their cell and never change during a level.
+    pub fn add_one_way(&mut self, position: IVec2, direction: IVec2) {
+        self.one_way_cells.insert(position, direction);
+    }
+
+    /// Close the goals of levels with closed goals, they open when the last food is eaten.
+    pub fn close_goals(&mut self, level_template: &LevelTemplate) {
+        if !level_template.closed_goals || level_template.food_positions.is_empty() {
+            return;
+        }
+
+        for (position, _) in &level_template.goals {
+            self.mark_position_occupied(*position, LevelEntityType::ClosedGoal);
         }
     }
 
+    /// Add a pair of portals, like one-way walls portals don't occupy their cells.
+    pub fn add_portal_pair(&mut self, first: IVec2, second: IVec2) {
+        self.portals.insert(first, second);
+        self.portals.insert(second, first);
+    }
+
+    /// The position of the portal paired with the portal at this position if any.
+    pub fn portal_exit(&self, position: IVec2) -> Option<IVec2> {
+        self.portals.get(&position).copied()
+    }
+
+    /// Can an entity moving in a direction enter a position, one-way walls block the other directions.
+    pub fn can_enter(&self, position: IVec2, direction: IVec2) -> bool {
+        self.one_way_cells
+            .get(&position)
+            .map_or(true, |one_way_direction| *one_way_direction == direction)
+    }
+
     pub fn occupied_cells(&self) -> &HashMap<IVec2, LevelEntityType> {
         &self.occupied_cells
     }
 
+    /// The positions occupied by a type of entity, in no particular order.
+    pub fn positions_of(&self, entity_type: LevelEntityType) -> impl Iterator<Item = IVec2> + '_ {
+        self.occupied_cells
+            .iter()
+            .filter(move |(_, value)| **value == entity_type)
+            .map(|(position, _)| *position)
+    }
+
+    /// The positions occupied by the snake with the given index, in no particular order.
+    pub fn snake_positions(&self, snake_index: i32) -> impl Iterator<Item = IVec2> + '_ {
+        self.positions_of(LevelEntityType::Snake(snake_index))
+    }
+
+    /// Check that the snake cells are exactly the parts of the snakes, to catch the move and undo bugs
+    /// desynchronizing the level from the snakes. The error has the snake parts not marked as their snake
+    /// and the stale snake cells.
+    pub fn validate_against(&self, snakes: &[Snake]) -> Result<(), Vec<IVec2>> {
+        let mut mismatches: Vec<IVec2> = snakes
+            .iter()
+            .flat_map(|snake| {
+                snake
+                    .parts()
+                    .iter()
+                    .map(|(position, _)| *position)
+                    .filter(|position| {
+                        self.entity_at(*position) != Some(LevelEntityType::Snake(snake.index()))
+                    })
+            })
+            .collect();
+
+        mismatches.extend(
+            self.occupied_cells
+                .iter()
+                .filter(|(position, entity)| {
+                    matches!(entity, LevelEntityType::Snake(_))
+                        && !snakes
+                            .iter()
+                            .any(|snake| snake.occupies_position(**position))
+                })
+                .map(|(position, _)| *position),
+        );
+
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+
+        mismatches.sort_by_key(|position| (position.x, position.y));
+        Err(mismatches)
+    }
+
+    /// The entity occupying a position if any.
+    pub fn entity_at(&self, position: IVec2) -> Option<LevelEntityType> {
+        self.occupied_cells.get(&position).copied()
+    }
+
+    /// Classify the content of a position, new entity types must be classified here.
+    pub fn occupancy(&self, position: IVec2) -> Occupancy {
+        match self.occupied_cells.get(&position) {
+            None => Occupancy::Empty,
+            Some(
+                LevelEntityType::Wall | LevelEntityType::ClosedGoal | LevelEntityType::Conveyor(_),
+            ) => Occupancy::Solid,
+            Some(LevelEntityType::Spike(orientation)) => Occupancy::Spike(*orientation),
+            Some(LevelEntityType::Food) => Occupancy::Food,
+            Some(LevelEntityType::ShrinkFood) => Occupancy::ShrinkFood,
+            Some(LevelEntityType::Block) => Occupancy::Block,
+            Some(LevelEntityType::Snake(index)) => Occupancy::Snake(*index),
+        }
+    }
+
     pub fn is_empty(&self, position: IVec2) -> bool {
-        !self.occupied_cells.contains_key(&position)
+        self.occupancy(position) == Occupancy::Empty
     }
 
     pub fn is_empty_or_spike(&self, position: IVec2) -> bool {
-        !self.occupied_cells.contains_key(&position) || self.is_spike(position)
+        matches!(
+            self.occupancy(position),
+            Occupancy::Empty | Occupancy::Spike(_)
+        )
+    }
+
+    /// Can the snake with the given index move into this position, either empty or occupied by the snake itself.
+    pub fn is_walkable(&self, position: IVec2, snake_index: i32) -> bool {
+        match self.occupancy(position) {
+            Occupancy::Empty => true,
+            Occupancy::Snake(index) => index == snake_index,
+            _ => false,
+        }
     }
 
     pub fn set_empty(&mut self, position: IVec2) -> Option<LevelEntityType> {
@@ -45,23 +228,40 @@ impl LevelInstance {
     }
 
     pub fn is_food(&self, position: IVec2) -> bool {
-        matches!(
-            self.occupied_cells.get(&position),
-            Some(LevelEntityType::Food)
-        )
+        self.occupancy(position) == Occupancy::Food
+    }
+
+    pub fn is_shrink_food(&self, position: IVec2) -> bool {
+        self.occupancy(position) == Occupancy::ShrinkFood
     }
 
     pub fn is_spike(&self, position: IVec2) -> bool {
-        matches!(
-            self.occupied_cells.get(&position),
-            Some(LevelEntityType::Spike)
-        )
+        matches!(self.occupancy(position), Occupancy::Spike(_))
+    }
+
+    /// Is there a spike killing a snake part entering the position moving in `direction`.
+    pub fn is_lethal_spike(&self, position: IVec2, direction: IVec2) -> bool {
+        match self.occupancy(position) {
+            Occupancy::Spike(orientation) => orientation.is_lethal_from(direction),
+            _ => false,
+        }
+    }
+
+    pub fn is_block(&self, position: IVec2) -> bool {
+        self.occupancy(position) == Occupancy::Block
     }
 
     pub fn is_snake(&self, position: IVec2) -> Option<i32> {
-        let walkable = self.occupied_cells.get(&position);
-        match walkable {
-            Some(LevelEntityType::Snake(index)) => Some(*index),
+        match self.occupancy(position) {
+            Occupancy::Snake(index) => Some(index),
+            _ => None,
+        }
+    }
+
+    /// The direction of the conveyor at a position if any.
+    pub fn conveyor_direction(&self, position: IVec2) -> Option<IVec2> {
+        match self.entity_at(position) {
+            Some(LevelEntityType::Conveyor(direction)) => Some(direction),
             _ => None,
         }
     }
@@ -73,9 +273,19 @@ impl LevelInstance {
         &mut self,
         snake: &Snake,
         direction: IVec2,
+    ) -> Vec<LevelEntityUpdateEvent> {
+        self.move_snake_head_to(snake, snake.head_position() + direction)
+    }
+
+    /// Move the head of a snake to a new position, the rest of the snake follows.
+    /// This is a move forward unless the snake goes through a portal.
+    /// Returns a list of updates to the walkable cells that can be undone.
+    pub fn move_snake_head_to(
+        &mut self,
+        snake: &Snake,
+        new_position: IVec2,
     ) -> Vec<LevelEntityUpdateEvent> {
         let mut updates: Vec<LevelEntityUpdateEvent> = Vec::with_capacity(2);
-        let new_position = snake.head_position() + direction;
 
         let old_value = self.set_empty(snake.tail_position()).unwrap();
         self.mark_position_occupied(new_position, LevelEntityType::Snake(snake.index()));
@@ -89,27 +299,65 @@ impl LevelInstance {
         updates
     }
 
-    /// Move a snake by an offset:
+    /// Move snakes and blocks by an offset at once, they can move into each other's old locations:
     /// Set the old locations are empty and mark the new locations as occupied.
     /// Returns a list of updates to the walkable cells that can be undone.
-    pub fn move_snake(&mut self, snake: &Snake, offset: IVec2) -> Vec<LevelEntityUpdateEvent> {
+    pub fn move_entities(
+        &mut self,
+        snakes: &[&Snake],
+        blocks: &[IVec2],
+        offset: IVec2,
+    ) -> Vec<LevelEntityUpdateEvent> {
+        let parts_count = snakes.iter().map(|snake| snake.len()).sum::<usize>() + blocks.len();
         let mut updates: VecDeque<LevelEntityUpdateEvent> =
-            VecDeque::with_capacity(2 * snake.len());
+            VecDeque::with_capacity(2 * parts_count);
+
+        let old_positions = snakes
+            .iter()
+            .flat_map(|snake| snake.parts().iter().map(|(position, _)| *position))
+            .chain(blocks.iter().copied());
+        for position in old_positions {
+            let old_value = self.set_empty(position).unwrap();
+            updates.push_front(LevelEntityUpdateEvent::ClearPosition(position, old_value));
+        }
 
-        for (position, _) in snake.parts() {
-            let old_value = self.set_empty(*position).unwrap();
-            updates.push_front(LevelEntityUpdateEvent::ClearPosition(*position, old_value));
+        for snake in snakes {
+            for (position, _) in snake.parts() {
+                let new_position = *position + offset;
+                self.mark_position_occupied(new_position, LevelEntityType::Snake(snake.index()));
+                updates.push_front(LevelEntityUpdateEvent::FillPosition(new_position));
+            }
         }
-        for (position, _) in snake.parts() {
+        for position in blocks {
             let new_position = *position + offset;
-            self.mark_position_occupied(new_position, LevelEntityType::Snake(snake.index()));
+            self.mark_position_occupied(new_position, LevelEntityType::Block);
             updates.push_front(LevelEntityUpdateEvent::FillPosition(new_position));
         }
 
         updates.into()
     }
 
+    /// Remove the eaten food, the closed goals open with the last food and are closed again if the eat is undone.
     pub fn eat_food(&mut self, position: IVec2) -> Vec<LevelEntityUpdateEvent> {
+        let old_value = self.set_empty(position).unwrap();
+        let mut updates = vec![LevelEntityUpdateEvent::ClearPosition(position, old_value)];
+
+        if self.positions_of(LevelEntityType::Food).next().is_none() {
+            let closed_goals: Vec<IVec2> = self.positions_of(LevelEntityType::ClosedGoal).collect();
+            for goal_position in closed_goals {
+                self.set_empty(goal_position);
+                updates.push(LevelEntityUpdateEvent::ClearPosition(
+                    goal_position,
+                    LevelEntityType::ClosedGoal,
+                ));
+            }
+        }
+
+        updates
+    }
+
+    /// Remove a block that fell out of the level.
+    pub fn remove_block(&mut self, position: IVec2) -> Vec<LevelEntityUpdateEvent> {
         let old_value = self.set_empty(position).unwrap();
         vec![LevelEntityUpdateEvent::ClearPosition(position, old_value)]
     }
@@ -122,6 +370,16 @@ impl LevelInstance {
         vec![LevelEntityUpdateEvent::FillPosition(new_part_position)]
     }
 
+    /// Remove the tail of a snake.
+    pub fn shrink_snake(&mut self, snake: &Snake) -> Vec<LevelEntityUpdateEvent> {
+        let tail_position = snake.tail_position();
+        let old_value = self.set_empty(tail_position).unwrap();
+        vec![LevelEntityUpdateEvent::ClearPosition(
+            tail_position,
+            old_value,
+        )]
+    }
+
     pub fn clear_snake_positions(&mut self, snake: &Snake) -> Vec<LevelEntityUpdateEvent> {
         let mut updates: Vec<LevelEntityUpdateEvent> = Vec::with_capacity(snake.len());
         for (position, _) in snake.parts() {
@@ -153,49 +411,367 @@ impl LevelInstance {
         }
     }
 
-    pub fn can_push_snake(&self, snake: &Snake, direction: IVec2) -> bool {
-        snake.parts().iter().all(|(position, _)| {
-            self.is_empty(*position + direction)
-                || self.is_snake_with_index(*position + direction, snake.index())
-        })
+    /// Find the snakes and blocks pushed when the snake `pusher_index` moves into `position`.
+    /// Snakes and blocks in contact in the push direction are pushed together.
+    /// Returns None if the push is blocked by another entity, a one-way wall or by the pusher itself.
+    pub fn find_pushed_entities(
+        &self,
+        snakes: &[&Snake],
+        pusher_index: i32,
+        position: IVec2,
+        direction: IVec2,
+    ) -> Option<PushedEntities> {
+        self.find_moved_entities(
+            snakes,
+            Some(pusher_index),
+            PushedEntities::default(),
+            vec![position],
+            direction,
+        )
     }
 
-    pub fn is_snake_with_index(&self, position: IVec2, snake_index: i32) -> bool {
-        let walkable = self.occupied_cells.get(&position);
-        match walkable {
-            Some(LevelEntityType::Snake(index)) => *index == snake_index,
-            _ => false,
+    /// Find the snakes and blocks moved when a conveyor carries the snake `snake_index`, the snake included.
+    /// The snake pushes the entities in contact in the direction of the conveyor like a pushed snake does.
+    /// Returns None if the snake is blocked.
+    pub fn find_conveyed_entities(
+        &self,
+        snakes: &[&Snake],
+        snake_index: i32,
+        direction: IVec2,
+    ) -> Option<PushedEntities> {
+        let snake = snakes.iter().find(|snake| snake.index() == snake_index)?;
+        let to_visit = snake
+            .parts()
+            .iter()
+            .map(|(position, _)| *position + direction)
+            .collect();
+        let pushed = PushedEntities {
+            snakes: vec![snake_index],
+            blocks: vec![],
+        };
+
+        self.find_moved_entities(snakes, None, pushed, to_visit, direction)
+    }
+
+    /// Visit the snakes and blocks in contact in the direction of the move, starting from the positions in `to_visit`.
+    fn find_moved_entities(
+        &self,
+        snakes: &[&Snake],
+        pusher_index: Option<i32>,
+        mut pushed: PushedEntities,
+        mut to_visit: Vec<IVec2>,
+        direction: IVec2,
+    ) -> Option<PushedEntities> {
+        while let Some(position) = to_visit.pop() {
+            if !self.can_enter(position, direction) {
+                return None;
+            }
+
+            match self.entity_at(position) {
+                None => {}
+                Some(LevelEntityType::Snake(index)) if Some(index) == pusher_index => return None,
+                Some(LevelEntityType::Snake(index)) => {
+                    if !pushed.snakes.contains(&index) {
+                        pushed.snakes.push(index);
+
+                        let snake = snakes
+                            .iter()
+                            .find(|snake| snake.index() == index)
+                            .expect("Missing pushed snake.");
+                        to_visit.extend(
+                            snake
+                                .parts()
+                                .iter()
+                                .map(|(position, _)| *position + direction),
+                        );
+                    }
+                }
+                Some(LevelEntityType::Block) => {
+                    if !pushed.blocks.contains(&position) {
+                        pushed.blocks.push(position);
+                        to_visit.push(position + direction);
+                    }
+                }
+                Some(_) => return None,
+            }
         }
+
+        Some(pushed)
     }
 
+    /// Find where a snake head moving into a position emerges if the position is a free portal.
+    /// Returns None if the position is not a free portal, and Some(None) if the exit of the portal is blocked.
+    pub fn find_portal_exit(&self, position: IVec2, direction: IVec2) -> Option<Option<IVec2>> {
+        if !self.is_empty(position) {
+            return None;
+        }
+
+        let exit = self.portal_exit(position)?;
+        Some((self.is_empty(exit) && self.can_enter(exit, direction)).then_some(exit))
+    }
+
+    pub fn is_snake_with_index(&self, position: IVec2, snake_index: i32) -> bool {
+        self.is_snake(position) == Some(snake_index)
+    }
+
+    /// Closed goals block the snakes like walls.
     pub fn is_wall_or_spike(&self, position: IVec2) -> bool {
         matches!(
-            self.occupied_cells.get(&position),
-            Some(LevelEntityType::Wall)
-        ) || matches!(
-            self.occupied_cells.get(&position),
-            Some(LevelEntityType::Spike)
+            self.occupancy(position),
+            Occupancy::Solid | Occupancy::Spike(_)
         )
     }
 
-    pub fn get_distance_to_ground(&self, position: IVec2, snake_index: i32) -> i32 {
+    /// Distance from a position to the first cell below that can support the snake, below is along the gravity.
+    /// Returns None if nothing stops the fall before the edge of the level grid.
+    pub fn get_distance_to_ground(&self, position: IVec2, snake_index: i32) -> Option<i32> {
         let mut distance = 1;
 
-        const ARBITRARY_HIGH_DISTANCE: i32 = 50;
-
-        let mut current_position = position + IVec2::NEG_Y;
-        while self.is_empty_or_spike(current_position)
-            || self.is_snake_with_index(current_position, snake_index)
+        // Snakes fall through the spikes lethal from above, stand on the other spikes
+        // and on one-way walls that can't be entered going down.
+        let mut current_position = position + self.gravity;
+        while (self.is_walkable(current_position, snake_index)
+            || self.is_lethal_spike(current_position, self.gravity))
+            && self.can_enter(current_position, self.gravity)
         {
-            current_position += IVec2::NEG_Y;
+            // There is no ground below.
+            if current_position.dot(self.gravity) >= self.gravity_edge() {
+                return None;
+            }
+
+            current_position += self.gravity;
             distance += 1;
+        }
 
-            // There is no ground below.
-            if current_position.y <= 0 {
-                return ARBITRARY_HIGH_DISTANCE;
+        Some(distance)
+    }
+
+    /// Distance from a block to the first cell below that can support it, blocks rest on spikes.
+    /// Returns None if nothing stops the fall before the edge of the level.
+    pub fn get_block_distance_to_ground(&self, position: IVec2) -> Option<i32> {
+        let mut distance = 1;
+
+        let mut current_position = position + self.gravity;
+        while self.is_empty(current_position) && self.can_enter(current_position, self.gravity) {
+            if current_position.dot(self.gravity) >= self.gravity_edge() {
+                return None;
             }
+
+            current_position += self.gravity;
+            distance += 1;
+        }
+
+        Some(distance)
+    }
+
+    /// Simulate a player move of the snake `snake_index` and return the resulting occupancy, the level is left intact.
+    /// The move pushes the snakes and blocks in the way and eats the food, gravity and jumps are not simulated.
+    /// Returns None if the move is blocked.
+    pub fn simulate_move(
+        &self,
+        snakes: &[Snake],
+        snake_index: i32,
+        direction: IVec2,
+    ) -> Option<LevelInstance> {
+        let snake = snakes.iter().find(|snake| snake.index() == snake_index)?;
+        let new_position = snake.head_position() + direction;
+        let portal_exit = self.find_portal_exit(new_position, direction);
+
+        if snake.occupies_position(new_position)
+            || self.is_wall_or_spike(new_position)
+            || !self.can_enter(new_position, direction)
+        {
+            return None;
+        }
+
+        if portal_exit == Some(None) {
+            return None;
         }
 
-        distance
+        let mut level = self.clone();
+
+        if let Some(Some(exit)) = portal_exit {
+            level.move_snake_head_to(snake, exit);
+            return Some(level);
+        }
+
+        if self.is_snake(new_position).is_some() || self.is_block(new_position) {
+            let other_snakes: Vec<&Snake> = snakes
+                .iter()
+                .filter(|other_snake| other_snake.index() != snake_index)
+                .collect();
+
+            let pushed =
+                self.find_pushed_entities(&other_snakes, snake_index, new_position, direction)?;
+
+            let pushed_snakes: Vec<&Snake> = other_snakes
+                .into_iter()
+                .filter(|other_snake| pushed.snakes.contains(&other_snake.index()))
+                .collect();
+            level.move_entities(&pushed_snakes, &pushed.blocks, direction);
+        }
+
+        let eats_food = level.is_food(new_position);
+        let eats_shrink_food = level.is_shrink_food(new_position);
+        if eats_shrink_food && snake.len() <= 2 {
+            return None;
+        }
+
+        if eats_food || eats_shrink_food {
+            level.eat_food(new_position);
+        }
+
+        level.move_snake_forward(snake, direction);
+
+        let mut moved_snake = snake.clone();
+        moved_snake.move_forward(direction);
+        if eats_food {
+            level.grow_snake(&moved_snake);
+        }
+        if eats_shrink_food {
+            level.shrink_snake(&moved_snake);
+        }
+
+        Some(level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_occupancy_predicates() {
+        let mut level = LevelInstance::new();
+        let entities = [
+            LevelEntityType::Wall,
+            LevelEntityType::ClosedGoal,
+            LevelEntityType::Spike(SpikeOrientation::Omnidirectional),
+            LevelEntityType::Food,
+            LevelEntityType::ShrinkFood,
+            LevelEntityType::Block,
+            LevelEntityType::Snake(0),
+            LevelEntityType::Snake(1),
+            LevelEntityType::Conveyor(IVec2::X),
+        ];
+        for (x, entity) in entities.into_iter().enumerate() {
+            level.mark_position_occupied(IVec2::new(x as i32, 0), entity);
+        }
+
+        // is_empty, is_empty_or_spike, is_wall_or_spike, is_walkable by snake 0, is_spike, is_block, is_snake.
+        let expected = [
+            (false, false, true, false, false, false, None),
+            (false, false, true, false, false, false, None),
+            (false, true, true, false, true, false, None),
+            (false, false, false, false, false, false, None),
+            (false, false, false, false, false, false, None),
+            (false, false, false, false, false, true, None),
+            (false, false, false, true, false, false, Some(0)),
+            (false, false, false, false, false, false, Some(1)),
+            (false, false, true, false, false, false, None),
+            (true, true, false, true, false, false, None),
+        ];
+
+        for (x, expected) in expected.into_iter().enumerate() {
+            let position = IVec2::new(x as i32, 0);
+            let predicates = (
+                level.is_empty(position),
+                level.is_empty_or_spike(position),
+                level.is_wall_or_spike(position),
+                level.is_walkable(position, 0),
+                level.is_spike(position),
+                level.is_block(position),
+                level.is_snake(position),
+            );
+            assert_eq!(predicates, expected, "{:?}", level.entity_at(position));
+        }
+
+        assert!(level.is_food(IVec2::new(3, 0)));
+        assert!(!level.is_food(IVec2::new(4, 0)));
+        assert!(level.is_shrink_food(IVec2::new(4, 0)));
+        assert!(!level.is_shrink_food(IVec2::new(3, 0)));
+    }
+
+    #[test]
+    pub fn test_positions_of() {
+        let mut level = LevelInstance::new();
+        for x in 0..5 {
+            level.mark_position_occupied(IVec2::new(x, 0), LevelEntityType::Wall);
+        }
+        level.mark_position_occupied(IVec2::new(3, 1), LevelEntityType::Food);
+        level.mark_position_occupied(
+            IVec2::new(4, 2),
+            LevelEntityType::Spike(SpikeOrientation::Omnidirectional),
+        );
+        level.mark_snake_positions(&Snake::from_positions(
+            &[IVec2::new(2, 1), IVec2::new(1, 1)],
+            0,
+        ));
+
+        assert_eq!(
+            level
+                .positions_of(LevelEntityType::Food)
+                .collect::<Vec<_>>(),
+            vec![IVec2::new(3, 1)]
+        );
+        assert_eq!(
+            level
+                .positions_of(LevelEntityType::Spike(SpikeOrientation::Omnidirectional))
+                .collect::<Vec<_>>(),
+            vec![IVec2::new(4, 2)]
+        );
+        assert_eq!(level.positions_of(LevelEntityType::Wall).count(), 5);
+
+        let mut snake_positions: Vec<IVec2> = level.snake_positions(0).collect();
+        snake_positions.sort_by_key(|position| position.x);
+        assert_eq!(snake_positions, vec![IVec2::new(1, 1), IVec2::new(2, 1)]);
+        assert_eq!(level.snake_positions(1).count(), 0);
+    }
+
+    #[test]
+    pub fn test_validate_against() {
+        let mut level = LevelInstance::new();
+        let snake = Snake::from_positions(&[IVec2::new(2, 0), IVec2::new(1, 0)], 0);
+        level.mark_snake_positions(&snake);
+        assert_eq!(level.validate_against(&[snake.clone()]), Ok(()));
+
+        // A part of the snake not marked and a stale snake cell.
+        level.set_empty(IVec2::new(1, 0));
+        level.mark_position_occupied(IVec2::new(5, 5), LevelEntityType::Snake(0));
+        assert_eq!(
+            level.validate_against(&[snake.clone()]),
+            Err(vec![IVec2::new(1, 0), IVec2::new(5, 5)])
+        );
+
+        // The cells of a snake no longer in the level are stale.
+        let mut level = LevelInstance::new();
+        level.mark_snake_positions(&snake);
+        assert_eq!(
+            level.validate_against(&[]),
+            Err(vec![IVec2::new(1, 0), IVec2::new(2, 0)])
+        );
+    }
+
+    #[test]
+    pub fn test_lethal_spikes() {
+        let mut level = LevelInstance::new();
+        let floor_spike = IVec2::new(0, 0);
+        let spike = IVec2::new(1, 0);
+        level.mark_position_occupied(
+            floor_spike,
+            LevelEntityType::Spike(SpikeOrientation::Pointing(IVec2::Y)),
+        );
+        level.mark_position_occupied(
+            spike,
+            LevelEntityType::Spike(SpikeOrientation::Omnidirectional),
+        );
+
+        assert!(level.is_lethal_spike(floor_spike, IVec2::NEG_Y));
+        assert!(!level.is_lethal_spike(floor_spike, IVec2::X));
+        assert!(!level.is_lethal_spike(floor_spike, IVec2::Y));
+        for direction in [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y] {
+            assert!(level.is_lethal_spike(spike, direction));
+            assert!(!level.is_lethal_spike(IVec2::new(2, 0), direction));
+        }
     }
 }
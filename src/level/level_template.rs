@@ -1,12 +1,14 @@
-use std::iter::once;
+use std::{fmt, iter::once, str::FromStr};
 
-use anyhow::{bail, Result};
-use bevy::{prelude::*, utils::HashSet};
+use bevy::prelude::*;
 use game_grid::*;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use crate::gameplay::game_constants_pluggin::{DOWN, LEFT, RIGHT, UP};
 
+use super::grid_ext::GridExt;
+
 #[derive(GridCell, Copy, Clone, Debug, PartialEq, Eq, Default)]
 pub enum Cell {
     #[cell('#')]
@@ -19,44 +21,349 @@ pub enum Cell {
     #[cell('o')]
     Food,
 
+    /// A fruit that shrinks the snake eating it.
+    #[cell('*')]
+    ShrinkFood,
+
     #[cell('X')]
     Goal,
 
-    #[cell('+')]
-    Spike,
+    /// A goal only accepting the snake with the given index in the sorted head letters, fex '1' is 'C' with snakes 'A' and 'C'.
+    /// Only the first ten snakes can have their own goal.
+    #[cell('0'..='9')]
+    SnakeGoal(char),
+
+    /// A spike, '+' is lethal from every side, the others only from the side they point to:
+    /// '!' up, '\'' down, '{' left and '}' right.
+    #[cell('+'|'!'|'\''|'{'|'}')]
+    Spike(char),
+
+    #[cell('=')]
+    Block,
+
+    /// A one-way wall that can only be entered moving in the direction of the marker:
+    /// ')' right, '(' left, '-' up and '~' down.
+    #[cell(')'|'('|'-'|'~')]
+    OneWay(char),
+
+    /// A conveyor carrying the snakes resting on it: '[' to the left and ']' to the right.
+    /// '<' and '>' are head markers so the conveyors use brackets.
+    #[cell('['|']')]
+    Conveyor(char),
+
+    /// A portal, the two portals with the same marker are paired.
+    #[cell('@'|'$'|'%'|'&')]
+    Portal(char),
 
     #[cell('A'..='Z')]
     SnakeHead(char),
 
     #[cell('a'..='z')]
     SnakePart(char),
+
+    /// Optional marker next to a snake head setting its initial facing.
+    /// 'v' is a snake part so facing down uses '_'.
+    #[cell('>'|'<'|'^'|'_')]
+    HeadMarker(char),
+}
+
+fn head_marker_direction(marker: char) -> IVec2 {
+    match marker {
+        '>' => RIGHT,
+        '<' => LEFT,
+        '^' => UP,
+        '_' => DOWN,
+        _ => panic!("Invalid head marker '{}'.", marker),
+    }
+}
+
+fn head_marker_for_direction(direction: IVec2) -> char {
+    match direction {
+        RIGHT => '>',
+        LEFT => '<',
+        UP => '^',
+        DOWN => '_',
+        _ => panic!("Invalid head direction {}.", direction),
+    }
+}
+
+fn one_way_direction(marker: char) -> IVec2 {
+    match marker {
+        ')' => RIGHT,
+        '(' => LEFT,
+        '-' => UP,
+        '~' => DOWN,
+        _ => panic!("Invalid one-way marker '{}'.", marker),
+    }
+}
+
+fn one_way_for_direction(direction: IVec2) -> char {
+    match direction {
+        RIGHT => ')',
+        LEFT => '(',
+        UP => '-',
+        DOWN => '~',
+        _ => panic!("Invalid one-way direction {}.", direction),
+    }
 }
 
+fn conveyor_direction(marker: char) -> IVec2 {
+    match marker {
+        ']' => RIGHT,
+        '[' => LEFT,
+        _ => panic!("Invalid conveyor marker '{}'.", marker),
+    }
+}
+
+fn conveyor_for_direction(direction: IVec2) -> char {
+    match direction {
+        RIGHT => ']',
+        LEFT => '[',
+        _ => panic!("Invalid conveyor direction {}.", direction),
+    }
+}
+
+/// Gravity directions are written `down`, `up`, `left` or `right` in the level header.
+fn gravity_direction(name: &str) -> Option<IVec2> {
+    match name {
+        "down" => Some(DOWN),
+        "up" => Some(UP),
+        "left" => Some(LEFT),
+        "right" => Some(RIGHT),
+        _ => None,
+    }
+}
+
+fn gravity_name(direction: IVec2) -> &'static str {
+    match direction {
+        DOWN => "down",
+        UP => "up",
+        LEFT => "left",
+        RIGHT => "right",
+        _ => panic!("Invalid gravity direction {}.", direction),
+    }
+}
+
+/// The side a spike points to, a spike only kills the snakes coming at its tip.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum SpikeOrientation {
+    /// Lethal from every side, the original spikes.
+    #[default]
+    Omnidirectional,
+    Pointing(IVec2),
+}
+
+impl SpikeOrientation {
+    /// Is the spike lethal to a snake part entering it moving in `direction`.
+    pub fn is_lethal_from(&self, direction: IVec2) -> bool {
+        match self {
+            SpikeOrientation::Omnidirectional => true,
+            SpikeOrientation::Pointing(tip) => *tip == -direction,
+        }
+    }
+}
+
+fn spike_orientation(marker: char) -> SpikeOrientation {
+    match marker {
+        '+' => SpikeOrientation::Omnidirectional,
+        '!' => SpikeOrientation::Pointing(UP),
+        '\'' => SpikeOrientation::Pointing(DOWN),
+        '{' => SpikeOrientation::Pointing(LEFT),
+        '}' => SpikeOrientation::Pointing(RIGHT),
+        _ => panic!("Invalid spike marker '{}'.", marker),
+    }
+}
+
+fn spike_for_orientation(orientation: SpikeOrientation) -> char {
+    match orientation {
+        SpikeOrientation::Omnidirectional => '+',
+        SpikeOrientation::Pointing(UP) => '!',
+        SpikeOrientation::Pointing(DOWN) => '\'',
+        SpikeOrientation::Pointing(LEFT) => '{',
+        SpikeOrientation::Pointing(RIGHT) => '}',
+        SpikeOrientation::Pointing(direction) => panic!("Invalid spike direction {}.", direction),
+    }
+}
+
+/// How many snakes must exit through the goal to complete a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GoalCondition {
+    #[default]
+    AllSnakes,
+    AnySnake,
+    Count(usize),
+}
+
+impl GoalCondition {
+    pub fn is_met(&self, exited_count: usize, snake_count: usize) -> bool {
+        match self {
+            GoalCondition::AllSnakes => exited_count == snake_count,
+            GoalCondition::AnySnake => exited_count > 0,
+            GoalCondition::Count(count) => exited_count >= *count,
+        }
+    }
+}
+
+/// Goal conditions are written `all`, `any` or a number of snakes in the level header.
+impl FromStr for GoalCondition {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "all" => Ok(GoalCondition::AllSnakes),
+            "any" => Ok(GoalCondition::AnySnake),
+            _ => value.parse().map(GoalCondition::Count).map_err(|_| ()),
+        }
+    }
+}
+
+impl fmt::Display for GoalCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoalCondition::AllSnakes => write!(f, "all"),
+            GoalCondition::AnySnake => write!(f, "any"),
+            GoalCondition::Count(count) => write!(f, "{}", count),
+        }
+    }
+}
+
+/// The portal markers, each marker is used by one pair of portals.
+const PORTAL_MARKERS: [char; 4] = ['@', '$', '%', '&'];
+
+/// Snakes are identified by a letter 'A'..='Z' in the level string, which caps the number of snakes in a level.
+/// Parsed levels can't go over it, levels built programmatically are checked by `LevelTemplate::validate`.
+pub const MAX_SNAKES: usize = 26;
+
+/// Snake goals are identified by a digit '0'..='9' in the level string, so only the first snakes can have their own goal.
+pub const MAX_SNAKE_GOALS: usize = 10;
+
 pub type SnakeElement = (IVec2, IVec2);
 pub type SnakeTemplate = Vec<SnakeElement>;
 
+/// Separates the optional header from the grid in a level string.
+/// The header is made of `key: value` lines, fex:
+/// ```text
+/// title: First steps
+/// par: 4
+/// ---
+/// ....X
+/// .aaA.
+/// #####
+/// ```
+const HEADER_SEPARATOR: &str = "---";
+
+/// How many cells past the edge of the level a snake falls before it is out of the level, falling down it sinks
+/// below the water line.
+const FALL_OUT_OF_LEVEL_DEPTH: i32 = 2;
+
 #[derive(Debug, Clone, Resource)]
 pub struct LevelTemplate {
+    pub title: Option<String>,
+    /// Target number of moves to solve the level.
+    pub par: Option<usize>,
+    /// Height of the lethal water, snake parts below it drown.
+    pub water_level: Option<i32>,
+    /// The direction the snakes and blocks fall in, down unless the level changes it.
+    pub gravity: IVec2,
+    pub goal_condition: GoalCondition,
+    /// The goals block the snakes and blocks like walls until all the food is eaten,
+    /// otherwise the snakes can walk over the inactive goals.
+    pub closed_goals: bool,
     pub grid: Grid<Cell>,
-    pub goal_position: IVec2,
+    /// The goals with the index of the snake they accept, goals without index accept any snake.
+    pub goals: Vec<(IVec2, Option<i32>)>,
     pub initial_snakes: Vec<SnakeTemplate>,
     pub food_positions: Vec<IVec2>,
-    pub spike_positions: Vec<IVec2>,
+    pub shrink_food_positions: Vec<IVec2>,
+    /// Spikes with the side they point to.
+    pub spike_positions: Vec<(IVec2, SpikeOrientation)>,
+    pub block_positions: Vec<IVec2>,
+    /// One-way walls with the direction they can be entered in.
+    pub one_way_positions: Vec<(IVec2, IVec2)>,
+    /// Conveyors with the direction they carry the snakes resting on them.
+    pub conveyor_positions: Vec<(IVec2, IVec2)>,
+    /// Pairs of portals, a snake entering one portal emerges at the other.
+    pub portal_pairs: Vec<(IVec2, IVec2)>,
 }
 
-#[derive(Debug, Error)]
-enum ParseLevelError {
-    #[error("Missing goal cell 'X'.")]
+/// Errors in the authoring of a level, detected when parsing or validating a level.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum LevelValidationError {
+    #[error("Invalid level header line '{0}'.")]
+    InvalidHeader(String),
+
+    #[error("Invalid level grid: {0}")]
+    InvalidGrid(String),
+
+    #[error("Missing goal cell 'X' or '0'..='9'.")]
     MissingLevelGoal,
 
+    #[error("Goal '{0}' is for a snake that is not in the level.")]
+    GoalWithoutSnake(char),
+
     #[error("Missing snake head start position 'A'..='Z'.")]
     MissingSnakeHead,
 
-    #[error("Snake should be of length at least 2.")]
-    InvalidSnake,
+    #[error("Snake '{0}' should be of length at least 2.")]
+    InvalidSnake(char),
+
+    #[error("Level has {0} snakes, at most {max} are supported.", max = MAX_SNAKES)]
+    TooManySnakes(usize),
+
+    #[error("Goal for snake {0}, only the first {max} snakes can have their own goal.", max = MAX_SNAKE_GOALS)]
+    SnakeGoalOutOfRange(i32),
+
+    #[error("Snake head '{0}' is used by more than one snake.")]
+    DuplicateSnakeHead(char),
+
+    #[error("Snake '{0}' has a part at {1} touching more than its previous and next parts.")]
+    AmbiguousSnake(char, IVec2),
+
+    #[error("Snake '{0}' has more than one head marker.")]
+    MultipleHeadMarkers(char),
+
+    #[error("Head marker at {0} is not next to a snake head.")]
+    StrayHeadMarker(IVec2),
+
+    #[error("Snake part '{0}' at {1} is not connected to its head.")]
+    DisconnectedSnakePart(char, IVec2),
+
+    #[error("Goal at {0} overlaps a wall.")]
+    GoalOverlapsWall(IVec2),
+
+    #[error("Food at {0} overlaps a wall or a snake.")]
+    FoodOverlaps(IVec2),
+
+    #[error("Spike at {0} overlaps a wall or a snake.")]
+    SpikeOverlaps(IVec2),
+
+    #[error("Block at {0} overlaps a wall or a snake.")]
+    BlockOverlaps(IVec2),
+
+    #[error("Portal '{0}' should appear exactly twice, found {1}.")]
+    UnpairedPortal(char, usize),
+
+    #[error("Goal condition requires {0} snakes, the level should have at least as many.")]
+    InvalidGoalCount(usize),
+}
+
+/// A snake from the positions of its parts from the head to the tail, each part faces away from the next one.
+/// There must be at least two parts.
+pub fn snake_template_from_positions(parts: &[IVec2]) -> SnakeTemplate {
+    // Infer parts direction from previous part.
+    let directions = parts
+        .iter()
+        .zip(parts.iter().skip(1))
+        .map(|(position, prev_position)| *position - *prev_position)
+        .chain(once(parts[parts.len() - 2] - parts[parts.len() - 1]));
+
+    parts.iter().copied().zip(directions).collect()
 }
 
-fn extract_snake_template(grid: &Grid<Cell>, start_head_index: usize) -> Result<SnakeTemplate> {
+fn extract_snake_template(
+    grid: &Grid<Cell>,
+    start_head_index: usize,
+) -> Result<SnakeTemplate, LevelValidationError> {
     let head_cell = grid[start_head_index];
     let start_head_position = grid.position_for_index(start_head_index);
     let Cell::SnakeHead(head_char) = head_cell else {
@@ -69,52 +376,169 @@ fn extract_snake_template(grid: &Grid<Cell>, start_head_index: usize) -> Result<
         .next()
         .expect("Snake head should be in the range 'A'..='Z' and have a valid lowercase.");
 
-    // Search for the parts around the head.
-    let mut parts = vec![start_head_position];
-    {
-        let mut visited = HashSet::<IVec2>::new();
-        let mut current_position = start_head_position;
-        let search_dirs = vec![UP, DOWN, RIGHT, LEFT];
+    // Follow the body from the head, going forward from the previous part.
+    // A part touching more than its previous and next part makes the order ambiguous.
+    let is_snake_cell = |position: IVec2| {
+        grid.is_in_bounds(position)
+            && (grid.cell_at(position) == Cell::SnakeHead(head_char)
+                || grid.cell_at(position) == Cell::SnakePart(part_char))
+    };
 
-        while !visited.contains(&current_position)
-            && (grid.cell_at(current_position) == Cell::SnakeHead(head_char)
-                || grid.cell_at(current_position) == Cell::SnakePart(part_char))
-        {
-            visited.insert(current_position);
-            for search_dir in search_dirs.iter() {
-                let new_position = current_position + *search_dir;
-                if !grid.is_in_bounds(new_position) || visited.contains(&new_position) {
-                    continue;
-                }
+    let mut parts = vec![start_head_position];
+    let mut previous_position: Option<IVec2> = None;
+    let mut current_position = start_head_position;
+    loop {
+        let neighbors: Vec<IVec2> = [UP, DOWN, RIGHT, LEFT]
+            .iter()
+            .map(|direction| current_position + *direction)
+            .filter(|position| is_snake_cell(*position))
+            .collect();
 
-                if grid.cell_at(new_position) == Cell::SnakePart(part_char) {
-                    parts.push(new_position);
-                    current_position = new_position;
-                    break;
-                }
-            }
+        let max_neighbors = if previous_position.is_none() { 1 } else { 2 };
+        if neighbors.len() > max_neighbors {
+            return Err(LevelValidationError::AmbiguousSnake(
+                head_char,
+                current_position,
+            ));
         }
+
+        let Some(next_position) = neighbors
+            .into_iter()
+            .find(|position| Some(*position) != previous_position) else {
+            break;
+        };
+
+        parts.push(next_position);
+        previous_position = Some(current_position);
+        current_position = next_position;
     }
 
     if parts.len() < 2 {
-        bail!(ParseLevelError::InvalidSnake);
+        return Err(LevelValidationError::InvalidSnake(head_char));
     }
 
-    // Infer parts direction from previous part.
-    let directions = parts
+    let mut snake = snake_template_from_positions(&parts);
+
+    // An explicit marker overrides the head direction inferred from the first part.
+    let markers: Vec<char> = [UP, DOWN, RIGHT, LEFT]
         .iter()
-        .zip(parts.iter().skip(1))
-        .map(|(position, prev_position)| *position - *prev_position)
-        .chain(once(parts[parts.len() - 2] - parts[parts.len() - 1]));
+        .map(|direction| start_head_position + *direction)
+        .filter(|position| grid.is_in_bounds(*position))
+        .filter_map(|position| match grid.cell_at(position) {
+            Cell::HeadMarker(marker) => Some(marker),
+            _ => None,
+        })
+        .collect();
 
-    let snake = parts.iter().copied().zip(directions).collect();
+    match markers[..] {
+        [] => {}
+        [marker] => snake[0].1 = head_marker_direction(marker),
+        _ => return Err(LevelValidationError::MultipleHeadMarkers(head_char)),
+    }
 
     Ok(snake)
 }
 
+#[derive(Default)]
+struct LevelHeader {
+    title: Option<String>,
+    par: Option<usize>,
+    water_level: Option<i32>,
+    gravity: Option<IVec2>,
+    goal_condition: GoalCondition,
+    closed_goals: bool,
+}
+
+/// Split a level in its header and its grid at the first line that is exactly the separator.
+/// A row of up one-way walls can also be `---`, so the separator must follow at least one header line
+/// and only `key: value` lines, ':' is not a cell of the grid.
+fn split_header(level_string: &str) -> Option<(&str, &str)> {
+    let mut offset = 0;
+    for line in level_string.split_inclusive('\n') {
+        let content = line.trim_end_matches(&['\n', '\r'][..]);
+        if content == HEADER_SEPARATOR {
+            let has_header = !level_string[..offset].trim().is_empty();
+            return has_header.then_some((
+                &level_string[..offset],
+                &level_string[offset + line.len()..],
+            ));
+        }
+
+        // A row of the grid, the level has no header.
+        if !content.trim().is_empty() && !content.contains(':') {
+            return None;
+        }
+        offset += line.len();
+    }
+
+    None
+}
+
+fn parse_header(header: &str) -> Result<LevelHeader, LevelValidationError> {
+    let mut level_header = LevelHeader::default();
+
+    for line in header
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let Some((key, value)) = line.split_once(':') else {
+            return Err(LevelValidationError::InvalidHeader(line.to_owned()));
+        };
+
+        let value = value.trim();
+        match key.trim() {
+            "title" => level_header.title = Some(value.to_owned()),
+            "par" => {
+                let par = value
+                    .parse()
+                    .map_err(|_| LevelValidationError::InvalidHeader(line.to_owned()))?;
+                level_header.par = Some(par);
+            }
+            "water" => {
+                let water_level = value
+                    .parse()
+                    .map_err(|_| LevelValidationError::InvalidHeader(line.to_owned()))?;
+                level_header.water_level = Some(water_level);
+            }
+            "gravity" => {
+                let gravity = gravity_direction(value)
+                    .ok_or_else(|| LevelValidationError::InvalidHeader(line.to_owned()))?;
+                level_header.gravity = Some(gravity);
+            }
+            "goal" => {
+                level_header.goal_condition = value
+                    .parse()
+                    .map_err(|_| LevelValidationError::InvalidHeader(line.to_owned()))?;
+            }
+            "closed_goals" => {
+                level_header.closed_goals = value
+                    .parse()
+                    .map_err(|_| LevelValidationError::InvalidHeader(line.to_owned()))?;
+            }
+            _ => return Err(LevelValidationError::InvalidHeader(line.to_owned())),
+        }
+    }
+
+    Ok(level_header)
+}
+
 impl LevelTemplate {
-    pub fn parse(level_string: &str) -> Result<LevelTemplate> {
-        let mut grid = level_string.parse::<Grid<Cell>>()?.flip_y();
+    pub fn parse(level_string: &str) -> Result<LevelTemplate, LevelValidationError> {
+        // Levels authored on Windows end their lines with "\r\n", the grid parser only splits on '\n'.
+        let level_string = level_string.replace("\r\n", "\n");
+
+        let (header, grid_string) = match split_header(&level_string) {
+            Some((header, grid_string)) => {
+                (parse_header(header)?, grid_string.trim_start_matches('\n'))
+            }
+            None => (LevelHeader::default(), level_string.as_str()),
+        };
+
+        let mut grid = grid_string
+            .parse::<Grid<Cell>>()
+            .map_err(|error| LevelValidationError::InvalidGrid(error.to_string()))?
+            .flip_y();
 
         // Find and extract the snakes.
         let mut start_heads: Vec<(usize, Cell, char)> = grid
@@ -131,15 +555,22 @@ impl LevelTemplate {
             .collect();
 
         if start_heads.is_empty() {
-            bail!(ParseLevelError::MissingSnakeHead);
+            return Err(LevelValidationError::MissingSnakeHead);
         }
 
         start_heads.sort_by_key(|element| element.2);
 
+        if let Some(duplicate) = start_heads
+            .windows(2)
+            .find(|heads| heads[0].2 == heads[1].2)
+        {
+            return Err(LevelValidationError::DuplicateSnakeHead(duplicate[0].2));
+        }
+
         let snakes: Vec<SnakeTemplate> = start_heads
             .iter()
             .map(|(start_head_index, _, _)| extract_snake_template(&grid, *start_head_index))
-            .collect::<Result<Vec<SnakeTemplate>>>()?;
+            .collect::<Result<Vec<SnakeTemplate>, LevelValidationError>>()?;
 
         // Set the cells where the snakes are as empty, they are managed as part of the game state.
         for snake in &snakes {
@@ -148,47 +579,344 @@ impl LevelTemplate {
             }
         }
 
-        // Find the goal position.
-        let goal_index = grid
-            .cells()
-            .position(|&cell| cell == Cell::Goal)
-            .ok_or(ParseLevelError::MissingLevelGoal)?;
+        // Clear the head markers, they are only valid next to a snake head.
+        for position in grid.find_all(|cell| matches!(cell, Cell::HeadMarker(_))) {
+            let next_to_head = snakes.iter().any(|snake| {
+                let offset = (snake[0].0 - position).abs();
+                offset.x + offset.y == 1
+            });
+            if !next_to_head {
+                return Err(LevelValidationError::StrayHeadMarker(position));
+            }
 
-        let goal_position = grid.position_for_index(goal_index);
+            grid.set_cell(position, Cell::Empty);
+        }
 
-        grid.set_cell(goal_position, Cell::Empty);
+        // Any part left was not reached from its head.
+        if let Some(position) = grid.find(|cell| matches!(cell, Cell::SnakePart(_))) {
+            let Cell::SnakePart(part_char) = grid.cell_at(position) else {
+                unreachable!();
+            };
+            return Err(LevelValidationError::DisconnectedSnakePart(
+                part_char, position,
+            ));
+        }
 
-        // Find the food positons.
-        let food_positions: Vec<IVec2> = grid
+        // Find the goals and set empty.
+        let goals: Vec<(IVec2, Option<i32>)> = grid
             .iter()
-            .filter(|(_, cell)| *cell == Cell::Food)
-            .map(|(position, _)| position)
+            .filter_map(|(position, cell)| match cell {
+                Cell::Goal => Some((position, None)),
+                Cell::SnakeGoal(c) => Some((position, Some(c as i32 - '0' as i32))),
+                _ => None,
+            })
             .collect();
 
-        // And set empty.
-        for position in &food_positions {
+        if goals.is_empty() {
+            return Err(LevelValidationError::MissingLevelGoal);
+        }
+
+        for (position, _) in &goals {
             grid.set_cell(*position, Cell::Empty);
         }
 
-        // Find the spikes positons.
-        let spike_positions: Vec<IVec2> = grid
+        // Find the food, shrink food, spikes and blocks positions and set empty in one pass.
+        let mut food_positions = vec![];
+        let mut shrink_food_positions = vec![];
+        let mut spike_positions = vec![];
+        let mut block_positions = vec![];
+        for (position, cell) in grid.iter_mut() {
+            let positions = match *cell {
+                Cell::Food => &mut food_positions,
+                Cell::ShrinkFood => &mut shrink_food_positions,
+                Cell::Spike(marker) => {
+                    spike_positions.push((position, spike_orientation(marker)));
+                    *cell = Cell::Empty;
+                    continue;
+                }
+                Cell::Block => &mut block_positions,
+                _ => continue,
+            };
+
+            positions.push(position);
+            *cell = Cell::Empty;
+        }
+
+        // Find the one-way walls positons and set empty.
+        let one_way_positions: Vec<(IVec2, IVec2)> = grid
             .iter()
-            .filter(|(_, cell)| *cell == Cell::Spike)
-            .map(|(position, _)| position)
+            .filter_map(|(position, cell)| match cell {
+                Cell::OneWay(marker) => Some((position, one_way_direction(marker))),
+                _ => None,
+            })
             .collect();
+        for (position, _) in &one_way_positions {
+            grid.set_cell(*position, Cell::Empty);
+        }
 
-        // And set empty.
-        for position in &spike_positions {
+        // Find the conveyors positions and set empty.
+        let conveyor_positions: Vec<(IVec2, IVec2)> = grid
+            .iter()
+            .filter_map(|(position, cell)| match cell {
+                Cell::Conveyor(marker) => Some((position, conveyor_direction(marker))),
+                _ => None,
+            })
+            .collect();
+        for (position, _) in &conveyor_positions {
             grid.set_cell(*position, Cell::Empty);
         }
 
-        Ok(LevelTemplate {
+        // Find the portal pairs and set empty.
+        let mut portal_pairs = vec![];
+        for marker in PORTAL_MARKERS {
+            let portal_positions = grid.find_all(|&cell| cell == Cell::Portal(marker));
+            match portal_positions[..] {
+                [] => {}
+                [first, second] => portal_pairs.push((first, second)),
+                _ => {
+                    return Err(LevelValidationError::UnpairedPortal(
+                        marker,
+                        portal_positions.len(),
+                    ))
+                }
+            }
+
+            for position in &portal_positions {
+                grid.set_cell(*position, Cell::Empty);
+            }
+        }
+
+        let level = LevelTemplate {
+            title: header.title,
+            par: header.par,
+            water_level: header.water_level,
+            gravity: header.gravity.unwrap_or(DOWN),
+            goal_condition: header.goal_condition,
+            closed_goals: header.closed_goals,
             grid,
-            goal_position,
+            goals,
             initial_snakes: snakes,
             food_positions,
+            shrink_food_positions,
             spike_positions,
-        })
+            block_positions,
+            one_way_positions,
+            conveyor_positions,
+            portal_pairs,
+        };
+
+        level.validate()?;
+
+        Ok(level)
+    }
+
+    /// Check that the entities of the level don't overlap each other.
+    /// This can't happen for parsed levels but levels built or edited programmatically can end up in such a state.
+    pub fn validate(&self) -> Result<(), LevelValidationError> {
+        let is_wall = |position: IVec2| {
+            self.grid.is_in_bounds(position) && self.grid.cell_at(position) == Cell::Wall
+        };
+        let is_snake = |position: IVec2| {
+            self.initial_snakes
+                .iter()
+                .any(|snake| snake.iter().any(|(part, _)| *part == position))
+        };
+
+        if self.initial_snakes.len() > MAX_SNAKES {
+            return Err(LevelValidationError::TooManySnakes(
+                self.initial_snakes.len(),
+            ));
+        }
+
+        if let GoalCondition::Count(count) = self.goal_condition {
+            if count == 0 || count > self.initial_snakes.len() {
+                return Err(LevelValidationError::InvalidGoalCount(count));
+            }
+        }
+
+        for (position, snake_index) in &self.goals {
+            if is_wall(*position) {
+                return Err(LevelValidationError::GoalOverlapsWall(*position));
+            }
+
+            if let Some(snake_index) = *snake_index {
+                if snake_index < 0 || snake_index as usize >= MAX_SNAKE_GOALS {
+                    return Err(LevelValidationError::SnakeGoalOutOfRange(snake_index));
+                }
+
+                if snake_index as usize >= self.initial_snakes.len() {
+                    return Err(LevelValidationError::GoalWithoutSnake(
+                        (b'0' + snake_index as u8) as char,
+                    ));
+                }
+            }
+        }
+
+        if let Some(position) = self
+            .food_positions
+            .iter()
+            .chain(&self.shrink_food_positions)
+            .find(|position| is_wall(**position) || is_snake(**position))
+        {
+            return Err(LevelValidationError::FoodOverlaps(*position));
+        }
+
+        if let Some(position) = self
+            .spike_positions
+            .iter()
+            .map(|(position, _)| position)
+            .find(|position| is_wall(**position) || is_snake(**position))
+        {
+            return Err(LevelValidationError::SpikeOverlaps(*position));
+        }
+
+        if let Some(position) = self
+            .block_positions
+            .iter()
+            .find(|position| is_wall(**position) || is_snake(**position))
+        {
+            return Err(LevelValidationError::BlockOverlaps(*position));
+        }
+
+        Ok(())
+    }
+
+    /// The size of the level grid in cells.
+    pub fn grid_size(&self) -> IVec2 {
+        IVec2::new(self.grid.width() as i32, self.grid.height() as i32)
+    }
+
+    /// The distance along the gravity past which a falling snake is out of the level, snakes fall a little past
+    /// the edge of the grid before being out. Falling down, the edge is the water line at the bottom of the grid
+    /// unless the level raises it.
+    pub fn fall_out_of_level_limit(&self) -> i32 {
+        let edge = match self.gravity {
+            DOWN => -self.water_level.unwrap_or(0),
+            UP => self.grid.height() as i32 - 1,
+            RIGHT => self.grid.width() as i32 - 1,
+            _ => 0,
+        };
+        edge + FALL_OUT_OF_LEVEL_DEPTH
+    }
+}
+
+/// Write the level back to its ascii representation, such that parsing the output yields the same level.
+impl fmt::Display for LevelTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut grid = self.grid.clone();
+
+        for (position, snake_index) in &self.goals {
+            let cell = match snake_index {
+                Some(snake_index) => Cell::SnakeGoal((b'0' + *snake_index as u8) as char),
+                None => Cell::Goal,
+            };
+            grid.set_cell(*position, cell);
+        }
+
+        for position in &self.food_positions {
+            grid.set_cell(*position, Cell::Food);
+        }
+
+        for position in &self.shrink_food_positions {
+            grid.set_cell(*position, Cell::ShrinkFood);
+        }
+
+        for (position, orientation) in &self.spike_positions {
+            grid.set_cell(*position, Cell::Spike(spike_for_orientation(*orientation)));
+        }
+
+        for position in &self.block_positions {
+            grid.set_cell(*position, Cell::Block);
+        }
+
+        for (position, direction) in &self.one_way_positions {
+            grid.set_cell(*position, Cell::OneWay(one_way_for_direction(*direction)));
+        }
+
+        for (position, direction) in &self.conveyor_positions {
+            grid.set_cell(
+                *position,
+                Cell::Conveyor(conveyor_for_direction(*direction)),
+            );
+        }
+
+        for ((first, second), marker) in self.portal_pairs.iter().zip(PORTAL_MARKERS) {
+            grid.set_cell(*first, Cell::Portal(marker));
+            grid.set_cell(*second, Cell::Portal(marker));
+        }
+
+        for (snake_index, snake) in self.initial_snakes.iter().enumerate() {
+            let head_char = (b'A' + snake_index as u8) as char;
+            let part_char = head_char.to_ascii_lowercase();
+
+            for (part_index, (position, _)) in snake.iter().enumerate() {
+                let cell = if part_index == 0 {
+                    Cell::SnakeHead(head_char)
+                } else {
+                    Cell::SnakePart(part_char)
+                };
+                grid.set_cell(*position, cell);
+            }
+
+            // Write a marker if the head direction can't be inferred from the first part.
+            let (head_position, head_direction) = snake[0];
+            if head_direction != head_position - snake[1].0 {
+                let marker_position = [UP, DOWN, RIGHT, LEFT]
+                    .iter()
+                    .map(|direction| head_position + *direction)
+                    .find(|position| {
+                        grid.is_in_bounds(*position) && grid.cell_at(*position) == Cell::Empty
+                    });
+
+                if let Some(marker_position) = marker_position {
+                    grid.set_cell(
+                        marker_position,
+                        Cell::HeadMarker(head_marker_for_direction(head_direction)),
+                    );
+                }
+            }
+        }
+
+        let level_string = grid.flip_y().format_with(|cell, _| match cell {
+            Cell::Empty => '.',
+            _ => char::from(cell),
+        });
+
+        if let Some(title) = &self.title {
+            writeln!(f, "title: {}", title)?;
+        }
+
+        if let Some(par) = self.par {
+            writeln!(f, "par: {}", par)?;
+        }
+
+        if let Some(water_level) = self.water_level {
+            writeln!(f, "water: {}", water_level)?;
+        }
+
+        if self.gravity != DOWN {
+            writeln!(f, "gravity: {}", gravity_name(self.gravity))?;
+        }
+
+        if self.goal_condition != GoalCondition::default() {
+            writeln!(f, "goal: {}", self.goal_condition)?;
+        }
+
+        if self.closed_goals {
+            writeln!(f, "closed_goals: true")?;
+        }
+
+        if self.title.is_some()
+            || self.par.is_some()
+            || self.water_level.is_some()
+            || self.gravity != DOWN
+            || self.goal_condition != GoalCondition::default()
+            || self.closed_goals
+        {
+            writeln!(f, "{}", HEADER_SEPARATOR)?;
+        }
+
+        write!(f, "{}", level_string)
     }
 }
 
@@ -196,6 +924,43 @@ impl LevelTemplate {
 mod tests {
     use super::*;
 
+    #[test]
+    pub fn test_cell_chars_are_unambiguous() {
+        // Two variants sharing a char would make one of them unreachable when parsing.
+        let expected = [
+            ('#', Cell::Wall),
+            (' ', Cell::Empty),
+            ('.', Cell::Empty),
+            ('o', Cell::Food),
+            ('*', Cell::ShrinkFood),
+            ('X', Cell::Goal),
+            ('0', Cell::SnakeGoal('0')),
+            ('9', Cell::SnakeGoal('9')),
+            ('+', Cell::Spike('+')),
+            ('!', Cell::Spike('!')),
+            ('\'', Cell::Spike('\'')),
+            ('{', Cell::Spike('{')),
+            ('}', Cell::Spike('}')),
+            ('=', Cell::Block),
+            (')', Cell::OneWay(')')),
+            ('(', Cell::OneWay('(')),
+            ('-', Cell::OneWay('-')),
+            ('~', Cell::OneWay('~')),
+            ('[', Cell::Conveyor('[')),
+            (']', Cell::Conveyor(']')),
+            ('@', Cell::Portal('@')),
+            ('&', Cell::Portal('&')),
+            ('A', Cell::SnakeHead('A')),
+            ('Z', Cell::SnakeHead('Z')),
+            ('a', Cell::SnakePart('a')),
+            ('z', Cell::SnakePart('z')),
+        ];
+
+        for (c, cell) in expected {
+            assert_eq!(Cell::try_from(c).ok(), Some(cell), "char '{}'", c);
+        }
+    }
+
     #[test]
     pub fn test_snake_extraction_complex() {
         const LEVEL: &str = "..A.....\n\
@@ -210,4 +975,283 @@ mod tests {
         assert_eq!(level.initial_snakes[0].len(), 4);
         assert_eq!(level.initial_snakes[1].len(), 3);
     }
+
+    #[test]
+    pub fn test_multiple_goals() {
+        const LEVEL: &str = "X..1X\n\
+        .aA0.\n\
+        bB###";
+
+        let level = LevelTemplate::parse(LEVEL).unwrap();
+        assert_eq!(
+            level.goals,
+            vec![
+                (IVec2::new(3, 1), Some(0)),
+                (IVec2::new(0, 2), None),
+                (IVec2::new(3, 2), Some(1)),
+                (IVec2::new(4, 2), None),
+            ]
+        );
+        assert_eq!(level.to_string(), LEVEL);
+
+        assert_eq!(
+            LevelTemplate::parse("X..2X\n.aA..\n#####").unwrap_err(),
+            LevelValidationError::GoalWithoutSnake('2')
+        );
+    }
+
+    #[test]
+    pub fn test_crlf_line_endings() {
+        const LEVEL_LF: &str = "....X\n.aA..\n#####";
+        const LEVEL_CRLF: &str = "....X\r\n.aA..\r\n#####";
+
+        let level_lf = LevelTemplate::parse(LEVEL_LF).unwrap();
+        let level_crlf = LevelTemplate::parse(LEVEL_CRLF).unwrap();
+
+        assert_eq!(level_lf.grid.to_string(), level_crlf.grid.to_string());
+        assert_eq!(level_lf.goals, level_crlf.goals);
+        assert_eq!(level_lf.initial_snakes, level_crlf.initial_snakes);
+    }
+
+    #[test]
+    pub fn test_to_string_round_trip() {
+        const LEVEL: &str = "@.A....o\n\
+        #.aBbb.}\n\
+        #.aa*.X+\n\
+        #.-#=(Cc\n\
+        ...@[]..";
+
+        let level = LevelTemplate::parse(LEVEL).unwrap();
+        let round_trip = LevelTemplate::parse(&level.to_string()).unwrap();
+
+        assert_eq!(level.to_string(), LEVEL);
+        assert_eq!(level.grid.to_string(), round_trip.grid.to_string());
+        assert_eq!(level.goals, round_trip.goals);
+        assert_eq!(level.initial_snakes, round_trip.initial_snakes);
+        assert_eq!(level.food_positions, round_trip.food_positions);
+        assert_eq!(
+            level.shrink_food_positions,
+            round_trip.shrink_food_positions
+        );
+        assert_eq!(level.spike_positions, round_trip.spike_positions);
+        assert_eq!(level.block_positions, round_trip.block_positions);
+        assert_eq!(level.one_way_positions, round_trip.one_way_positions);
+        assert_eq!(level.conveyor_positions, round_trip.conveyor_positions);
+        assert_eq!(level.portal_pairs, round_trip.portal_pairs);
+    }
+
+    #[test]
+    pub fn test_one_way_row_after_header() {
+        // A row of up one-way walls reads like the header separator.
+        const LEVEL: &str = "title: Ledge\n\
+        ---\n\
+        ....X\n\
+        .aaA.\n\
+        #---#\n\
+        #####";
+
+        let level = LevelTemplate::parse(LEVEL).unwrap();
+        assert_eq!(level.title.as_deref(), Some("Ledge"));
+        assert_eq!(level.grid.height(), 4);
+        assert_eq!(
+            level.one_way_positions,
+            vec![
+                (IVec2::new(1, 1), UP),
+                (IVec2::new(2, 1), UP),
+                (IVec2::new(3, 1), UP)
+            ]
+        );
+        assert_eq!(level.to_string(), LEVEL);
+    }
+
+    #[test]
+    pub fn test_validation_errors() {
+        assert_eq!(
+            LevelTemplate::parse("..X\nA..\n###").unwrap_err(),
+            LevelValidationError::InvalidSnake('A')
+        );
+
+        assert_eq!(
+            LevelTemplate::parse("..X.\naA.A\n...a").unwrap_err(),
+            LevelValidationError::DuplicateSnakeHead('A')
+        );
+
+        assert_eq!(
+            LevelTemplate::parse("..X.\naA..\n...a").unwrap_err(),
+            LevelValidationError::DisconnectedSnakePart('a', IVec2::new(3, 0))
+        );
+
+        assert_eq!(
+            LevelTemplate::parse("@.X.\naA..\n####").unwrap_err(),
+            LevelValidationError::UnpairedPortal('@', 1)
+        );
+
+        let mut level = LevelTemplate::parse("..X.\naA..\n####").unwrap();
+        level.goals[0].0 = IVec2::new(0, 0);
+        assert_eq!(
+            level.validate(),
+            Err(LevelValidationError::GoalOverlapsWall(IVec2::new(0, 0)))
+        );
+
+        let mut level = LevelTemplate::parse("..X.\naA..\n####").unwrap();
+        level.food_positions.push(IVec2::new(1, 1));
+        assert_eq!(
+            level.validate(),
+            Err(LevelValidationError::FoodOverlaps(IVec2::new(1, 1)))
+        );
+    }
+
+    #[test]
+    pub fn test_snake_caps_of_built_levels() {
+        // A level string can't go over the caps, but a level built programmatically can.
+        let mut level = LevelTemplate::parse("..X.\naA..\n####").unwrap();
+        level.initial_snakes = vec![level.initial_snakes[0].clone(); MAX_SNAKES + 1];
+        assert_eq!(
+            level.validate(),
+            Err(LevelValidationError::TooManySnakes(MAX_SNAKES + 1))
+        );
+
+        let mut level = LevelTemplate::parse("..X.\naA..\n####").unwrap();
+        level.initial_snakes = vec![level.initial_snakes[0].clone(); MAX_SNAKE_GOALS + 1];
+        level.goals[0].1 = Some(MAX_SNAKE_GOALS as i32);
+        assert_eq!(
+            level.validate(),
+            Err(LevelValidationError::SnakeGoalOutOfRange(
+                MAX_SNAKE_GOALS as i32
+            ))
+        );
+    }
+
+    #[test]
+    pub fn test_snake_touching_itself_is_an_error() {
+        // The body can be followed in two different orders from the part next to the head.
+        const SNAKES_BUG: &str = "....X\n\
+        Aaa..\n\
+        .aa..\n\
+        #####";
+
+        assert_eq!(
+            LevelTemplate::parse(SNAKES_BUG).unwrap_err(),
+            LevelValidationError::AmbiguousSnake('A', IVec2::new(1, 2))
+        );
+    }
+
+    #[test]
+    pub fn test_head_markers() {
+        const LEVEL: &str = "...^X\n\
+        .aaA.\n\
+        #####";
+
+        let level = LevelTemplate::parse(LEVEL).unwrap();
+        assert_eq!(level.initial_snakes[0][0], (IVec2::new(3, 1), UP));
+        assert_eq!(level.grid.cell_at(IVec2::new(3, 2)), Cell::Empty);
+        assert_eq!(level.to_string(), LEVEL);
+
+        let level = LevelTemplate::parse("....X\n.aaA.\n#####").unwrap();
+        assert_eq!(level.initial_snakes[0][0], (IVec2::new(3, 1), RIGHT));
+
+        assert_eq!(
+            LevelTemplate::parse("..>.X\n.aaA.\n#####").unwrap_err(),
+            LevelValidationError::StrayHeadMarker(IVec2::new(2, 2))
+        );
+
+        assert_eq!(
+            LevelTemplate::parse("...^X\n.aaA>\n#####").unwrap_err(),
+            LevelValidationError::MultipleHeadMarkers('A')
+        );
+    }
+
+    #[test]
+    pub fn test_header() {
+        const LEVEL: &str = "title: First steps\n\
+        par: 4\n\
+        water: 1\n\
+        gravity: left\n\
+        goal: any\n\
+        closed_goals: true\n\
+        ---\n\
+        ....X\n\
+        .aaA.\n\
+        #####";
+
+        let level = LevelTemplate::parse(LEVEL).unwrap();
+        assert_eq!(level.title.as_deref(), Some("First steps"));
+        assert_eq!(level.par, Some(4));
+        assert_eq!(level.water_level, Some(1));
+        assert_eq!(level.gravity, LEFT);
+        assert_eq!(level.goal_condition, GoalCondition::AnySnake);
+        assert!(level.closed_goals);
+        assert_eq!(level.grid.height(), 3);
+        assert_eq!(level.to_string(), LEVEL);
+
+        assert_eq!(
+            LevelTemplate::parse("unknown: 1\n---\n....X\n.aaA.\n#####").unwrap_err(),
+            LevelValidationError::InvalidHeader("unknown: 1".to_owned())
+        );
+
+        assert_eq!(
+            LevelTemplate::parse("gravity: sideways\n---\n....X\n.aaA.\n#####").unwrap_err(),
+            LevelValidationError::InvalidHeader("gravity: sideways".to_owned())
+        );
+
+        assert_eq!(
+            LevelTemplate::parse("par: many\n---\n....X\n.aaA.\n#####").unwrap_err(),
+            LevelValidationError::InvalidHeader("par: many".to_owned())
+        );
+
+        assert_eq!(
+            LevelTemplate::parse("goal: 2\n---\n....X\n.aaA.\n#####").unwrap_err(),
+            LevelValidationError::InvalidGoalCount(2)
+        );
+    }
+
+    #[test]
+    pub fn test_header_separator_in_title_and_grid() {
+        const LEVEL: &str = "title: Before---After\n\
+        ---\n\
+        ....X\n\
+        .aaA.\n\
+        #---#";
+
+        let level = LevelTemplate::parse(LEVEL).unwrap();
+        assert_eq!(level.title.as_deref(), Some("Before---After"));
+        assert_eq!(level.grid.height(), 3);
+        assert_eq!(level.one_way_positions.len(), 3);
+        assert_eq!(level.to_string(), LEVEL);
+
+        // Without a header, a row with `---` is part of the grid.
+        let level = LevelTemplate::parse("....X\n.aaA.\n#---#\n#####").unwrap();
+        assert_eq!(level.title, None);
+        assert_eq!(level.grid.height(), 4);
+    }
+
+    #[test]
+    pub fn test_one_way_row_without_header() {
+        // Rows of up one-way walls that read like the header separator.
+        let level = LevelTemplate::parse("..X\naA.\n---\n###").unwrap();
+        assert_eq!(level.title, None);
+        assert_eq!(level.grid.height(), 4);
+        assert_eq!(level.one_way_positions.len(), 3);
+
+        let level = LevelTemplate::parse("....X\n.aaA.\n  ---\n#####").unwrap();
+        assert_eq!(level.grid.height(), 4);
+        assert_eq!(level.one_way_positions.len(), 3);
+
+        let level = LevelTemplate::parse("---\n..X\naA.\n###").unwrap();
+        assert_eq!(level.grid.height(), 4);
+        assert_eq!(level.one_way_positions.len(), 3);
+    }
+
+    #[test]
+    pub fn test_goal_condition() {
+        assert!(!GoalCondition::AllSnakes.is_met(1, 2));
+        assert!(GoalCondition::AllSnakes.is_met(2, 2));
+        assert!(GoalCondition::AnySnake.is_met(1, 2));
+        assert!(!GoalCondition::Count(2).is_met(1, 3));
+        assert!(GoalCondition::Count(2).is_met(2, 3));
+
+        assert_eq!("any".parse(), Ok(GoalCondition::AnySnake));
+        assert_eq!("3".parse(), Ok(GoalCondition::Count(3)));
+        assert_eq!("some".parse::<GoalCondition>(), Err(()));
+    }
 }
@@ -217,9 +217,9 @@ const LEVEL_15: &str = ".............
 ......#....X.
 .............
 ...#.....#...
-..bbB........
-.#b.b........
-.#Bbb........
+..bbC........
+.#b.c........
+.#Bcc........
 .####........
 .............";
 
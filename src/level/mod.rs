@@ -1,3 +1,7 @@
+pub mod game_state;
+pub mod grid_ext;
+pub mod grid_stack;
+pub mod level_generator;
 pub mod level_instance;
 pub mod level_template;
 pub mod levels;
@@ -35,10 +35,65 @@ pub const EXIT_ON_JUMP: &str = ".......
 ....a....
 ###########";
 
-pub const TEST_LEVELS: [&str; 5] = [
+pub const PUSH_CHAIN: &str = "..........X
+...........
+.aAbBcC....
+###########";
+
+pub const PUSH_BLOCK_OFF_LEDGE: &str = "..........X
+...........
+.aA=.......
+#####......
+###########";
+
+pub const STACKED_SNAKES_FALL: &str = "......X....
+...aA......
+...bB......
+...........
+...........
+###########";
+
+pub const RAISED_WATER: &str = "water: 2
+---
+......X....
+.aA........
+###........
+#..........
+###########";
+
+pub const ONE_WAY_WALLS: &str = "..........X
+...........
+.)..Aa.....
+..----.....
+###########";
+
+pub const PORTALS: &str = "..........X
+...........
+.aA@....@..
+###########";
+
+pub const SNAKE_GOALS: &str = "...........
+.aA.1.0.Bb.
+###########";
+
+pub const CONVEYORS: &str = ".........X
+..........
+.aA.=.....
+.]]]]]....
+##########";
+
+pub const TEST_LEVELS: [&str; 13] = [
     EXIT_ON_JUMP,
     BUG_SNAKES_ON_TOP,
     FALL_ON_SPIKE,
     FALL_ON_SNAKE_BUG,
     ACTIVATE_ON_EAT,
+    PUSH_CHAIN,
+    PUSH_BLOCK_OFF_LEDGE,
+    STACKED_SNAKES_FALL,
+    RAISED_WATER,
+    ONE_WAY_WALLS,
+    PORTALS,
+    SNAKE_GOALS,
+    CONVEYORS,
 ];
@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::{AppLooplessStateExt, ConditionSet};
+
+use crate::{
+    despawn_with,
+    gameplay::{
+        level_pluggin::RestartLevelEvent,
+        movement_pluggin::GravityFall,
+        snake_pluggin::Snake,
+        undo::{PlayerUndoEvent, SnakeHistory},
+    },
+    menus::pause_menu::Paused,
+    GameState,
+};
+
+use super::{
+    button_interact_visual_system, DisabledButton, MenuStyles, DISABLED_BUTTON_TEXT_COLOR,
+};
+
+/// On screen undo and restart buttons, for the players without a keyboard.
+pub struct GameControlsPlugin;
+
+impl Plugin for GameControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_enter_system(GameState::Game, setup_controls)
+            .add_exit_system(GameState::Game, despawn_with::<GameControls>)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Game)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(button_interact_visual_system)
+                    .with_system(on_control_button_interact_system)
+                    .into(),
+            )
+            .add_system(disable_control_buttons_system.run_in_state(GameState::Game));
+    }
+}
+
+#[derive(Component)]
+struct GameControls;
+
+#[derive(Component, Clone, Copy)]
+enum ControlButton {
+    Undo,
+    Restart,
+}
+
+fn setup_controls(mut commands: Commands, menu_styles: Res<MenuStyles>) {
+    let controls = commands
+        .spawn((
+            NodeBundle {
+                background_color: BackgroundColor(Color::NONE),
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        top: Val::Px(8.0),
+                        right: Val::Px(8.0),
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::Row,
+                    ..default()
+                },
+                ..default()
+            },
+            GameControls,
+        ))
+        .id();
+
+    let mut buttons = vec![];
+    for (button, label) in [
+        (ControlButton::Undo, "Undo"),
+        (ControlButton::Restart, "Restart"),
+    ] {
+        let button = commands
+            .spawn((
+                ButtonBundle {
+                    style: menu_styles.button_style.clone(),
+                    background_color: BackgroundColor(Color::NONE),
+                    ..default()
+                },
+                button,
+                DisabledButton,
+            ))
+            .with_children(|parent| {
+                parent.spawn(TextBundle {
+                    text: Text::from_section(
+                        label,
+                        TextStyle {
+                            color: DISABLED_BUTTON_TEXT_COLOR,
+                            ..menu_styles.button_text_style.clone()
+                        },
+                    ),
+                    ..default()
+                });
+            })
+            .id();
+        buttons.push(button);
+    }
+
+    commands.entity(controls).push_children(&buttons);
+}
+
+/// The buttons are disabled when there is no move to undo.
+fn disable_control_buttons_system(
+    mut commands: Commands,
+    snake_history: Option<Res<SnakeHistory>>,
+    buttons_query: Query<(Entity, &Children, Option<&DisabledButton>), With<ControlButton>>,
+    mut text_query: Query<&mut Text>,
+) {
+    let enabled = snake_history.map_or(false, |history| history.can_undo());
+
+    for (entity, children, disabled) in &buttons_query {
+        if enabled != disabled.is_some() {
+            continue;
+        }
+
+        let mut text = text_query.get_mut(children[0]).unwrap();
+        if enabled {
+            commands.entity(entity).remove::<DisabledButton>();
+            text.sections[0].style.color = Color::BLACK;
+        } else {
+            commands.entity(entity).insert(DisabledButton);
+            text.sections[0].style.color = DISABLED_BUTTON_TEXT_COLOR;
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn on_control_button_interact_system(
+    query: Query<
+        (&Interaction, &ControlButton),
+        (Changed<Interaction>, With<Button>, Without<DisabledButton>),
+    >,
+    falling_snakes: Query<(With<Snake>, With<GravityFall>)>,
+    mut player_undo_event: EventWriter<PlayerUndoEvent>,
+    mut event_restart_level: EventWriter<RestartLevelEvent>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match button {
+            ControlButton::Undo => {
+                // Same as the keyboard, undoing is not allowed while the snakes fall.
+                if falling_snakes.is_empty() {
+                    player_undo_event.send(PlayerUndoEvent);
+                }
+            }
+            ControlButton::Restart => event_restart_level.send(RestartLevelEvent),
+        }
+    }
+}
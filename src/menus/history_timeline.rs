@@ -0,0 +1,161 @@
+use bevy::prelude::*;
+use iyes_loopless::prelude::{AppLooplessStateExt, ConditionSet};
+
+use crate::{
+    despawn_with,
+    gameplay::{
+        game_constants_pluggin::{GameConstants, DOWN, LEFT, RIGHT, UP},
+        movement_pluggin::GravityFall,
+        snake_pluggin::Snake,
+        undo::{SnakeHistory, UndoToMoveEvent},
+    },
+    menus::pause_menu::Paused,
+    GameState,
+};
+
+use super::MenuStyles;
+
+/// The number of recent moves shown in the timeline.
+const TIMELINE_LENGTH: usize = 24;
+
+/// The player moves of the level as arrows at the bottom of the screen, clicking a move undoes the moves after it.
+pub struct HistoryTimelinePlugin;
+
+impl Plugin for HistoryTimelinePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_exit_system(GameState::Game, despawn_with::<HistoryTimeline>)
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Game)
+                    .run_if_resource_exists::<SnakeHistory>()
+                    .with_system(update_timeline_system)
+                    .into(),
+            )
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Game)
+                    .run_unless_resource_exists::<Paused>()
+                    .with_system(on_timeline_button_interact_system)
+                    .into(),
+            );
+    }
+}
+
+#[derive(Component)]
+struct HistoryTimeline;
+
+/// A move of the timeline, clicking it undoes the moves until this number of moves is left.
+#[derive(Component)]
+struct TimelineMove(usize);
+
+fn direction_arrow(direction: IVec2) -> &'static str {
+    match direction {
+        RIGHT => ">",
+        LEFT => "<",
+        UP => "^",
+        DOWN => "v",
+        _ => "?",
+    }
+}
+
+/// Rebuild the timeline when the player moves change.
+fn update_timeline_system(
+    mut commands: Commands,
+    snake_history: Res<SnakeHistory>,
+    menu_styles: Res<MenuStyles>,
+    constants: Res<GameConstants>,
+    timeline_query: Query<Entity, With<HistoryTimeline>>,
+    mut shown_moves: Local<Option<Vec<(i32, IVec2)>>>,
+) {
+    if !snake_history.is_changed() {
+        return;
+    }
+
+    let player_moves = snake_history.player_moves();
+    if shown_moves.as_ref() == Some(&player_moves) && !timeline_query.is_empty() {
+        return;
+    }
+
+    for entity in &timeline_query {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let timeline = commands
+        .spawn((
+            NodeBundle {
+                background_color: BackgroundColor(Color::NONE),
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        bottom: Val::Px(8.0),
+                        left: Val::Px(8.0),
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            HistoryTimeline,
+        ))
+        .id();
+
+    let button_style = Style {
+        size: Size::new(Val::Px(28.0), Val::Px(28.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        margin: UiRect::all(Val::Px(2.0)),
+        ..default()
+    };
+
+    let mut buttons = vec![];
+    let mut spawn_button = |label: &str, move_count: usize, color: Color| {
+        let button = commands
+            .spawn((
+                ButtonBundle {
+                    style: button_style.clone(),
+                    background_color: BackgroundColor(color),
+                    ..default()
+                },
+                TimelineMove(move_count),
+            ))
+            .with_children(|parent| {
+                parent.spawn(TextBundle {
+                    text: Text::from_section(label, menu_styles.button_text_style.clone()),
+                    ..default()
+                });
+            })
+            .id();
+        buttons.push(button);
+    };
+
+    // Going back to the start of the level is always possible, the older moves are hidden.
+    spawn_button("|", 0, Color::NONE);
+    let first_shown = player_moves.len().saturating_sub(TIMELINE_LENGTH);
+    for (index, (snake_index, direction)) in player_moves.iter().enumerate().skip(first_shown) {
+        let color = constants.palette.snake_colors(*snake_index)[1].with_a(0.6);
+        spawn_button(direction_arrow(*direction), index + 1, color);
+    }
+
+    commands.entity(timeline).push_children(&buttons);
+    *shown_moves = Some(player_moves);
+}
+
+#[allow(clippy::type_complexity)]
+fn on_timeline_button_interact_system(
+    query: Query<(&Interaction, &TimelineMove), (Changed<Interaction>, With<Button>)>,
+    falling_snakes: Query<(With<Snake>, With<GravityFall>)>,
+    mut undo_to_move_event: EventWriter<UndoToMoveEvent>,
+) {
+    for (interaction, timeline_move) in query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        // Same as the undo key, undoing is not allowed while the snakes fall.
+        if falling_snakes.is_empty() {
+            undo_to_move_event.send(UndoToMoveEvent(timeline_move.0));
+        }
+    }
+}
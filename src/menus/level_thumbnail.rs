@@ -0,0 +1,187 @@
+//! Small images of the levels for the level select menu, rasterized from the level templates.
+
+use bevy::{
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+    utils::HashMap,
+};
+
+use crate::{
+    gameplay::game_constants_pluggin::{Palette, BACKGROUND_COLOR, GOAL_COLOR, WALL_COLOR},
+    level::level_template::{Cell, LevelTemplate},
+};
+
+/// The size in pixels of a cell of the level in the thumbnails.
+pub const THUMBNAIL_CELL_PIXELS: usize = 4;
+
+/// The thumbnails of the levels by level index, generated once per level for the current palette.
+#[derive(Resource, Default)]
+pub struct LevelThumbnails {
+    palette: Palette,
+    thumbnails: HashMap<usize, Handle<Image>>,
+}
+
+impl LevelThumbnails {
+    /// The thumbnail of a level, generated on the first request.
+    pub fn get_or_create(
+        &mut self,
+        level_index: usize,
+        level: &LevelTemplate,
+        palette: Palette,
+        images: &mut Assets<Image>,
+    ) -> Handle<Image> {
+        if palette != self.palette {
+            self.palette = palette;
+            self.thumbnails.clear();
+        }
+
+        self.thumbnails
+            .entry(level_index)
+            .or_insert_with(|| images.add(level_thumbnail(level, palette)))
+            .clone()
+    }
+}
+
+/// Rasterize a level to an image, each cell of the level is a square of `THUMBNAIL_CELL_PIXELS` pixels.
+pub fn level_thumbnail(level: &LevelTemplate, palette: Palette) -> Image {
+    let (width, height, pixels) = thumbnail_pixels(level, palette);
+
+    Image::new(
+        Extent3d {
+            width: width as u32,
+            height: height as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixels,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// The width, height and rgba pixels of the thumbnail of a level, the rows start from the top of the level.
+pub fn thumbnail_pixels(level: &LevelTemplate, palette: Palette) -> (usize, usize, Vec<u8>) {
+    let grid_width = level.grid.width();
+    let grid_height = level.grid.height();
+    let width = grid_width * THUMBNAIL_CELL_PIXELS;
+    let height = grid_height * THUMBNAIL_CELL_PIXELS;
+
+    let mut pixels: Vec<u8> = BACKGROUND_COLOR
+        .as_rgba_u8()
+        .into_iter()
+        .cycle()
+        .take(width * height * 4)
+        .collect();
+
+    let mut fill_cell = |position: IVec2, color: Color| {
+        if position.x < 0
+            || position.y < 0
+            || position.x as usize >= grid_width
+            || position.y as usize >= grid_height
+        {
+            return;
+        }
+
+        // The grid y axis points up, the image rows go down.
+        let left = position.x as usize * THUMBNAIL_CELL_PIXELS;
+        let top = (grid_height - 1 - position.y as usize) * THUMBNAIL_CELL_PIXELS;
+        let color = color.as_rgba_u8();
+        for y in top..top + THUMBNAIL_CELL_PIXELS {
+            for x in left..left + THUMBNAIL_CELL_PIXELS {
+                let index = (y * width + x) * 4;
+                pixels[index..index + 4].copy_from_slice(&color);
+            }
+        }
+    };
+
+    let colors = palette.colors();
+
+    for (position, cell) in level.grid.iter() {
+        if cell == Cell::Wall {
+            fill_cell(position, WALL_COLOR);
+        }
+    }
+
+    for (position, _) in &level.one_way_positions {
+        fill_cell(*position, WALL_COLOR);
+    }
+
+    for (position, _) in &level.conveyor_positions {
+        fill_cell(*position, WALL_COLOR);
+    }
+
+    for (position, snake_index) in &level.goals {
+        let color = match snake_index {
+            Some(snake_index) => palette.snake_colors(*snake_index)[0],
+            None => GOAL_COLOR,
+        };
+        fill_cell(*position, color);
+    }
+
+    for position in &level.food_positions {
+        fill_cell(*position, colors.food);
+    }
+
+    for position in &level.shrink_food_positions {
+        fill_cell(*position, colors.shrink_food);
+    }
+
+    for (position, _) in &level.spike_positions {
+        fill_cell(*position, colors.spike);
+    }
+
+    for position in &level.block_positions {
+        fill_cell(*position, colors.block);
+    }
+
+    for (first, second) in &level.portal_pairs {
+        fill_cell(*first, colors.portal);
+        fill_cell(*second, colors.portal);
+    }
+
+    for (snake_index, snake) in level.initial_snakes.iter().enumerate() {
+        let color = palette.snake_colors(snake_index as i32)[0];
+        for (position, _) in snake {
+            fill_cell(*position, color);
+        }
+    }
+
+    (width, height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_thumbnail_pixels() {
+        let level = LevelTemplate::parse("X..o\n.aA.\n####").unwrap();
+        let (width, height, pixels) = thumbnail_pixels(&level, Palette::Default);
+
+        assert_eq!(width, 4 * THUMBNAIL_CELL_PIXELS);
+        assert_eq!(height, 3 * THUMBNAIL_CELL_PIXELS);
+        assert_eq!(pixels.len(), width * height * 4);
+
+        let pixel_at_cell = |x: usize, row: usize| {
+            let index = ((row * THUMBNAIL_CELL_PIXELS) * width + x * THUMBNAIL_CELL_PIXELS) * 4;
+            [
+                pixels[index],
+                pixels[index + 1],
+                pixels[index + 2],
+                pixels[index + 3],
+            ]
+        };
+
+        // The first row of the level is the top of the image.
+        assert_eq!(pixel_at_cell(0, 0), GOAL_COLOR.as_rgba_u8());
+        assert_eq!(
+            pixel_at_cell(3, 0),
+            Palette::Default.colors().food.as_rgba_u8()
+        );
+        assert_eq!(pixel_at_cell(1, 0), BACKGROUND_COLOR.as_rgba_u8());
+        assert_eq!(
+            pixel_at_cell(2, 1),
+            Palette::Default.snake_colors(0)[0].as_rgba_u8()
+        );
+        assert_eq!(pixel_at_cell(0, 2), WALL_COLOR.as_rgba_u8());
+    }
+}
@@ -1,13 +1,26 @@
 use bevy::prelude::*;
 
+pub mod game_controls;
+pub mod history_timeline;
+pub mod level_thumbnail;
 pub mod main_menu;
+pub mod pause_menu;
 pub mod select_level_menu;
 
 pub const FONT: &str = "Comfortaa-Regular.ttf";
 
+/// A button that can't be clicked, its text is greyed out and doesn't react to interactions.
+#[derive(Component)]
+pub struct DisabledButton;
+
+pub(crate) const DISABLED_BUTTON_TEXT_COLOR: Color = Color::rgb(0.75, 0.75, 0.75);
+
 #[allow(clippy::type_complexity)]
 pub fn button_interact_visual_system(
-    mut button_query: Query<(&Interaction, &Children), (Changed<Interaction>, With<Button>)>,
+    mut button_query: Query<
+        (&Interaction, &Children),
+        (Changed<Interaction>, With<Button>, Without<DisabledButton>),
+    >,
     mut text_query: Query<&mut Text>,
 ) {
     for (interaction, children) in &mut button_query {
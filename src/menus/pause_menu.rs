@@ -0,0 +1,172 @@
+use bevy::prelude::*;
+use iyes_loopless::{
+    prelude::{AppLooplessStateExt, ConditionSet, IntoConditionalSystem},
+    state::NextState,
+};
+
+use crate::{
+    despawn_with,
+    gameplay::level_pluggin::{ClearLevelEvent, RestartLevelEvent},
+    GameState,
+};
+
+use super::{button_interact_visual_system, MenuStyles};
+
+/// Present while the game is paused, the gameplay input systems don't run while it exists.
+#[derive(Resource)]
+pub struct Paused;
+
+pub struct PauseMenuPlugin;
+
+impl Plugin for PauseMenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_exit_system(GameState::Game, despawn_with::<PauseMenu>)
+            .add_exit_system(GameState::Game, remove_paused_system)
+            .add_system(
+                pause_on_escape_system
+                    .run_in_state(GameState::Game)
+                    .run_unless_resource_exists::<Paused>(),
+            )
+            .add_system_set(
+                ConditionSet::new()
+                    .run_in_state(GameState::Game)
+                    .run_if_resource_exists::<Paused>()
+                    .with_system(resume_on_escape_system)
+                    .with_system(button_interact_visual_system)
+                    .with_system(on_pause_button_interact_system)
+                    .into(),
+            );
+    }
+}
+
+#[derive(Component)]
+struct PauseMenu;
+
+#[derive(Component, Clone, Copy)]
+enum PauseButton {
+    Resume,
+    Restart,
+    QuitToMenu,
+}
+
+fn pause_on_escape_system(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    menu_styles: Res<MenuStyles>,
+) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        commands.insert_resource(Paused);
+        spawn_pause_menu(&mut commands, &menu_styles);
+    }
+}
+
+fn resume_on_escape_system(
+    mut commands: Commands,
+    keyboard: Res<Input<KeyCode>>,
+    pause_menu_query: Query<Entity, With<PauseMenu>>,
+) {
+    if keyboard.just_pressed(KeyCode::Escape) {
+        resume(&mut commands, &pause_menu_query);
+    }
+}
+
+fn remove_paused_system(mut commands: Commands) {
+    commands.remove_resource::<Paused>();
+}
+
+fn resume(commands: &mut Commands, pause_menu_query: &Query<Entity, With<PauseMenu>>) {
+    commands.remove_resource::<Paused>();
+    for entity in pause_menu_query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn on_pause_button_interact_system(
+    mut commands: Commands,
+    query: Query<(&Interaction, &PauseButton), (Changed<Interaction>, With<Button>)>,
+    pause_menu_query: Query<Entity, With<PauseMenu>>,
+    mut event_restart_level: EventWriter<RestartLevelEvent>,
+    mut event_clear_level: EventWriter<ClearLevelEvent>,
+) {
+    for (interaction, button) in query.iter() {
+        if *interaction != Interaction::Clicked {
+            continue;
+        }
+
+        match button {
+            PauseButton::Resume => resume(&mut commands, &pause_menu_query),
+            PauseButton::Restart => {
+                resume(&mut commands, &pause_menu_query);
+                event_restart_level.send(RestartLevelEvent);
+            }
+            PauseButton::QuitToMenu => {
+                event_clear_level.send(ClearLevelEvent);
+                commands.insert_resource(NextState(GameState::MainMenu));
+            }
+        }
+    }
+}
+
+fn spawn_pause_menu(commands: &mut Commands, menu_styles: &MenuStyles) {
+    let overlay = commands
+        .spawn((
+            NodeBundle {
+                background_color: BackgroundColor(Color::rgba(1.0, 1.0, 1.0, 0.6)),
+                style: Style {
+                    size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                    position_type: PositionType::Absolute,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            PauseMenu,
+        ))
+        .id();
+
+    let menu = commands
+        .spawn(NodeBundle {
+            background_color: BackgroundColor(Color::NONE),
+            style: menu_styles.layout_node_style.clone(),
+            ..Default::default()
+        })
+        .id();
+
+    let title = commands
+        .spawn(TextBundle {
+            text: Text::from_section("Paused", menu_styles.title_style.clone()),
+            style: menu_styles.button_style.clone(),
+            ..Default::default()
+        })
+        .id();
+
+    let mut children = vec![title];
+    for (button, label) in [
+        (PauseButton::Resume, "Resume"),
+        (PauseButton::Restart, "Restart"),
+        (PauseButton::QuitToMenu, "Quit to Menu"),
+    ] {
+        let button = commands
+            .spawn((
+                ButtonBundle {
+                    style: menu_styles.button_style.clone(),
+                    background_color: BackgroundColor(Color::NONE),
+                    ..Default::default()
+                },
+                button,
+            ))
+            .with_children(|parent| {
+                parent.spawn(TextBundle {
+                    text: Text::from_section(label, menu_styles.button_text_style.clone()),
+                    ..Default::default()
+                });
+            })
+            .id();
+        children.push(button);
+    }
+
+    commands.entity(menu).push_children(&children);
+    commands.entity(overlay).add_child(menu);
+}
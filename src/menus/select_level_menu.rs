@@ -1,12 +1,21 @@
-use bevy::prelude::*;
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+    utils::HashSet,
+};
 use iyes_loopless::{
     prelude::{AppLooplessStateExt, ConditionSet},
     state::NextState,
 };
 
-use crate::{despawn_with, level::levels::LEVELS, GameState};
+use crate::{
+    despawn_with,
+    gameplay::{game_constants_pluggin::GameConstants, level_pluggin::LevelCompletedEvent},
+    level::{level_template::LevelTemplate, levels::LEVELS},
+    GameState,
+};
 
-use super::{button_interact_visual_system, MenuStyles};
+use super::{button_interact_visual_system, level_thumbnail::LevelThumbnails, MenuStyles};
 
 pub struct SelectLevelMenuPlugin;
 
@@ -15,6 +24,9 @@ impl Plugin for SelectLevelMenuPlugin {
         app.add_enter_system(GameState::SelectLevelMenu, setup_camera)
             .add_enter_system(GameState::SelectLevelMenu, setup_menu)
             .add_exit_system(GameState::SelectLevelMenu, despawn_with::<SelectLevelMenu>)
+            .init_resource::<CompletedLevels>()
+            .init_resource::<LevelThumbnails>()
+            .add_system(record_completed_level_system)
             .add_system_set(
                 ConditionSet::new()
                     .run_in_state(GameState::SelectLevelMenu)
@@ -22,6 +34,7 @@ impl Plugin for SelectLevelMenuPlugin {
                     .with_system(button_interact_visual_system)
                     .with_system(on_back_button_interact_system)
                     .with_system(on_level_button_interact_system)
+                    .with_system(scroll_level_list_system)
                     .into(),
             );
     }
@@ -46,6 +59,52 @@ struct LevelButton(usize);
 #[derive(Resource)]
 pub struct NextLevel(pub usize);
 
+/// The indices of the levels completed by the player.
+#[derive(Resource, Default)]
+pub struct CompletedLevels(pub HashSet<usize>);
+
+/// The grid of level buttons, scrolled with the mouse wheel inside its panel.
+#[derive(Component, Default)]
+struct ScrollingList {
+    position: f32,
+}
+
+const LEVEL_BUTTON_WIDTH: f32 = 260.0;
+const LEVEL_THUMBNAIL_HEIGHT: f32 = 80.0;
+const COMPLETED_LEVEL_COLOR: Color = Color::rgba(0.27, 0.67, 0.38, 0.35);
+
+fn record_completed_level_system(
+    mut completed_levels: ResMut<CompletedLevels>,
+    mut event_level_completed: EventReader<LevelCompletedEvent>,
+) {
+    for event in event_level_completed.iter() {
+        completed_levels.0.insert(event.level_id);
+    }
+}
+
+fn scroll_level_list_system(
+    mut scroll_event: EventReader<MouseWheel>,
+    mut list_query: Query<(&mut ScrollingList, &mut Style, &Parent, &Node)>,
+    node_query: Query<&Node>,
+) {
+    for event in scroll_event.iter() {
+        for (mut list, mut style, parent, list_node) in &mut list_query {
+            let Ok(panel_node) = node_query.get(parent.get()) else {
+                continue;
+            };
+
+            let dy = match event.unit {
+                MouseScrollUnit::Line => event.y * 20.0,
+                MouseScrollUnit::Pixel => event.y,
+            };
+
+            let max_scroll = (list_node.size().y - panel_node.size().y).max(0.0);
+            list.position = (list.position + dy).clamp(-max_scroll, 0.0);
+            style.position.top = Val::Px(list.position);
+        }
+    }
+}
+
 #[allow(clippy::type_complexity)]
 fn on_back_button_interact_system(
     mut commands: Commands,
@@ -77,13 +136,27 @@ fn on_level_button_interact_system(
     }
 }
 
-fn setup_menu(mut commands: Commands, menu_styles: Res<MenuStyles>) {
+fn setup_menu(
+    mut commands: Commands,
+    menu_styles: Res<MenuStyles>,
+    completed_levels: Res<CompletedLevels>,
+    game_constants: Res<GameConstants>,
+    mut thumbnails: ResMut<LevelThumbnails>,
+    mut images: ResMut<Assets<Image>>,
+) {
     let button_style = Style {
         padding: UiRect::all(Val::Px(2.0)),
         margin: UiRect::all(Val::Px(2.0)),
         ..menu_styles.button_style
     };
 
+    let level_button_style = Style {
+        size: Size::new(Val::Px(LEVEL_BUTTON_WIDTH), Val::Auto),
+        flex_grow: 0.0,
+        flex_direction: FlexDirection::Column,
+        ..button_style
+    };
+
     let menu = commands
         .spawn((
             NodeBundle {
@@ -95,53 +168,112 @@ fn setup_menu(mut commands: Commands, menu_styles: Res<MenuStyles>) {
         ))
         .id();
 
-    let mut buttons: Vec<Entity> = Vec::with_capacity(LEVELS.len() + 1);
+    // The panel clips the grid of levels, the grid moves inside it when scrolling.
+    let panel = commands
+        .spawn(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(90.0), Val::Percent(75.0)),
+                overflow: Overflow::Hidden,
+                flex_direction: FlexDirection::Column,
+                align_self: AlignSelf::Center,
+                ..default()
+            },
+            background_color: BackgroundColor(Color::NONE),
+            ..default()
+        })
+        .id();
+
+    let list = commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    flex_wrap: FlexWrap::Wrap,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::FlexStart,
+                    ..default()
+                },
+                background_color: BackgroundColor(Color::NONE),
+                ..default()
+            },
+            ScrollingList::default(),
+        ))
+        .id();
+
+    let mut buttons: Vec<Entity> = Vec::with_capacity(LEVELS.len());
+
+    for (i, level) in LEVELS.iter().enumerate() {
+        let level_template = LevelTemplate::parse(level).ok();
+        let label = match level_template
+            .as_ref()
+            .and_then(|level| level.title.as_ref())
+        {
+            Some(title) => format!("Level {}: {}", i, title),
+            None => format!("Level {}", i),
+        };
+
+        let thumbnail = level_template.as_ref().map(|level_template| {
+            thumbnails.get_or_create(i, level_template, game_constants.palette, &mut images)
+        });
 
-    for i in 0..LEVELS.len() {
         buttons.push(
             commands
                 .spawn((
                     ButtonBundle {
-                        style: button_style.clone(),
-                        background_color: BackgroundColor(Color::NONE),
+                        style: level_button_style.clone(),
+                        background_color: BackgroundColor(if completed_levels.0.contains(&i) {
+                            COMPLETED_LEVEL_COLOR
+                        } else {
+                            Color::NONE
+                        }),
                         ..Default::default()
                     },
                     LevelButton(i),
                 ))
                 .with_children(|parent| {
+                    // The text stays the first child, the button visuals change its color.
                     parent.spawn(TextBundle {
-                        text: Text::from_section(
-                            format!("Level {}", i),
-                            menu_styles.button_text_style.clone(),
-                        ),
+                        text: Text::from_section(label, menu_styles.button_text_style.clone()),
                         ..Default::default()
                     });
+
+                    if let Some(thumbnail) = thumbnail {
+                        parent.spawn(ImageBundle {
+                            image: UiImage(thumbnail),
+                            style: Style {
+                                size: Size::new(Val::Auto, Val::Px(LEVEL_THUMBNAIL_HEIGHT)),
+                                ..default()
+                            },
+                            ..default()
+                        });
+                    }
                 })
                 .id(),
         );
     }
 
-    buttons.push(
-        commands
-            .spawn((
-                ButtonBundle {
-                    style: button_style,
-                    background_color: BackgroundColor(Color::NONE),
-                    ..Default::default()
-                },
-                BackButton,
-            ))
-            .with_children(|parent| {
-                parent.spawn(TextBundle {
-                    text: Text::from_section(
-                        "Back to Main Menu",
-                        menu_styles.button_text_style.clone(),
-                    ),
-                    ..Default::default()
-                });
-            })
-            .id(),
-    );
-
-    commands.entity(menu).push_children(&buttons);
+    commands.entity(list).push_children(&buttons);
+    commands.entity(panel).add_child(list);
+
+    let back_button = commands
+        .spawn((
+            ButtonBundle {
+                style: button_style,
+                background_color: BackgroundColor(Color::NONE),
+                ..Default::default()
+            },
+            BackButton,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle {
+                text: Text::from_section(
+                    "Back to Main Menu",
+                    menu_styles.button_text_style.clone(),
+                ),
+                ..Default::default()
+            });
+        })
+        .id();
+
+    commands.entity(menu).push_children(&[panel, back_button]);
 }
@@ -1,23 +1,38 @@
 use std::collections::VecDeque;
 
-use bevy::{prelude::*, time::FixedTimestep};
+use bevy::{app::AppExit, prelude::*, time::FixedTimestep};
+use iyes_loopless::prelude::AppLooplessStateExt;
 
 use crate::{
     gameplay::game_constants_pluggin::*,
     gameplay::level_pluggin::{
-        load_level_system, CurrentLevelId, StartLevelEventWithLevel, LOAD_LEVEL_STAGE,
+        load_level_system, ClearLevelEvent, CurrentLevelId, LevelCompletedEvent, LoadLevelStage,
+        StartLevelEventWithLevel,
     },
-    gameplay::movement_pluggin::MoveCommandEvent,
+    gameplay::movement_pluggin::{
+        GravityFall, LevelExitAnim, MoveCommand, MoveCommandEvent, MoveInputBuffer, PushedAnim,
+    },
+    gameplay::snake_pluggin::{Active, Snake},
     level::test_levels::*,
+    GameState,
 };
 
 #[derive(Clone)]
 struct TestInputCommand(IVec2);
 
+/// The expected state of the level once all the moves are played and the snakes are idle.
+#[derive(Clone, Debug, PartialEq)]
+enum TestExpectation {
+    /// The head positions of the snakes left in the level, ordered by snake index.
+    SnakeHeads(Vec<(i32, IVec2)>),
+    LevelCompleted,
+}
+
 #[derive(Resource, Clone)]
 struct TestCase {
     level: &'static str,
     moves: VecDeque<TestInputCommand>,
+    expected: TestExpectation,
 }
 
 #[derive(Resource)]
@@ -25,15 +40,42 @@ struct TestCases {
     cases: Vec<TestCase>,
 }
 
+/// The state of the test run, all the test cases run in order unless a single test case is requested.
+#[derive(Resource)]
+pub struct TestRun {
+    current: usize,
+    run_all: bool,
+    level_completed: bool,
+    failures: Vec<usize>,
+}
+
+impl TestRun {
+    pub fn new(test_case: Option<usize>) -> Self {
+        TestRun {
+            current: test_case.unwrap_or(0),
+            run_all: test_case.is_none(),
+            level_completed: false,
+            failures: vec![],
+        }
+    }
+}
+
 macro_rules! test_case {
-    ($name:ident, $($move:ident,)+) => {
+    ($name:ident, $($move:ident,)+ => $expected:expr) => {
         TestCase {
             level: $name,
             moves: VecDeque::from([$(TestInputCommand($move),)+]),
+            expected: $expected,
         }
     };
 }
 
+macro_rules! snake_heads {
+    ($(($index:expr, $x:expr, $y:expr)),* $(,)?) => {
+        TestExpectation::SnakeHeads(vec![$(($index, IVec2::new($x, $y)),)*])
+    };
+}
+
 macro_rules! test_cases {
     ($($case:expr,)*) => {
         TestCases {
@@ -55,9 +97,12 @@ impl Plugin for AutomatedTestPluggin {
             .add_system_set(
                 SystemSet::new()
                     .with_run_criteria(FixedTimestep::step(1.0))
+                    .with_system(check_test_case_system.before(moc_player_input))
                     .with_system(moc_player_input),
             )
-            .add_system_to_stage(LOAD_LEVEL_STAGE, start_test_case.before(load_level_system));
+            .add_enter_system(GameState::Game, start_first_test_case_system)
+            .add_system(record_level_completed_system)
+            .add_system_to_stage(LoadLevelStage, start_test_case.before(load_level_system));
     }
 }
 
@@ -72,8 +117,93 @@ fn moc_player_input(
     move_command_event.send(MoveCommandEvent(next_move.0));
 }
 
+fn start_first_test_case_system(
+    test_run: Res<TestRun>,
+    mut event_start_test_case: EventWriter<StartTestCaseEventWithIndex>,
+) {
+    event_start_test_case.send(StartTestCaseEventWithIndex(test_run.current));
+}
+
+fn record_level_completed_system(
+    mut test_run: ResMut<TestRun>,
+    mut event_level_completed: EventReader<LevelCompletedEvent>,
+) {
+    if event_level_completed.iter().next().is_some() {
+        test_run.level_completed = true;
+    }
+}
+
+/// Check the expectation once all the moves are played and the snakes are idle, then start the next test case.
+/// The app exits with a nonzero code if any test case failed.
+#[allow(clippy::too_many_arguments)]
+fn check_test_case_system(
+    test_case: Option<Res<TestCase>>,
+    test_cases: Res<TestCases>,
+    mut test_run: ResMut<TestRun>,
+    move_input_buffer: Res<MoveInputBuffer>,
+    mut event_clear_level: EventWriter<ClearLevelEvent>,
+    mut event_start_test_case: EventWriter<StartTestCaseEventWithIndex>,
+    mut exit: EventWriter<AppExit>,
+    busy_query: Query<
+        (),
+        Or<(
+            With<MoveCommand>,
+            With<PushedAnim>,
+            With<GravityFall>,
+            With<LevelExitAnim>,
+        )>,
+    >,
+    snakes_query: Query<&Snake, With<Active>>,
+) {
+    let Some(test_case) = test_case else {
+        return;
+    };
+
+    if !test_case.moves.is_empty() || move_input_buffer.0.is_some() || !busy_query.is_empty() {
+        return;
+    }
+
+    let result = if test_run.level_completed {
+        TestExpectation::LevelCompleted
+    } else {
+        let mut heads: Vec<(i32, IVec2)> = snakes_query
+            .iter()
+            .map(|snake| (snake.index(), snake.head_position()))
+            .collect();
+        heads.sort_by_key(|(index, _)| *index);
+        TestExpectation::SnakeHeads(heads)
+    };
+
+    if result == test_case.expected {
+        info!("Test case {} passed.", test_run.current);
+    } else {
+        error!(
+            "Test case {} failed, expected {:?} but got {:?}.",
+            test_run.current, test_case.expected, result
+        );
+        let current = test_run.current;
+        test_run.failures.push(current);
+    }
+
+    if test_run.run_all && test_run.current + 1 < test_cases.cases.len() {
+        test_run.current += 1;
+        event_clear_level.send(ClearLevelEvent);
+        event_start_test_case.send(StartTestCaseEventWithIndex(test_run.current));
+        return;
+    }
+
+    if test_run.failures.is_empty() {
+        info!("All test cases passed.");
+        exit.send(AppExit);
+    } else {
+        error!("Failed test cases: {:?}.", test_run.failures);
+        std::process::exit(1);
+    }
+}
+
 fn start_test_case(
     test_cases: Res<TestCases>,
+    mut test_run: ResMut<TestRun>,
     mut commands: Commands,
     mut event_start_level: EventWriter<StartLevelEventWithLevel>,
     mut event_reader: EventReader<StartTestCaseEventWithIndex>,
@@ -83,6 +213,7 @@ fn start_test_case(
     };
 
     commands.insert_resource(CurrentLevelId(event.0));
+    test_run.level_completed = false;
 
     let new_test_case = &test_cases.cases[event.0];
     commands.insert_resource(new_test_case.clone());
@@ -92,9 +223,16 @@ fn start_test_case(
 
 fn init_automation(mut commands: Commands) {
     let test_cases = test_cases! {
-        test_case!(FALL_ON_SNAKE_BUG, RIGHT,),
-        test_case!(BUG_EXIT_LEVEL_ON_FALL, RIGHT,),
-        test_case!(FALL_ON_SPIKE, RIGHT, RIGHT,),
+        test_case!(FALL_ON_SNAKE_BUG, RIGHT, => snake_heads![(0, 3, 2), (1, 1, 3)]),
+        test_case!(BUG_EXIT_LEVEL_ON_FALL, RIGHT, => TestExpectation::LevelCompleted),
+        test_case!(FALL_ON_SPIKE, RIGHT, RIGHT, => snake_heads![(0, 3, 2)]),
+        test_case!(PUSH_CHAIN, RIGHT, RIGHT, => snake_heads![(0, 4, 1), (1, 6, 1), (2, 8, 1)]),
+        test_case!(PUSH_BLOCK_OFF_LEDGE, RIGHT, RIGHT, => snake_heads![(0, 4, 2)]),
+        test_case!(STACKED_SNAKES_FALL, RIGHT, => snake_heads![(0, 5, 2), (1, 4, 1)]),
+        test_case!(RAISED_WATER, RIGHT, RIGHT, => snake_heads![(0, 3, 3)]),
+        test_case!(ONE_WAY_WALLS, LEFT, LEFT, LEFT, => snake_heads![(0, 2, 2)]),
+        test_case!(PORTALS, RIGHT, RIGHT, => snake_heads![(0, 9, 1)]),
+        test_case!(SNAKE_GOALS, RIGHT, RIGHT, RIGHT, RIGHT, => snake_heads![(1, 8, 1)]),
     };
 
     commands.insert_resource(test_cases);
@@ -1,26 +1,97 @@
-use bevy::prelude::*;
-use bevy_egui::EguiPlugin;
+use bevy::{prelude::*, utils::HashSet};
+use bevy_egui::{EguiContext, EguiPlugin};
 use bevy_inspector_egui::bevy_inspector;
 use bevy_inspector_egui::DefaultInspectorConfigPlugin;
 use bevy_prototype_debug_lines::{DebugLines, DebugLinesPlugin};
 use iyes_loopless::prelude::ConditionSet;
 
-use crate::gameplay::game_constants_pluggin::GameConstants;
+use crate::gameplay::game_constants_pluggin::{GameConstants, KeyBindings};
+use crate::gameplay::level_pluggin::{ClearLevelEvent, StartLevelEventWithIndex};
+use crate::level::grid_ext::GridExt;
 use crate::level::level_instance::LevelEntityType;
 use crate::level::level_instance::LevelInstance;
+use crate::level::levels::LEVELS;
 use crate::GameState;
 use crate::{
     gameplay::game_constants_pluggin::{to_world, GRID_TO_WORLD_UNIT},
     gameplay::snake_pluggin::Snake,
-    level::level_template::LevelTemplate,
+    level::level_template::{Cell, LevelTemplate},
 };
 
 pub struct DevToolsPlugin;
 
+/// The keys toggling the dev tools and the inspector, they must not be bound to a control of the game.
+const DEV_TOOLS_KEY: KeyCode = KeyCode::F1;
+const INSPECTOR_KEY: KeyCode = KeyCode::I;
+
 #[derive(Default, Resource)]
 pub struct DevToolsSettings {
     pub dev_tools_enabled: bool,
     pub inspector_enabled: bool,
+    /// The cell layers not drawn by `debug_draw_level_cells`.
+    pub hidden_cell_layers: HashSet<CellLayer>,
+    /// The digits of the index of the level to jump to, typed while the dev tools are enabled.
+    pub level_jump_input: String,
+}
+
+/// The layers of the level cells debug draw, one per `LevelEntityType` with all the snakes in the same layer.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum CellLayer {
+    Food,
+    ShrinkFood,
+    Wall,
+    Snake,
+    Spike,
+    Block,
+    ClosedGoal,
+}
+
+impl CellLayer {
+    pub const ALL: [CellLayer; 7] = [
+        CellLayer::Food,
+        CellLayer::ShrinkFood,
+        CellLayer::Wall,
+        CellLayer::Snake,
+        CellLayer::Spike,
+        CellLayer::Block,
+        CellLayer::ClosedGoal,
+    ];
+
+    pub fn of(value: &LevelEntityType) -> Self {
+        match value {
+            LevelEntityType::Food => CellLayer::Food,
+            LevelEntityType::ShrinkFood => CellLayer::ShrinkFood,
+            LevelEntityType::Wall | LevelEntityType::Conveyor(_) => CellLayer::Wall,
+            LevelEntityType::Snake(_) => CellLayer::Snake,
+            LevelEntityType::Spike(_) => CellLayer::Spike,
+            LevelEntityType::Block => CellLayer::Block,
+            LevelEntityType::ClosedGoal => CellLayer::ClosedGoal,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            CellLayer::Food => Color::RED,
+            CellLayer::ShrinkFood => Color::PURPLE,
+            CellLayer::Wall => Color::BLACK,
+            CellLayer::Snake => Color::BLUE,
+            CellLayer::Spike => Color::DARK_GRAY,
+            CellLayer::Block => Color::GRAY,
+            CellLayer::ClosedGoal => Color::ORANGE,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CellLayer::Food => "Food",
+            CellLayer::ShrinkFood => "Shrink Food",
+            CellLayer::Wall => "Wall",
+            CellLayer::Snake => "Snake",
+            CellLayer::Spike => "Spike",
+            CellLayer::Block => "Block",
+            CellLayer::ClosedGoal => "Closed Goal",
+        }
+    }
 }
 
 impl Plugin for DevToolsPlugin {
@@ -35,7 +106,9 @@ impl Plugin for DevToolsPlugin {
                 ConditionSet::new()
                     .run_in_state(GameState::Game)
                     .with_system(toogle_dev_tools_system)
+                    .with_system(level_jump_system)
                     .with_system(inspector_ui_system)
+                    .with_system(dev_tools_ui_system)
                     .into(),
             )
             .add_system_set(
@@ -45,6 +118,7 @@ impl Plugin for DevToolsPlugin {
                     .with_system(debug_draw_grid_system)
                     .with_system(debug_draw_snake_system)
                     .with_system(debug_draw_level_cells)
+                    .with_system(debug_print_level_system)
                     .into(),
             );
     }
@@ -54,17 +128,83 @@ fn toogle_dev_tools_system(
     keyboard: Res<Input<KeyCode>>,
     mut dev_tool_settings: ResMut<DevToolsSettings>,
 ) {
-    if keyboard.just_pressed(KeyCode::Tab) {
+    if keyboard.just_pressed(DEV_TOOLS_KEY) {
         let old_value = dev_tool_settings.dev_tools_enabled;
         dev_tool_settings.dev_tools_enabled = !old_value;
     }
 
-    if keyboard.just_pressed(KeyCode::I) {
+    if keyboard.just_pressed(INSPECTOR_KEY) {
         let old_value = dev_tool_settings.inspector_enabled;
         dev_tool_settings.inspector_enabled = !old_value;
     }
 }
 
+/// The digit typed with a key of the top row or of the numpad.
+fn key_digit(key: KeyCode) -> Option<char> {
+    let digit = match key {
+        KeyCode::Key0 | KeyCode::Numpad0 => '0',
+        KeyCode::Key1 | KeyCode::Numpad1 => '1',
+        KeyCode::Key2 | KeyCode::Numpad2 => '2',
+        KeyCode::Key3 | KeyCode::Numpad3 => '3',
+        KeyCode::Key4 | KeyCode::Numpad4 => '4',
+        KeyCode::Key5 | KeyCode::Numpad5 => '5',
+        KeyCode::Key6 | KeyCode::Numpad6 => '6',
+        KeyCode::Key7 | KeyCode::Numpad7 => '7',
+        KeyCode::Key8 | KeyCode::Numpad8 => '8',
+        KeyCode::Key9 | KeyCode::Numpad9 => '9',
+        _ => return None,
+    };
+    Some(digit)
+}
+
+/// The index of the level to jump to from the typed digits, None if there is no such level.
+fn level_jump_index(input: &str) -> Option<usize> {
+    input
+        .parse::<usize>()
+        .ok()
+        .filter(|index| *index < LEVELS.len())
+}
+
+/// Type the index of a level then enter to jump to it, like starting the game with `--level`.
+fn level_jump_system(
+    keyboard: Res<Input<KeyCode>>,
+    mut dev_tool_settings: ResMut<DevToolsSettings>,
+    mut event_clear_level: EventWriter<ClearLevelEvent>,
+    mut event_start_level: EventWriter<StartLevelEventWithIndex>,
+) {
+    if !dev_tool_settings.dev_tools_enabled {
+        if !dev_tool_settings.level_jump_input.is_empty() {
+            dev_tool_settings.level_jump_input.clear();
+        }
+        return;
+    }
+
+    for key in keyboard.get_just_pressed() {
+        if let Some(digit) = key_digit(*key) {
+            dev_tool_settings.level_jump_input.push(digit);
+        }
+    }
+
+    if !keyboard.any_just_pressed([KeyCode::Return, KeyCode::NumpadEnter])
+        || dev_tool_settings.level_jump_input.is_empty()
+    {
+        return;
+    }
+
+    let input = std::mem::take(&mut dev_tool_settings.level_jump_input);
+    let Some(level_index) = level_jump_index(&input) else {
+        warn!(
+            "No level {}, the level index should be less than {}.",
+            input,
+            LEVELS.len()
+        );
+        return;
+    };
+
+    event_clear_level.send(ClearLevelEvent);
+    event_start_level.send(StartLevelEventWithIndex(level_index));
+}
+
 fn inspector_ui_system(world: &mut World) {
     let dev_tool_settings = world
         .get_resource::<DevToolsSettings>()
@@ -87,6 +227,10 @@ fn inspector_ui_system(world: &mut World) {
         bevy_inspector_egui::bevy_inspector::ui_for_resource::<GameConstants>(world, ui);
     });
 
+    egui::Window::new("KeyBindings").show(&egui_context, |ui| {
+        bevy_inspector_egui::bevy_inspector::ui_for_resource::<KeyBindings>(world, ui);
+    });
+
     egui::Window::new("Inspector").show(&egui_context, |ui| {
         egui::ScrollArea::vertical().show(ui, |ui| {
             bevy_inspector::ui_for_world(world, ui);
@@ -94,6 +238,44 @@ fn inspector_ui_system(world: &mut World) {
     });
 }
 
+/// Panel with the dev tools toggles and the legend of the level cells debug draw.
+fn dev_tools_ui_system(
+    mut egui_context: ResMut<EguiContext>,
+    mut dev_tool_settings: ResMut<DevToolsSettings>,
+) {
+    if !dev_tool_settings.dev_tools_enabled {
+        return;
+    }
+
+    egui::Window::new("Dev Tools").show(egui_context.ctx_mut(), |ui| {
+        ui.checkbox(&mut dev_tool_settings.inspector_enabled, "Inspector (I)");
+        ui.label(format!(
+            "Jump to level (index + Enter): {}",
+            dev_tool_settings.level_jump_input
+        ));
+
+        ui.separator();
+        ui.label("Level cells");
+
+        for layer in CellLayer::ALL {
+            let mut visible = !dev_tool_settings.hidden_cell_layers.contains(&layer);
+            let [r, g, b, _] = layer.color().as_rgba_u8();
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut visible, "");
+                ui.colored_label(egui::Color32::from_rgb(r, g, b), "■");
+                ui.label(layer.name());
+            });
+
+            if visible {
+                dev_tool_settings.hidden_cell_layers.remove(&layer);
+            } else {
+                dev_tool_settings.hidden_cell_layers.insert(layer);
+            }
+        }
+    });
+}
+
 fn debug_draw_grid_system(
     dev_tool_settings: Res<DevToolsSettings>,
     level: Res<LevelTemplate>,
@@ -128,6 +310,28 @@ fn debug_draw_grid_system(
     }
 }
 
+fn debug_print_level_system(
+    dev_tool_settings: Res<DevToolsSettings>,
+    keyboard: Res<Input<KeyCode>>,
+    level: Res<LevelTemplate>,
+) {
+    if !dev_tool_settings.dev_tools_enabled || !keyboard.just_pressed(KeyCode::P) {
+        return;
+    }
+
+    // The level grid is stored with y up, flip it back so that it prints like the level string.
+    let level_string = level
+        .grid
+        .clone()
+        .flip_y()
+        .format_with(|cell, _| match cell {
+            Cell::Empty => '.',
+            _ => char::from(cell),
+        });
+
+    info!("Level grid:\n{}", level_string);
+}
+
 fn debug_draw_snake_system(
     dev_tool_settings: Res<DevToolsSettings>,
     mut lines: ResMut<DebugLines>,
@@ -185,16 +389,41 @@ fn debug_draw_level_cells(
     }
 
     for (position, value) in level.occupied_cells() {
+        let layer = CellLayer::of(value);
+        if dev_tool_settings.hidden_cell_layers.contains(&layer) {
+            continue;
+        }
+
         let world_grid = to_world(*position);
         let world_grid = Vec3::new(world_grid.x, world_grid.y, 0.0);
 
-        let color = match value {
-            LevelEntityType::Food => Color::RED,
-            LevelEntityType::Wall => Color::BLACK,
-            LevelEntityType::Snake(_) => Color::BLUE,
-            LevelEntityType::Spike => Color::DARK_GRAY,
-        };
+        draw_cross(lines.as_mut(), world_grid, layer.color());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        draw_cross(lines.as_mut(), world_grid, color);
+    #[test]
+    pub fn test_toggle_keys_are_not_game_controls() {
+        let key_bindings = KeyBindings::default();
+        for key in key_bindings.keys() {
+            assert_ne!(key, DEV_TOOLS_KEY);
+            assert_ne!(key, INSPECTOR_KEY);
+        }
+    }
+
+    #[test]
+    pub fn test_level_jump_index() {
+        assert_eq!(level_jump_index("0"), Some(0));
+        assert_eq!(level_jump_index("007"), Some(7));
+        assert_eq!(
+            level_jump_index(&(LEVELS.len() - 1).to_string()),
+            Some(LEVELS.len() - 1)
+        );
+        assert_eq!(level_jump_index(&LEVELS.len().to_string()), None);
+        assert_eq!(level_jump_index(""), None);
+        assert_eq!(level_jump_index("99999999999999999999999"), None);
     }
 }
@@ -1,2 +1,3 @@
 pub mod automated_test_pluggin;
 pub mod dev_tools_pluggin;
+pub mod replay_pluggin;
@@ -0,0 +1,324 @@
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use iyes_loopless::prelude::{AppLooplessFixedTimestepExt, ConditionSet};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    gameplay::level_pluggin::{RestartLevelEvent, StartLevelEventWithLevel},
+    gameplay::movement_pluggin::MoveCommandEvent,
+    gameplay::snake_pluggin::{SelectedSnake, Snake},
+    gameplay::undo::{PlayerUndoEvent, UndoToMoveEvent},
+    level::level_instance::LevelInstance,
+};
+
+/// The actions are stamped with the tick of a fixed timestep so that they replay at the same time.
+const REPLAY_TIMESTEP: &str = "replay_timestep";
+const REPLAY_TICK_DURATION: Duration = Duration::from_millis(16);
+
+/// An action of the player changing the level.
+/// The redos are recorded as the moves they replay.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayAction {
+    /// A move of the snake with the index, the snake is selected before moving.
+    Move(i32, IVec2),
+    Undo,
+    UndoToMove(usize),
+    Restart,
+}
+
+/// An action sent at a given tick since the level started.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayEvent {
+    pub tick: u64,
+    pub action: ReplayAction,
+}
+
+/// The actions of a play session, to be replayed on the same level.
+#[derive(Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
+pub struct Replay {
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn to_bytes(&self) -> bincode::Result<Vec<u8>> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Replay> {
+        bincode::deserialize(bytes)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Replay> {
+        Ok(Replay::from_bytes(&std::fs::read(path)?)?)
+    }
+}
+
+/// Records the actions of the session, the file is written after each action.
+#[derive(Resource)]
+pub struct Recorder {
+    path: PathBuf,
+    tick: u64,
+    replay: Replay,
+}
+
+impl Recorder {
+    pub fn new(path: PathBuf) -> Self {
+        Recorder {
+            path,
+            tick: 0,
+            replay: Replay::default(),
+        }
+    }
+}
+
+/// Sends the actions of a replay at the tick they were recorded.
+#[derive(Resource)]
+pub struct Player {
+    tick: u64,
+    events: VecDeque<ReplayEvent>,
+}
+
+impl Player {
+    pub fn new(replay: Replay) -> Self {
+        Player {
+            tick: 0,
+            events: replay.events.into(),
+        }
+    }
+}
+
+pub struct ReplayPluggin;
+
+impl Plugin for ReplayPluggin {
+    fn build(&self, app: &mut App) {
+        app.add_fixed_timestep(REPLAY_TICK_DURATION, REPLAY_TIMESTEP)
+            .add_fixed_timestep_system_set(
+                REPLAY_TIMESTEP,
+                0,
+                ConditionSet::new()
+                    .run_if_resource_exists::<LevelInstance>()
+                    .run_if_resource_exists::<Recorder>()
+                    .with_system(record_actions_system)
+                    .into(),
+            )
+            .add_fixed_timestep_system_set(
+                REPLAY_TIMESTEP,
+                0,
+                ConditionSet::new()
+                    .run_if_resource_exists::<LevelInstance>()
+                    .run_if_resource_exists::<Player>()
+                    .with_system(play_actions_system)
+                    .into(),
+            );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn record_actions_system(
+    mut recorder: ResMut<Recorder>,
+    mut start_level_event: EventReader<StartLevelEventWithLevel>,
+    mut move_command_event: EventReader<MoveCommandEvent>,
+    mut player_undo_event: EventReader<PlayerUndoEvent>,
+    mut undo_to_move_event: EventReader<UndoToMoveEvent>,
+    mut restart_level_event: EventReader<RestartLevelEvent>,
+    selected_snake_query: Query<&Snake, With<SelectedSnake>>,
+) {
+    // The ticks are counted from the start of the level.
+    if start_level_event.iter().next().is_some() {
+        recorder.tick = 0;
+    }
+    recorder.tick += 1;
+
+    let mut actions: Vec<ReplayAction> = vec![];
+    if let Ok(snake) = selected_snake_query.get_single() {
+        actions.extend(
+            move_command_event
+                .iter()
+                .map(|event| ReplayAction::Move(snake.index(), event.0)),
+        );
+    }
+    actions.extend(player_undo_event.iter().map(|_| ReplayAction::Undo));
+    actions.extend(
+        undo_to_move_event
+            .iter()
+            .map(|event| ReplayAction::UndoToMove(event.0)),
+    );
+    actions.extend(restart_level_event.iter().map(|_| ReplayAction::Restart));
+
+    if actions.is_empty() {
+        return;
+    }
+
+    let tick = recorder.tick;
+    recorder.replay.events.extend(
+        actions
+            .into_iter()
+            .map(|action| ReplayEvent { tick, action }),
+    );
+
+    if let Err(error) = recorder.replay.save(&recorder.path) {
+        error!(
+            "Failed to save the replay to {:?}: {}",
+            recorder.path, error
+        );
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn play_actions_system(
+    mut player: ResMut<Player>,
+    mut commands: Commands,
+    mut start_level_event: EventReader<StartLevelEventWithLevel>,
+    mut move_command_event: EventWriter<MoveCommandEvent>,
+    mut player_undo_event: EventWriter<PlayerUndoEvent>,
+    mut undo_to_move_event: EventWriter<UndoToMoveEvent>,
+    mut restart_level_event: EventWriter<RestartLevelEvent>,
+    snakes_query: Query<(Entity, &Snake, Option<&SelectedSnake>)>,
+) {
+    if start_level_event.iter().next().is_some() {
+        player.tick = 0;
+    }
+    player.tick += 1;
+
+    while let Some(next_event) = player.events.front() {
+        if next_event.tick > player.tick {
+            break;
+        }
+
+        match next_event.action {
+            ReplayAction::Move(snake_index, direction) => {
+                // The selection is applied with the commands, before the moves are played.
+                for (entity, snake, selected) in &snakes_query {
+                    if snake.index() == snake_index && selected.is_none() {
+                        commands.entity(entity).insert(SelectedSnake);
+                    } else if snake.index() != snake_index && selected.is_some() {
+                        commands.entity(entity).remove::<SelectedSnake>();
+                    }
+                }
+                move_command_event.send(MoveCommandEvent(direction));
+            }
+            ReplayAction::Undo => player_undo_event.send(PlayerUndoEvent),
+            ReplayAction::UndoToMove(move_count) => {
+                undo_to_move_event.send(UndoToMoveEvent(move_count))
+            }
+            ReplayAction::Restart => restart_level_event.send(RestartLevelEvent),
+        }
+        player.events.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameplay::game_constants_pluggin::{LEFT, RIGHT};
+
+    #[test]
+    pub fn test_replay_round_trip() {
+        let replay = Replay {
+            events: vec![
+                ReplayEvent {
+                    tick: 3,
+                    action: ReplayAction::Move(0, RIGHT),
+                },
+                ReplayEvent {
+                    tick: 20,
+                    action: ReplayAction::Undo,
+                },
+                ReplayEvent {
+                    tick: 40,
+                    action: ReplayAction::Move(1, LEFT),
+                },
+                ReplayEvent {
+                    tick: 50,
+                    action: ReplayAction::UndoToMove(0),
+                },
+                ReplayEvent {
+                    tick: 60,
+                    action: ReplayAction::Restart,
+                },
+            ],
+        };
+
+        let bytes = replay.to_bytes().unwrap();
+        assert_eq!(Replay::from_bytes(&bytes).unwrap(), replay);
+    }
+
+    fn player_app(events: Vec<ReplayEvent>) -> App {
+        let mut app = App::new();
+        app.add_event::<StartLevelEventWithLevel>()
+            .add_event::<MoveCommandEvent>()
+            .add_event::<PlayerUndoEvent>()
+            .add_event::<UndoToMoveEvent>()
+            .add_event::<RestartLevelEvent>()
+            .insert_resource(Player::new(Replay { events }))
+            .add_system(play_actions_system);
+        app
+    }
+
+    #[test]
+    pub fn test_play_actions_at_recorded_tick() {
+        let mut app = player_app(vec![
+            ReplayEvent {
+                tick: 2,
+                action: ReplayAction::Move(1, RIGHT),
+            },
+            ReplayEvent {
+                tick: 2,
+                action: ReplayAction::Undo,
+            },
+        ]);
+        let first_snake = app
+            .world
+            .spawn((
+                Snake::from_positions(&[IVec2::new(1, 0), IVec2::new(0, 0)], 0),
+                SelectedSnake,
+            ))
+            .id();
+        let second_snake = app
+            .world
+            .spawn(Snake::from_positions(
+                &[IVec2::new(3, 0), IVec2::new(2, 0)],
+                1,
+            ))
+            .id();
+
+        app.update();
+        assert!(app.world.resource::<Events<MoveCommandEvent>>().is_empty());
+
+        app.update();
+        assert_eq!(app.world.resource::<Events<MoveCommandEvent>>().len(), 1);
+        assert_eq!(app.world.resource::<Events<PlayerUndoEvent>>().len(), 1);
+        assert!(app.world.resource::<Player>().events.is_empty());
+
+        // The snake that moved in the recording is selected.
+        assert!(!app.world.entity(first_snake).contains::<SelectedSnake>());
+        assert!(app.world.entity(second_snake).contains::<SelectedSnake>());
+    }
+
+    #[test]
+    pub fn test_play_ticks_restart_with_the_level() {
+        let mut app = player_app(vec![ReplayEvent {
+            tick: 2,
+            action: ReplayAction::Restart,
+        }]);
+
+        app.update();
+        app.world
+            .send_event(StartLevelEventWithLevel(String::default()));
+        app.update();
+        assert!(app.world.resource::<Events<RestartLevelEvent>>().is_empty());
+
+        app.update();
+        assert_eq!(app.world.resource::<Events<RestartLevelEvent>>().len(), 1);
+    }
+}